@@ -1,16 +1,55 @@
-use std::{num::NonZeroUsize, time::Duration};
+use std::{borrow::Cow, num::NonZeroUsize, time::Duration};
 
+use azure_core::{error::ErrorKind as AzureErrorKind, StatusCode};
 use azure_storage::StorageCredentials;
 use azure_storage_queues::{
     operations::Message, PopReceipt, QueueClient, QueueServiceClientBuilder,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[allow(deprecated)]
 use crate::{
-    builder::Static, queue::Acker, Delivery, QueueBackend, QueueBuilder, QueueError, Result,
+    builder::Static,
+    metrics::QueueMetrics,
+    queue::{Acker, PeekAcker},
+    Attributes, Capabilities, Delivery, DeliveryMetadata, ErrorKind, MessageOrdering, QueueAdmin,
+    QueueBackend, QueueBuilder, QueueError, QueueStats, Result,
 };
 
+/// Wraps an error returned by Azure as a [`QueueError::Backend`] for the given
+/// operation.
+fn aqs_error(operation: &'static str) -> impl FnOnce(azure_core::Error) -> QueueError {
+    move |e| {
+        let kind = match e.kind() {
+            // https://learn.microsoft.com/en-us/rest/api/storageservices/queue-service-error-codes
+            AzureErrorKind::HttpResponse { status, error_code } => match error_code.as_deref() {
+                Some("QueueNotFound") => ErrorKind::QueueNotFound,
+                Some("PopReceiptMismatch" | "MessageNotFound") => ErrorKind::InvalidReceiptHandle,
+                Some(
+                    "AuthenticationFailed"
+                    | "AuthorizationFailure"
+                    | "AuthorizationPermissionMismatch"
+                    | "InsufficientAccountPermissions",
+                ) => ErrorKind::AuthFailure,
+                Some("ServerBusy") => ErrorKind::Throttled,
+                Some("OperationTimedOut") => ErrorKind::Timeout,
+                _ => match status {
+                    StatusCode::TooManyRequests | StatusCode::ServiceUnavailable => {
+                        ErrorKind::Throttled
+                    }
+                    StatusCode::Unauthorized | StatusCode::Forbidden => ErrorKind::AuthFailure,
+                    StatusCode::RequestTimeout | StatusCode::GatewayTimeout => ErrorKind::Timeout,
+                    _ => ErrorKind::Other,
+                },
+            },
+            AzureErrorKind::Io => ErrorKind::ConnectionLost,
+            AzureErrorKind::Credential => ErrorKind::AuthFailure,
+            _ => ErrorKind::Other,
+        };
+        QueueError::backend(kind, "azure_queue_storage", operation, e)
+    }
+}
+
 fn get_client(cfg: &AqsConfig) -> QueueClient {
     let AqsConfig {
         queue_name,
@@ -43,8 +82,35 @@ impl AqsBackend {
     }
 }
 
+impl<S, L> QueueBuilder<AqsBackend, S, L> {
+    /// Builds an [`AqsAdmin`] for the configured queue.
+    pub async fn build_admin(self) -> Result<AqsAdmin> {
+        Ok(AqsAdmin {
+            client: get_client(&self.config),
+        })
+    }
+}
+
 const DEFAULT_RECV_TIMEOUT: Duration = Duration::from_secs(180);
 const DEFAULT_EMPTY_RECV_DELAY: Duration = Duration::from_millis(200);
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/put-message#request-body
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/put-message#uri-parameters
+const MAX_DELAY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Azure Queue Storage has no native message metadata, so messages sent with
+/// attributes have their text wrapped in this JSON envelope.
+///
+/// Messages without attributes are sent as-is, and anything that doesn't
+/// deserialize to exactly this shape is treated as a plain message.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct AqsEnvelope<'a> {
+    #[serde(rename = "__omniqueue_attributes")]
+    attributes: Cow<'a, Attributes>,
+    #[serde(rename = "__omniqueue_payload")]
+    payload: Cow<'a, str>,
+}
 
 #[derive(Clone)]
 pub struct AqsConfig {
@@ -69,32 +135,50 @@ impl QueueBackend for AqsBackend {
 
     async fn new_pair(config: Self::Config) -> Result<(AqsProducer, AqsConsumer)> {
         let client = get_client(&config);
+        let metrics = queue_metrics(&config);
         Ok((
             AqsProducer {
                 client: client.clone(),
                 config: config.clone(),
+                metrics: metrics.clone(),
             },
             AqsConsumer {
                 client: client.clone(),
                 config: config.clone(),
+                metrics,
             },
         ))
     }
 
     async fn producing_half(config: Self::Config) -> Result<AqsProducer> {
         let client = get_client(&config);
-        Ok(AqsProducer { client, config })
+        let metrics = queue_metrics(&config);
+        Ok(AqsProducer {
+            client,
+            config,
+            metrics,
+        })
     }
 
     async fn consuming_half(config: Self::Config) -> Result<AqsConsumer> {
         let client = get_client(&config);
-        Ok(AqsConsumer { client, config })
+        let metrics = queue_metrics(&config);
+        Ok(AqsConsumer {
+            client,
+            config,
+            metrics,
+        })
     }
 }
 
+fn queue_metrics(config: &AqsConfig) -> QueueMetrics {
+    QueueMetrics::new("azure_queue_storage", &config.queue_name)
+}
+
 pub struct AqsProducer {
     client: QueueClient,
     config: AqsConfig,
+    metrics: QueueMetrics,
 }
 
 impl AqsProducer {
@@ -111,13 +195,16 @@ impl AqsProducer {
         )
     )]
     pub async fn send_raw_scheduled(&self, payload: &str, delay: Duration) -> Result<()> {
-        self.client
-            .put_message(payload)
-            .visibility_timeout(delay)
-            .ttl(self.config.message_ttl)
-            .await
-            .map_err(QueueError::generic)
-            .map(|_| ())
+        let send = async {
+            self.client
+                .put_message(payload)
+                .visibility_timeout(delay)
+                .ttl(self.config.message_ttl)
+                .await
+                .map_err(aqs_error("send"))
+                .map(|_| ())
+        };
+        self.metrics.send([payload.len()], send).await
     }
 
     pub async fn send_serde_json<P: Serialize + Sync>(&self, payload: &P) -> Result<()> {
@@ -125,6 +212,35 @@ impl AqsProducer {
         self.send_raw(&payload).await
     }
 
+    /// Sends a raw message with the given attributes.
+    ///
+    /// If `attributes` is non-empty, the payload is wrapped in an envelope
+    /// that [`AqsConsumer`] unwraps again on receipt.
+    pub async fn send_raw_with_attributes(
+        &self,
+        payload: &str,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        if attributes.is_empty() {
+            return self.send_raw(payload).await;
+        }
+
+        let envelope = serde_json::to_string(&AqsEnvelope {
+            attributes: Cow::Borrowed(attributes),
+            payload: Cow::Borrowed(payload),
+        })?;
+        self.send_raw(&envelope).await
+    }
+
+    pub async fn send_serde_json_with_attributes<P: Serialize + Sync>(
+        &self,
+        payload: &P,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = serde_json::to_string(payload)?;
+        self.send_raw_with_attributes(&payload, attributes).await
+    }
+
     pub async fn send_serde_json_scheduled<P: Serialize + Sync>(
         &self,
         payload: &P,
@@ -139,11 +255,40 @@ impl AqsProducer {
             "redrive_dlq is not supported by AqsBackend",
         ))
     }
+
+    pub async fn health_check(&self) -> Result<()> {
+        check_queue(&self.client).await
+    }
+}
+
+/// Checks that the queue exists and can be accessed, by fetching its
+/// metadata.
+async fn check_queue(client: &QueueClient) -> Result<()> {
+    client
+        .get_metadata()
+        .await
+        .map_err(aqs_error("health_check"))?;
+    Ok(())
 }
 
 impl crate::QueueProducer for AqsProducer {
     type Payload = String;
-    omni_delegate!(send_raw, send_serde_json, redrive_dlq);
+    omni_delegate!(
+        send_raw,
+        send_serde_json,
+        send_raw_with_attributes,
+        send_serde_json_with_attributes,
+        redrive_dlq,
+        health_check
+    );
+
+    fn payload_is_text(&self) -> bool {
+        true
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities()
+    }
 }
 impl crate::ScheduledQueueProducer for AqsProducer {
     omni_delegate!(send_raw_scheduled, send_serde_json_scheduled);
@@ -154,12 +299,17 @@ impl crate::ScheduledQueueProducer for AqsProducer {
 pub struct AqsConsumer {
     client: QueueClient,
     config: AqsConfig,
+    metrics: QueueMetrics,
 }
 
 struct AqsAcker {
     client: QueueClient,
     already_acked_or_nacked: bool,
     pop_receipt: PopReceipt,
+    message_id: String,
+    // Updating a message's visibility also replaces its text, so the
+    // original is kept around to be sent back unchanged.
+    message_text: String,
 }
 
 impl Acker for AqsAcker {
@@ -172,7 +322,7 @@ impl Acker for AqsAcker {
             .pop_receipt_client(self.pop_receipt.clone())
             .delete()
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(aqs_error("ack"))?;
         self.already_acked_or_nacked = true;
         Ok(())
     }
@@ -181,42 +331,96 @@ impl Acker for AqsAcker {
         Ok(())
     }
 
-    async fn set_ack_deadline(&mut self, _duration: Duration) -> Result<()> {
-        Err(QueueError::Unsupported(
-            "set_ack_deadline is not yet supported by AqsBackend",
-        ))
+    /// Makes the message invisible for `delay`, which Azure Queue Storage
+    /// caps at 7 days.
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        self.set_ack_deadline(delay).await?;
+        self.already_acked_or_nacked = true;
+        Ok(())
+    }
+
+    async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
+        if self.already_acked_or_nacked {
+            return Err(QueueError::CannotAckOrNackTwice);
+        }
+
+        let response = self
+            .client
+            .pop_receipt_client(self.pop_receipt.clone())
+            .update(self.message_text.clone(), duration)
+            .await
+            .map_err(aqs_error("set_ack_deadline"))?;
+
+        // The previous pop receipt is invalidated by the update
+        self.pop_receipt = PopReceipt::new(self.message_id.clone(), response.pop_receipt);
+        Ok(())
+    }
+}
+
+/// Splits a message's text into its payload and attributes, for messages that
+/// were sent in an envelope.
+fn open_envelope(message_text: &str) -> (String, Attributes) {
+    match serde_json::from_str::<AqsEnvelope<'_>>(message_text) {
+        Ok(envelope) => (
+            envelope.payload.into_owned(),
+            envelope.attributes.into_owned(),
+        ),
+        Err(_) => (message_text.to_owned(), Attributes::new()),
     }
 }
 
 impl AqsConsumer {
     fn wrap_message(&self, message: &Message) -> Delivery {
+        let (payload, attributes) = open_envelope(&message.message_text);
+
+        let metadata = DeliveryMetadata {
+            message_id: Some(message.message_id.clone()),
+            receive_count: Some(message.dequeue_count.try_into().unwrap_or(u32::MAX)),
+            enqueued_at: Some(message.insertion_time),
+        };
+
         Delivery::new(
-            message.message_text.as_bytes().to_owned(),
+            payload.into_bytes(),
             AqsAcker {
                 client: self.client.clone(),
                 pop_receipt: message.pop_receipt(),
+                message_id: message.message_id.clone(),
+                message_text: message.message_text.clone(),
                 already_acked_or_nacked: false,
             },
         )
+        .with_metadata(metadata)
+        .with_attributes(attributes)
     }
 
     /// Note that blocking receives are not supported by Azure Queue Storage.
     /// Calls to this method will return immediately if no messages are
     /// available for delivery in the queue.
     pub async fn receive(&mut self) -> Result<Delivery> {
-        self.client
+        let result = self
+            .client
             .get_messages()
             .visibility_timeout(self.config.receive_timeout.unwrap_or(DEFAULT_RECV_TIMEOUT))
             .await
-            .map_err(QueueError::generic)
+            .map_err(aqs_error("receive"))
             .and_then(|m| m.messages.into_iter().next().ok_or(QueueError::NoData))
-            .map(|m| self.wrap_message(&m))
+            .map(|m| self.wrap_message(&m));
+        self.metrics.receive(result)
     }
 
     pub async fn receive_all(
         &mut self,
         max_messages: usize,
         deadline: Duration,
+    ) -> Result<Vec<Delivery>> {
+        let result = self.receive_all_inner(max_messages, deadline).await;
+        self.metrics.receive_all(result)
+    }
+
+    async fn receive_all_inner(
+        &self,
+        max_messages: usize,
+        deadline: Duration,
     ) -> Result<Vec<Delivery>> {
         let end = std::time::Instant::now() + deadline;
         let mut interval = tokio::time::interval(
@@ -232,7 +436,7 @@ impl AqsConsumer {
                 .number_of_messages(max_messages.try_into().unwrap_or(u8::MAX))
                 .visibility_timeout(self.config.receive_timeout.unwrap_or(DEFAULT_RECV_TIMEOUT))
                 .await
-                .map_err(QueueError::generic)
+                .map_err(aqs_error("receive"))
                 .map(|m| {
                     m.messages
                         .iter()
@@ -247,14 +451,134 @@ impl AqsConsumer {
             }
         }
     }
+
+    pub async fn health_check(&self) -> Result<()> {
+        check_queue(&self.client).await
+    }
+
+    /// Returns the approximate message count from the queue metadata.
+    ///
+    /// Azure doesn't distinguish between visible and invisible messages in
+    /// this count, so in-flight and delayed messages are included in
+    /// [`QueueStats::visible`].
+    pub async fn stats(&self) -> Result<QueueStats> {
+        let metadata = self
+            .client
+            .get_metadata()
+            .await
+            .map_err(aqs_error("stats"))?;
+        Ok(QueueStats {
+            visible: Some(metadata.approximate_messages_count as u64),
+            ..Default::default()
+        })
+    }
+
+    /// Returns up to `max_messages` messages from the front of the queue,
+    /// which Azure Queue Storage caps at 32.
+    ///
+    /// Messages that are currently invisible aren't included.
+    pub async fn peek(&self, max_messages: usize) -> Result<Vec<Delivery>> {
+        if max_messages == 0 {
+            return Ok(Vec::new());
+        }
+
+        let response = self
+            .client
+            .peek_messages()
+            .number_of_messages(max_messages.min(32) as u8)
+            .await
+            .map_err(aqs_error("peek"))?;
+
+        Ok(response
+            .messages
+            .into_iter()
+            .map(|message| {
+                let (payload, attributes) = open_envelope(&message.message_text);
+                let metadata = DeliveryMetadata {
+                    message_id: Some(message.message_id),
+                    receive_count: Some(message.dequeue_count.try_into().unwrap_or(u32::MAX)),
+                    enqueued_at: Some(message.insertion_time),
+                };
+                Delivery::new(payload.into_bytes(), PeekAcker)
+                    .with_metadata(metadata)
+                    .with_attributes(attributes)
+            })
+            .collect())
+    }
 }
 
 impl crate::QueueConsumer for AqsConsumer {
     type Payload = String;
-    omni_delegate!(receive, receive_all);
+    omni_delegate!(receive, receive_all, health_check, stats, peek);
 
     fn max_messages(&self) -> Option<NonZeroUsize> {
         // https://learn.microsoft.com/en-us/rest/api/storageservices/get-messages#uri-parameters
         NonZeroUsize::new(32)
     }
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities()
+    }
+}
+
+fn capabilities() -> Capabilities {
+    Capabilities {
+        max_payload_size: Some(MAX_PAYLOAD_SIZE),
+        max_receive_batch_size: NonZeroUsize::new(32),
+        scheduling: true,
+        max_delay: Some(MAX_DELAY),
+        set_ack_deadline: true,
+        ordering: MessageOrdering::BestEffort,
+        ..Default::default()
+    }
+}
+
+/// Administers the configured queue.
+///
+/// Created with [`QueueBuilder::build_admin`].
+pub struct AqsAdmin {
+    client: QueueClient,
+}
+
+impl AqsAdmin {
+    pub async fn create_queue(&self) -> Result<()> {
+        self.client
+            .create()
+            .await
+            .map_err(aqs_error("create_queue"))?;
+        Ok(())
+    }
+
+    pub async fn delete_queue(&self) -> Result<()> {
+        self.client
+            .delete()
+            .await
+            .map_err(aqs_error("delete_queue"))?;
+        Ok(())
+    }
+
+    pub async fn purge(&self) -> Result<()> {
+        self.client
+            .clear_messages()
+            .await
+            .map_err(aqs_error("purge"))?;
+        Ok(())
+    }
+
+    pub async fn exists(&self) -> Result<bool> {
+        match self
+            .client
+            .get_metadata()
+            .await
+            .map_err(aqs_error("exists"))
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::QueueNotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl QueueAdmin for AqsAdmin {
+    omni_delegate!(create_queue, delete_queue, purge, exists);
 }
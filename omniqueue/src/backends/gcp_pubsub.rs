@@ -1,26 +1,49 @@
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use futures_util::{future::try_join_all, StreamExt};
+use gcloud_gax::grpc::{Code, Status};
 use gcloud_googleapis::pubsub::v1::PubsubMessage;
 use gcloud_pubsub::{
     client::{google_cloud_auth::credentials::CredentialsFile, Client, ClientConfig},
     publisher::Publisher,
     subscriber::ReceivedMessage,
-    subscription::Subscription,
+    subscription::{SeekTo, Subscription, SubscriptionConfig},
 };
 use serde::Serialize;
+use time::OffsetDateTime;
 
 #[allow(deprecated)]
 use crate::{
     builder::{QueueBuilder, Static},
-    queue::{Acker, Delivery, QueueBackend},
-    QueueError, Result,
+    metrics::QueueMetrics,
+    queue::{Acker, Delivery, DeliveryMetadata, QueueBackend},
+    Attributes, Capabilities, ErrorKind, QueueAdmin, QueueError, Result,
 };
 
+const BACKEND: &str = "gcp_pubsub";
+/// https://cloud.google.com/pubsub/quotas#resource_limits
+const MAX_PAYLOAD_SIZE: usize = 10_000_000;
+
+/// Wraps an error returned by Pub/Sub as a [`QueueError::Backend`] for the
+/// given operation.
+fn gcp_error(operation: &'static str) -> impl FnOnce(Status) -> QueueError {
+    move |status| {
+        let kind = match status.code() {
+            Code::Unavailable | Code::Aborted => ErrorKind::ConnectionLost,
+            Code::DeadlineExceeded => ErrorKind::Timeout,
+            Code::ResourceExhausted => ErrorKind::Throttled,
+            Code::Unauthenticated | Code::PermissionDenied => ErrorKind::AuthFailure,
+            Code::NotFound => ErrorKind::QueueNotFound,
+            _ => ErrorKind::Other,
+        };
+        QueueError::backend(kind, BACKEND, operation, status)
+    }
+}
+
 pub struct GcpPubSubBackend;
 
 impl GcpPubSubBackend {
@@ -32,6 +55,18 @@ impl GcpPubSubBackend {
     }
 }
 
+impl<S, L> QueueBuilder<GcpPubSubBackend, S, L> {
+    /// Builds a [`GcpPubSubAdmin`] for the configured topic and subscription.
+    pub async fn build_admin(self) -> Result<GcpPubSubAdmin> {
+        let client = get_client(&self.config).await?;
+        Ok(GcpPubSubAdmin {
+            client,
+            topic_id: self.config.topic_id,
+            subscription_id: self.config.subscription_id,
+        })
+    }
+}
+
 type Payload = Vec<u8>;
 
 // FIXME: topic/subscription are each for read/write. Split config up?
@@ -49,7 +84,7 @@ async fn configure_client_from_file<P: AsRef<Path>>(cred_file_path: P) -> Result
     ClientConfig::default()
         .with_credentials(creds)
         .await
-        .map_err(QueueError::generic)
+        .map_err(|e| QueueError::backend(ErrorKind::AuthFailure, BACKEND, "connect", e))
 }
 
 /// Making a `ClientConfig` via env vars is possible in two ways:
@@ -61,7 +96,7 @@ async fn configure_client_from_env() -> Result<ClientConfig> {
     ClientConfig::default()
         .with_auth()
         .await
-        .map_err(QueueError::generic)
+        .map_err(|e| QueueError::backend(ErrorKind::AuthFailure, BACKEND, "connect", e))
 }
 
 async fn get_client(cfg: &GcpPubSubConfig) -> Result<Client> {
@@ -74,7 +109,9 @@ async fn get_client(cfg: &GcpPubSubConfig) -> Result<Client> {
             configure_client_from_env().await?
         }
     };
-    Client::new(config).await.map_err(QueueError::generic)
+    Client::new(config)
+        .await
+        .map_err(|e| QueueError::backend(ErrorKind::Other, BACKEND, "connect", e))
 }
 
 #[allow(deprecated)]
@@ -109,6 +146,7 @@ impl QueueBackend for GcpPubSubBackend {
 pub struct GcpPubSubProducer {
     client: Client,
     topic_id: Arc<String>,
+    metrics: QueueMetrics,
 }
 
 impl GcpPubSubProducer {
@@ -117,11 +155,12 @@ impl GcpPubSubProducer {
         // Only warn if the topic doesn't exist at this point.
         // If it gets created after the fact, we should be able to still use it
         // when available, otherwise if it's still missing at that time, error.
-        if !topic.exists(None).await.map_err(QueueError::generic)? {
+        if !topic.exists(None).await.map_err(gcp_error("connect"))? {
             tracing::warn!("topic {} does not exist", &topic_id);
         }
         Ok(Self {
             client,
+            metrics: QueueMetrics::new(BACKEND, &topic_id),
             topic_id: Arc::new(topic_id),
         })
     }
@@ -136,9 +175,12 @@ impl GcpPubSubProducer {
         // (forever?) Giving this error will allow dependents to handle the
         // error case immediately when this happens, instead of holding the
         // connection open indefinitely.
-        if !topic.exists(None).await.map_err(QueueError::generic)? {
-            return Err(QueueError::Generic(
-                format!("topic {} does not exist", &self.topic_id).into(),
+        if !topic.exists(None).await.map_err(gcp_error("send"))? {
+            return Err(QueueError::backend(
+                ErrorKind::QueueNotFound,
+                BACKEND,
+                "send",
+                format!("topic {} does not exist", &self.topic_id),
             ));
         }
 
@@ -157,21 +199,91 @@ impl GcpPubSubProducer {
             ..Default::default()
         };
 
-        let publisher = self.publisher().await?;
-        let awaiter = publisher.publish(msg).await;
-        awaiter.get().await.map_err(QueueError::generic)?;
-        Ok(())
+        self.publish(msg).await
     }
 
     pub async fn send_serde_json<P: Serialize + Sync>(&self, payload: &P) -> Result<()> {
         self.send_raw(&serde_json::to_vec(&payload)?).await
     }
 
+    /// Sends a raw message with the given attributes set as Pub/Sub message
+    /// attributes.
+    #[tracing::instrument(
+        name = "send",
+        skip_all,
+        fields(payload_size = payload.len())
+    )]
+    pub async fn send_raw_with_attributes(
+        &self,
+        payload: &[u8],
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let msg = PubsubMessage {
+            data: payload.to_vec(),
+            attributes: attributes.clone(),
+            ..Default::default()
+        };
+
+        self.publish(msg).await
+    }
+
+    pub async fn send_serde_json_with_attributes<P: Serialize + Sync>(
+        &self,
+        payload: &P,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        self.send_raw_with_attributes(&serde_json::to_vec(&payload)?, attributes)
+            .await
+    }
+
+    async fn publish(&self, msg: PubsubMessage) -> Result<()> {
+        self.metrics
+            .send([msg.data.len()], async {
+                let publisher = self.publisher().await?;
+                let awaiter = publisher.publish(msg).await;
+                awaiter.get().await.map_err(gcp_error("send"))?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn publish_bulk(&self, msgs: Vec<PubsubMessage>) -> Result<()> {
+        let payload_sizes: Vec<_> = msgs.iter().map(|msg| msg.data.len()).collect();
+        self.metrics
+            .send(payload_sizes, async {
+                let publisher = self.publisher().await?;
+                let awaiters = publisher.publish_bulk(msgs).await;
+                try_join_all(awaiters.into_iter().map(|a| a.get()))
+                    .await
+                    .map_err(gcp_error("send_batch"))?;
+                Ok(())
+            })
+            .await
+    }
+
     pub async fn redrive_dlq(&self) -> Result<()> {
         Err(QueueError::Unsupported(
             "redrive_dlq is not supported by GcpPubSubBackend",
         ))
     }
+
+    /// Checks that the topic exists.
+    pub async fn health_check(&self) -> Result<()> {
+        let topic = self.client.topic(&self.topic_id);
+        if !topic
+            .exists(None)
+            .await
+            .map_err(gcp_error("health_check"))?
+        {
+            return Err(QueueError::backend(
+                ErrorKind::QueueNotFound,
+                BACKEND,
+                "health_check",
+                format!("topic {} does not exist", &self.topic_id),
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl std::fmt::Debug for GcpPubSubProducer {
@@ -184,7 +296,14 @@ impl std::fmt::Debug for GcpPubSubProducer {
 
 impl crate::QueueProducer for GcpPubSubProducer {
     type Payload = Payload;
-    omni_delegate!(send_raw, send_serde_json, redrive_dlq);
+    omni_delegate!(
+        send_raw,
+        send_serde_json,
+        send_raw_with_attributes,
+        send_serde_json_with_attributes,
+        redrive_dlq,
+        health_check
+    );
 
     /// This method is overwritten for the Google Cloud Pub/Sub backend to be
     /// more efficient than the default of sequentially publishing `payloads`.
@@ -201,12 +320,7 @@ impl crate::QueueProducer for GcpPubSubProducer {
             })
             .collect();
 
-        let publisher = self.publisher().await?;
-        let awaiters = publisher.publish_bulk(msgs).await;
-        try_join_all(awaiters.into_iter().map(|a| a.get()))
-            .await
-            .map_err(QueueError::generic)?;
-        Ok(())
+        self.publish_bulk(msgs).await
     }
 
     /// This method is overwritten for the Google Cloud Pub/Sub backend to be
@@ -226,34 +340,40 @@ impl crate::QueueProducer for GcpPubSubProducer {
             })
             .collect::<Result<_>>()?;
 
-        let publisher = self.publisher().await?;
-        let awaiters = publisher.publish_bulk(msgs).await;
-        try_join_all(awaiters.into_iter().map(|a| a.get()))
-            .await
-            .map_err(QueueError::generic)?;
-        Ok(())
+        self.publish_bulk(msgs).await
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities()
     }
 }
 
 pub struct GcpPubSubConsumer {
     client: Client,
     subscription_id: Arc<String>,
+    metrics: QueueMetrics,
 }
 
 impl GcpPubSubConsumer {
     async fn new(client: Client, subscription_id: String) -> Result<Self> {
         Ok(Self {
             client,
+            metrics: QueueMetrics::new(BACKEND, &subscription_id),
             subscription_id: Arc::new(subscription_id),
         })
     }
 
     pub async fn receive(&mut self) -> Result<Delivery> {
-        let subscription = subscription(&self.client, &self.subscription_id).await?;
+        let result = self.receive_inner().await;
+        self.metrics.receive(result)
+    }
+
+    async fn receive_inner(&mut self) -> Result<Delivery> {
+        let subscription = subscription(&self.client, &self.subscription_id, "receive").await?;
         let mut stream = subscription
             .subscribe(None)
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(gcp_error("receive"))?;
 
         let recv_msg = stream.next().await.ok_or_else(|| QueueError::NoData)?;
 
@@ -265,10 +385,19 @@ impl GcpPubSubConsumer {
         max_messages: usize,
         deadline: Duration,
     ) -> Result<Vec<Delivery>> {
-        let subscription = subscription(&self.client, &self.subscription_id).await?;
+        let result = self.receive_all_inner(max_messages, deadline).await;
+        self.metrics.receive_all(result)
+    }
+
+    async fn receive_all_inner(
+        &mut self,
+        max_messages: usize,
+        deadline: Duration,
+    ) -> Result<Vec<Delivery>> {
+        let subscription = subscription(&self.client, &self.subscription_id, "receive").await?;
         match tokio::time::timeout(deadline, subscription.pull(max_messages as _, None)).await {
             Ok(messages) => Ok(messages
-                .map_err(QueueError::generic)?
+                .map_err(gcp_error("receive"))?
                 .into_iter()
                 .map(|m| self.wrap_recv_msg(m))
                 .collect()),
@@ -277,6 +406,12 @@ impl GcpPubSubConsumer {
         }
     }
 
+    /// Checks that the subscription exists.
+    pub async fn health_check(&self) -> Result<()> {
+        subscription(&self.client, &self.subscription_id, "health_check").await?;
+        Ok(())
+    }
+
     fn wrap_recv_msg(&self, mut recv_msg: ReceivedMessage) -> Delivery {
         // FIXME: would be nice to avoid having to move the data out here.
         // While it's possible to ack via a subscription and an ack_id, nack
@@ -284,6 +419,20 @@ impl GcpPubSubConsumer {
         // to hold 2 copies of the payload, or move the bytes out so they can be
         // returned _outside of the Acker_.
         let payload = recv_msg.message.data.drain(..).collect();
+        let attributes = std::mem::take(&mut recv_msg.message.attributes);
+        let metadata = DeliveryMetadata {
+            message_id: Some(recv_msg.message.message_id.clone()),
+            // Only tracked by Pub/Sub for subscriptions with a dead letter policy
+            receive_count: recv_msg
+                .delivery_attempt()
+                .and_then(|attempt| attempt.try_into().ok()),
+            enqueued_at: recv_msg.message.publish_time.as_ref().and_then(|ts| {
+                OffsetDateTime::from_unix_timestamp_nanos(
+                    i128::from(ts.seconds) * 1_000_000_000 + i128::from(ts.nanos),
+                )
+                .ok()
+            }),
+        };
 
         Delivery::new(
             payload,
@@ -292,6 +441,8 @@ impl GcpPubSubConsumer {
                 subscription_id: self.subscription_id.clone(),
             },
         )
+        .with_metadata(metadata)
+        .with_attributes(attributes)
     }
 }
 
@@ -303,15 +454,22 @@ impl std::fmt::Debug for GcpPubSubConsumer {
     }
 }
 
-async fn subscription(client: &Client, subscription_id: &str) -> Result<Subscription> {
+async fn subscription(
+    client: &Client,
+    subscription_id: &str,
+    operation: &'static str,
+) -> Result<Subscription> {
     let subscription = client.subscription(subscription_id);
     if !subscription
         .exists(None)
         .await
-        .map_err(QueueError::generic)?
+        .map_err(gcp_error(operation))?
     {
-        return Err(QueueError::Generic(
-            format!("subscription {} does not exist", &subscription_id).into(),
+        return Err(QueueError::backend(
+            ErrorKind::QueueNotFound,
+            BACKEND,
+            operation,
+            format!("subscription {} does not exist", &subscription_id),
         ));
     }
     Ok(subscription)
@@ -319,7 +477,19 @@ async fn subscription(client: &Client, subscription_id: &str) -> Result<Subscrip
 
 impl crate::QueueConsumer for GcpPubSubConsumer {
     type Payload = Payload;
-    omni_delegate!(receive, receive_all);
+    omni_delegate!(receive, receive_all, health_check);
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities()
+    }
+}
+
+fn capabilities() -> Capabilities {
+    Capabilities {
+        max_payload_size: Some(MAX_PAYLOAD_SIZE),
+        set_ack_deadline: true,
+        ..Default::default()
+    }
 }
 
 struct GcpPubSubAcker {
@@ -339,11 +509,18 @@ impl std::fmt::Debug for GcpPubSubAcker {
 
 impl Acker for GcpPubSubAcker {
     async fn ack(&mut self) -> Result<()> {
-        self.recv_msg.ack().await.map_err(QueueError::generic)
+        self.recv_msg.ack().await.map_err(gcp_error("ack"))
     }
 
     async fn nack(&mut self) -> Result<()> {
-        self.recv_msg.nack().await.map_err(QueueError::generic)
+        self.recv_msg.nack().await.map_err(gcp_error("nack"))
+    }
+
+    /// Extends the message's ack deadline to `delay` without acking it, so
+    /// Pub/Sub redelivers it once the deadline passes. Pub/Sub caps ack
+    /// deadlines at 10 minutes.
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        self.set_ack_deadline(delay).await
     }
 
     async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
@@ -356,6 +533,93 @@ impl Acker for GcpPubSubAcker {
         self.recv_msg
             .modify_ack_deadline(duration_secs)
             .await
-            .map_err(QueueError::generic)
+            .map_err(gcp_error("set_ack_deadline"))
+    }
+}
+
+/// Administers the configured topic together with its subscription.
+///
+/// Both are created with the default settings. For anything else, such as a
+/// dead-letter policy, use the Pub/Sub client directly.
+///
+/// Created with [`QueueBuilder::build_admin`].
+pub struct GcpPubSubAdmin {
+    client: Client,
+    topic_id: String,
+    subscription_id: String,
+}
+
+impl GcpPubSubAdmin {
+    /// Creates the topic and the subscription to it, if they don't exist yet.
+    pub async fn create_queue(&self) -> Result<()> {
+        let topic = self.client.topic(&self.topic_id);
+        match topic.create(None, None).await {
+            Err(status) if status.code() != Code::AlreadyExists => {
+                return Err(gcp_error("create_queue")(status));
+            }
+            _ => {}
+        }
+
+        let subscription = self.client.subscription(&self.subscription_id);
+        match subscription
+            .create(
+                topic.fully_qualified_name(),
+                SubscriptionConfig::default(),
+                None,
+            )
+            .await
+        {
+            Err(status) if status.code() != Code::AlreadyExists => {
+                Err(gcp_error("create_queue")(status))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes the subscription, then the topic.
+    pub async fn delete_queue(&self) -> Result<()> {
+        self.client
+            .subscription(&self.subscription_id)
+            .delete(None)
+            .await
+            .map_err(gcp_error("delete_queue"))?;
+        self.client
+            .topic(&self.topic_id)
+            .delete(None)
+            .await
+            .map_err(gcp_error("delete_queue"))
+    }
+
+    /// Acknowledges all messages published to the topic so far by seeking
+    /// the subscription to the current time.
+    pub async fn purge(&self) -> Result<()> {
+        self.client
+            .subscription(&self.subscription_id)
+            .seek(SeekTo::Timestamp(SystemTime::now()), None)
+            .await
+            .map_err(gcp_error("purge"))
+    }
+
+    /// Returns whether both the topic and the subscription exist.
+    pub async fn exists(&self) -> Result<bool> {
+        let topic_exists = self
+            .client
+            .topic(&self.topic_id)
+            .exists(None)
+            .await
+            .map_err(gcp_error("exists"))?;
+        if !topic_exists {
+            return Ok(false);
+        }
+
+        self.client
+            .subscription(&self.subscription_id)
+            .exists(None)
+            .await
+            .map_err(gcp_error("exists"))
     }
 }
+
+impl QueueAdmin for GcpPubSubAdmin {
+    omni_delegate!(create_queue, delete_queue, purge, exists);
+}
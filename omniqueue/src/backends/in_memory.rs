@@ -1,13 +1,22 @@
-use std::time::{Duration, Instant};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use serde::Serialize;
-use tokio::sync::mpsc;
+use time::OffsetDateTime;
+use tokio::sync::Notify;
 
 #[allow(deprecated)]
 use crate::{
     builder::{QueueBuilder, Static},
-    queue::{Acker, Delivery, QueueBackend},
-    QueueError, Result,
+    metrics::QueueMetrics,
+    queue::{Acker, Delivery, DeliveryMetadata, PeekAcker, QueueBackend},
+    Attributes, Capabilities, MessageOrdering, QueueError, QueueStats, Result,
 };
 
 pub struct InMemoryBackend;
@@ -31,11 +40,15 @@ impl QueueBackend for InMemoryBackend {
     type Config = ();
 
     async fn new_pair(_config: ()) -> Result<(InMemoryProducer, InMemoryConsumer)> {
-        let (tx, rx) = mpsc::unbounded_channel();
+        let (tx, rx) = channel();
+        let metrics = QueueMetrics::new("in_memory", "");
 
         Ok((
-            InMemoryProducer { tx: tx.clone() },
-            InMemoryConsumer { tx, rx },
+            InMemoryProducer {
+                tx: tx.clone(),
+                metrics: metrics.clone(),
+            },
+            InMemoryConsumer { tx, rx, metrics },
         ))
     }
 
@@ -48,13 +61,129 @@ impl QueueBackend for InMemoryBackend {
     }
 }
 
+/// Source of message IDs, shared by all in-memory queues.
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The envelope messages are passed around in, so attributes and metadata can
+/// travel along with the payload.
+#[derive(Clone)]
+struct InMemoryMessage {
+    id: u64,
+    payload: Vec<u8>,
+    attributes: Attributes,
+    enqueued_at: OffsetDateTime,
+    receive_count: u32,
+}
+
+impl InMemoryMessage {
+    fn new(payload: &[u8], attributes: Attributes) -> Self {
+        Self {
+            id: NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed),
+            payload: payload.to_vec(),
+            attributes,
+            enqueued_at: OffsetDateTime::now_utc(),
+            receive_count: 0,
+        }
+    }
+
+    fn metadata(&self) -> DeliveryMetadata {
+        DeliveryMetadata {
+            message_id: Some(self.id.to_string()),
+            receive_count: Some(self.receive_count),
+            enqueued_at: Some(self.enqueued_at),
+        }
+    }
+}
+
+/// An unbounded channel like tokio's `mpsc` one, except that the buffered
+/// messages can be looked at without receiving them.
+///
+/// There is a single [`Receiver`], whose `recv` takes `&mut self`, so at most
+/// one task ever waits on `notify`.
+struct Channel {
+    queue: Mutex<VecDeque<InMemoryMessage>>,
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+fn channel() -> (Sender, Receiver) {
+    let channel = Arc::new(Channel {
+        queue: Mutex::new(VecDeque::new()),
+        notify: Notify::new(),
+        closed: AtomicBool::new(false),
+    });
+    (Sender(channel.clone()), Receiver(channel))
+}
+
+#[derive(Clone)]
+struct Sender(Arc<Channel>);
+
+impl Sender {
+    /// Fails if the receiver has been dropped.
+    fn send(&self, message: InMemoryMessage) -> Result<()> {
+        if self.is_closed() {
+            return Err(QueueError::Generic("receiver dropped".into()));
+        }
+        self.0.queue.lock().unwrap().push_back(message);
+        self.0.notify.notify_one();
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.0.closed.load(Ordering::Relaxed)
+    }
+}
+
+/// The receiving half of a [`Channel`]. Closes the channel when dropped.
+struct Receiver(Arc<Channel>);
+
+impl Receiver {
+    async fn recv(&mut self) -> InMemoryMessage {
+        loop {
+            if let Some(message) = self.try_recv() {
+                return message;
+            }
+            // `notify_one` stores a permit if nobody is waiting yet, so a
+            // message sent since `try_recv` isn't missed.
+            self.0.notify.notified().await;
+        }
+    }
+
+    fn try_recv(&mut self) -> Option<InMemoryMessage> {
+        self.0.queue.lock().unwrap().pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.0.queue.lock().unwrap().len()
+    }
+
+    fn peek(&self, max_messages: usize) -> Vec<InMemoryMessage> {
+        let queue = self.0.queue.lock().unwrap();
+        queue.iter().take(max_messages).cloned().collect()
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Relaxed);
+    }
+}
+
 pub struct InMemoryProducer {
-    tx: mpsc::UnboundedSender<Vec<u8>>,
+    tx: Sender,
+    metrics: QueueMetrics,
 }
 
 impl InMemoryProducer {
+    async fn send(&self, message: InMemoryMessage) -> Result<()> {
+        self.metrics
+            .send([message.payload.len()], async { self.tx.send(message) })
+            .await
+    }
+
     pub async fn send_raw(&self, payload: &[u8]) -> Result<()> {
-        self.tx.send(payload.to_vec()).map_err(QueueError::generic)
+        self.send(InMemoryMessage::new(payload, Attributes::new()))
+            .await
     }
 
     pub async fn send_serde_json<P: Serialize + Sync>(&self, payload: &P) -> Result<()> {
@@ -62,17 +191,39 @@ impl InMemoryProducer {
         self.send_raw(&payload).await
     }
 
+    pub async fn send_raw_with_attributes(
+        &self,
+        payload: &[u8],
+        attributes: &Attributes,
+    ) -> Result<()> {
+        self.send(InMemoryMessage::new(payload, attributes.clone()))
+            .await
+    }
+
+    pub async fn send_serde_json_with_attributes<P: Serialize + Sync>(
+        &self,
+        payload: &P,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = serde_json::to_vec(payload)?;
+        self.send_raw_with_attributes(&payload, attributes).await
+    }
+
     pub async fn send_raw_scheduled(&self, payload: &[u8], delay: Duration) -> Result<()> {
         let tx = self.tx.clone();
-        let payload = payload.to_vec();
-        tokio::spawn(async move {
-            tracing::trace!("MemoryQueue: event sent > (delay: {:?})", delay);
-            tokio::time::sleep(delay).await;
-            if tx.send(payload).is_err() {
-                tracing::error!("Receiver dropped");
-            }
-        });
-        Ok(())
+        let payload = InMemoryMessage::new(payload, Attributes::new());
+        self.metrics
+            .send([payload.payload.len()], async {
+                tokio::spawn(async move {
+                    tracing::trace!("MemoryQueue: event sent > (delay: {:?})", delay);
+                    tokio::time::sleep(delay).await;
+                    if tx.send(payload).is_err() {
+                        tracing::error!("Receiver dropped");
+                    }
+                });
+                Ok(())
+            })
+            .await
     }
 
     pub async fn send_serde_json_scheduled<P: Serialize + Sync>(
@@ -89,40 +240,59 @@ impl InMemoryProducer {
             "redrive_dlq is not supported by InMemoryBackend",
         ))
     }
+
+    /// Fails if the consumer has been dropped.
+    pub async fn health_check(&self) -> Result<()> {
+        if self.tx.is_closed() {
+            return Err(QueueError::Generic("consumer was dropped".into()));
+        }
+        Ok(())
+    }
 }
 
 impl crate::QueueProducer for InMemoryProducer {
     type Payload = Vec<u8>;
-    omni_delegate!(send_raw, send_serde_json, redrive_dlq);
+    omni_delegate!(
+        send_raw,
+        send_serde_json,
+        send_raw_with_attributes,
+        send_serde_json_with_attributes,
+        redrive_dlq,
+        health_check
+    );
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities()
+    }
 }
 impl crate::ScheduledQueueProducer for InMemoryProducer {
     omni_delegate!(send_raw_scheduled, send_serde_json_scheduled);
 }
 
 pub struct InMemoryConsumer {
-    rx: mpsc::UnboundedReceiver<Vec<u8>>,
-    tx: mpsc::UnboundedSender<Vec<u8>>,
+    rx: Receiver,
+    tx: Sender,
+    metrics: QueueMetrics,
 }
 
 impl InMemoryConsumer {
-    fn wrap_payload(&self, payload: Vec<u8>) -> Delivery {
+    fn wrap_payload(&self, mut message: InMemoryMessage) -> Delivery {
+        message.receive_count += 1;
         Delivery::new(
-            payload.clone(),
+            message.payload.clone(),
             InMemoryAcker {
                 tx: self.tx.clone(),
-                payload_copy: Some(payload),
+                message_copy: Some(message.clone()),
                 already_acked_or_nacked: false,
             },
         )
+        .with_metadata(message.metadata())
+        .with_attributes(message.attributes)
     }
 
     pub async fn receive(&mut self) -> Result<Delivery> {
-        let payload = self
-            .rx
-            .recv()
-            .await
-            .ok_or_else(|| QueueError::Generic("recv failed".into()))?;
-        Ok(self.wrap_payload(payload))
+        let message = self.rx.recv().await;
+        self.metrics.receive(Ok(self.wrap_payload(message)))
     }
 
     pub async fn receive_all(
@@ -130,37 +300,85 @@ impl InMemoryConsumer {
         max_messages: usize,
         deadline: Duration,
     ) -> Result<Vec<Delivery>> {
+        let out = self.receive_all_inner(max_messages, deadline).await;
+        self.metrics.receive_all(Ok(out))
+    }
+
+    async fn receive_all_inner(
+        &mut self,
+        max_messages: usize,
+        deadline: Duration,
+    ) -> Vec<Delivery> {
         let mut out = Vec::with_capacity(max_messages);
         let start = Instant::now();
         match tokio::time::timeout(deadline, self.rx.recv()).await {
-            Ok(Some(x)) => out.push(self.wrap_payload(x)),
-            // Timeouts and stream termination
-            Err(_) | Ok(None) => return Ok(out),
+            Ok(x) => out.push(self.wrap_payload(x)),
+            Err(_) => return out,
         }
 
         if max_messages > 1 {
             // `try_recv` will break the loop if no ready items are already
             // buffered in the channel. This should allow us to
             // opportunistically fill up the buffer in the remaining time.
-            while let Ok(x) = self.rx.try_recv() {
+            while let Some(x) = self.rx.try_recv() {
                 out.push(self.wrap_payload(x));
                 if out.len() >= max_messages || start.elapsed() >= deadline {
                     break;
                 }
             }
         }
-        Ok(out)
+        out
+    }
+
+    /// Always succeeds, as there is no broker to reach.
+    pub async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the number of messages buffered in the channel.
+    pub async fn stats(&self) -> Result<QueueStats> {
+        Ok(QueueStats {
+            visible: Some(self.rx.len() as u64),
+            ..Default::default()
+        })
+    }
+
+    /// Returns up to `max_messages` of the messages buffered in the channel.
+    pub async fn peek(&self, max_messages: usize) -> Result<Vec<Delivery>> {
+        Ok(self
+            .rx
+            .peek(max_messages)
+            .into_iter()
+            .map(|message| {
+                let metadata = message.metadata();
+                Delivery::new(message.payload, PeekAcker)
+                    .with_metadata(metadata)
+                    .with_attributes(message.attributes)
+            })
+            .collect())
     }
 }
 
 impl crate::QueueConsumer for InMemoryConsumer {
     type Payload = Vec<u8>;
-    omni_delegate!(receive, receive_all);
+    omni_delegate!(receive, receive_all, health_check, stats, peek);
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities()
+    }
+}
+
+fn capabilities() -> Capabilities {
+    Capabilities {
+        scheduling: true,
+        ordering: MessageOrdering::Fifo,
+        ..Default::default()
+    }
 }
 
 struct InMemoryAcker {
-    tx: mpsc::UnboundedSender<Vec<u8>>,
-    payload_copy: Option<Vec<u8>>,
+    tx: Sender,
+    message_copy: Option<InMemoryMessage>,
     already_acked_or_nacked: bool,
 }
 
@@ -179,16 +397,34 @@ impl Acker for InMemoryAcker {
             Err(QueueError::CannotAckOrNackTwice)
         } else {
             self.already_acked_or_nacked = true;
-            self.tx
-                .send(
-                    self.payload_copy
-                        .take()
-                        .ok_or(QueueError::CannotAckOrNackTwice)?,
-                )
-                .map_err(QueueError::generic)
+            self.tx.send(
+                self.message_copy
+                    .take()
+                    .ok_or(QueueError::CannotAckOrNackTwice)?,
+            )
         }
     }
 
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        if self.already_acked_or_nacked {
+            return Err(QueueError::CannotAckOrNackTwice);
+        }
+
+        self.already_acked_or_nacked = true;
+        let message = self
+            .message_copy
+            .take()
+            .ok_or(QueueError::CannotAckOrNackTwice)?;
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if tx.send(message).is_err() {
+                tracing::error!("Receiver dropped");
+            }
+        });
+        Ok(())
+    }
+
     async fn set_ack_deadline(&mut self, _duration: Duration) -> Result<()> {
         Err(QueueError::Unsupported(
             "set_ack_deadline is not yet supported by InMemoryBackend",
@@ -198,12 +434,23 @@ impl Acker for InMemoryAcker {
 
 #[cfg(test)]
 mod tests {
-    use std::time::{Duration, Instant};
+    use std::{
+        collections::HashSet,
+        time::{Duration, Instant},
+    };
 
+    use futures_util::{StreamExt as _, TryStreamExt as _};
     use serde::{Deserialize, Serialize};
 
-    use super::InMemoryBackend;
-    use crate::QueueProducer;
+    use super::{channel, InMemoryBackend, InMemoryMessage};
+    use crate::{
+        codec::Json, Attributes, MessageOrdering, QueueConsumer, QueueError, QueueProducer,
+        ScheduledQueueProducer as _,
+    };
+
+    fn message(payload: u8) -> InMemoryMessage {
+        InMemoryMessage::new(&[payload], Attributes::new())
+    }
 
     #[derive(Clone, Copy, Debug, Eq, Deserialize, PartialEq, Serialize)]
     struct TypeA {
@@ -235,6 +482,140 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_send_recv_with_attributes() {
+        let (p, mut c) = InMemoryBackend::builder().build_pair().await.unwrap();
+
+        let attributes = Attributes::from([("tenant_id".to_owned(), "t_1".to_owned())]);
+        p.send_serde_json_with_attributes(&TypeA { a: 15 }, &attributes)
+            .await
+            .unwrap();
+
+        let d = c.receive().await.unwrap();
+        assert_eq!(d.attributes(), &attributes);
+        assert_eq!(
+            d.payload_serde_json::<TypeA>().unwrap().unwrap(),
+            TypeA { a: 15 }
+        );
+
+        // Attributes survive redelivery after a nack
+        d.nack().await.unwrap();
+        let d = c.receive().await.unwrap();
+        assert_eq!(d.attributes(), &attributes);
+        d.ack().await.unwrap();
+
+        p.send_raw(b"no attributes").await.unwrap();
+        let d = c.receive().await.unwrap();
+        assert!(d.attributes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delivery_metadata() {
+        let (p, mut c) = InMemoryBackend::builder().build_pair().await.unwrap();
+
+        p.send_raw(b"first").await.unwrap();
+        p.send_raw(b"second").await.unwrap();
+
+        let d1 = c.receive().await.unwrap();
+        let metadata = d1.metadata().clone();
+        assert_eq!(metadata.receive_count, Some(1));
+        assert!(metadata.enqueued_at.is_some());
+
+        let d2 = c.receive().await.unwrap();
+        assert_ne!(d2.metadata().message_id, metadata.message_id);
+        d2.ack().await.unwrap();
+
+        // Redelivery keeps the message ID and bumps the receive count
+        d1.nack().await.unwrap();
+        let d1 = c.receive().await.unwrap();
+        assert_eq!(d1.metadata().message_id, metadata.message_id);
+        assert_eq!(d1.metadata().enqueued_at, metadata.enqueued_at);
+        assert_eq!(d1.metadata().receive_count, Some(2));
+        d1.ack().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_into_stream() {
+        let (p, c) = InMemoryBackend::builder().build_pair().await.unwrap();
+
+        for a in 0..3 {
+            p.send_serde_json(&TypeA { a }).await.unwrap();
+        }
+
+        let deliveries: Vec<_> = c.into_stream().take(3).try_collect().await.unwrap();
+        let payloads: Vec<_> = deliveries
+            .iter()
+            .map(|d| d.payload_serde_json::<TypeA>().unwrap().unwrap().a)
+            .collect();
+        assert_eq!(payloads, [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_dyn_into_stream() {
+        let (p, c) = InMemoryBackend::builder()
+            .make_dynamic()
+            .build_pair()
+            .await
+            .unwrap();
+
+        p.send_serde_json(&TypeA { a: 16 }).await.unwrap();
+
+        let mut stream = c.into_stream();
+        let d = stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            d.payload_serde_json::<TypeA>().unwrap().unwrap(),
+            TypeA { a: 16 }
+        );
+        d.ack().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_nack_with_delay() {
+        let (p, mut c) = InMemoryBackend::builder().build_pair().await.unwrap();
+
+        p.send_raw(b"retry me").await.unwrap();
+        let d = c.receive().await.unwrap();
+
+        let delay = Duration::from_millis(100);
+        let now = Instant::now();
+        d.nack_with_delay(delay).await.unwrap();
+
+        let d = c
+            .receive_all(1, delay * 2)
+            .await
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        assert!(now.elapsed() >= delay);
+        assert_eq!(d.borrow_payload().unwrap(), b"retry me");
+        assert_eq!(d.metadata().receive_count, Some(2));
+        d.ack().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_recv_encoded() {
+        let (p, mut c) = InMemoryBackend::builder().build_pair().await.unwrap();
+
+        p.send_encoded::<Json, _>(&TypeA { a: 13 }).await.unwrap();
+        let d = c.receive().await.unwrap();
+        assert_eq!(d.borrow_payload().unwrap(), br#"{"a":13}"#);
+        assert_eq!(
+            d.payload_decoded::<Json, TypeA>().unwrap(),
+            Some(TypeA { a: 13 })
+        );
+
+        p.send_encoded_batch::<Json, TypeA>([TypeA { a: 14 }, TypeA { a: 15 }])
+            .await
+            .unwrap();
+        let ds = c.receive_all(2, Duration::from_millis(1)).await.unwrap();
+        let payloads: Vec<_> = ds
+            .iter()
+            .map(|d| d.payload_decoded::<Json, TypeA>().unwrap().unwrap())
+            .collect();
+        assert_eq!(payloads, [TypeA { a: 14 }, TypeA { a: 15 }]);
+    }
+
     #[derive(Debug, Deserialize, Serialize, PartialEq)]
     struct ExType {
         a: u8,
@@ -356,6 +737,131 @@ mod tests {
         assert!(elapsed <= deadline + Duration::from_millis(200));
     }
 
+    #[tokio::test]
+    async fn test_health_check() {
+        let (p, c) = InMemoryBackend::builder()
+            .make_dynamic()
+            .build_pair()
+            .await
+            .unwrap();
+        p.health_check().await.unwrap();
+        c.health_check().await.unwrap();
+
+        drop(c);
+        p.health_check().await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_capabilities() {
+        let (p, c) = InMemoryBackend::builder().build_pair().await.unwrap();
+
+        let capabilities = p.capabilities();
+        assert!(capabilities.scheduling);
+        assert!(!capabilities.set_ack_deadline);
+        assert_eq!(capabilities.max_receive_batch_size, None);
+        assert_eq!(capabilities.ordering, MessageOrdering::Fifo);
+        assert_eq!(c.capabilities(), capabilities);
+
+        // The dynamic types report the capabilities of what they wrap
+        assert_eq!(p.into_dyn_scheduled().capabilities(), capabilities);
+        assert_eq!(c.into_dyn().capabilities(), capabilities);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let (p, mut c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        assert_eq!(c.stats().await.unwrap().visible, Some(0));
+
+        p.send_raw(b"1").await.unwrap();
+        p.send_raw(b"2").await.unwrap();
+        assert_eq!(c.stats().await.unwrap().visible, Some(2));
+
+        c.receive().await.unwrap().ack().await.unwrap();
+        let stats = c.stats().await.unwrap();
+        assert_eq!(stats.visible, Some(1));
+        assert_eq!(stats.in_flight, None);
+    }
+
+    #[tokio::test]
+    async fn test_peek() {
+        let (p, mut c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        p.send_raw(b"1").await.unwrap();
+        p.send_raw(b"2").await.unwrap();
+        p.send_raw(b"3").await.unwrap();
+
+        let peeked = c.peek(2).await.unwrap();
+        let payloads: Vec<_> = peeked.iter().map(|d| d.borrow_payload().unwrap()).collect();
+        assert_eq!(payloads, [b"1", b"2"]);
+        assert_eq!(peeked[0].metadata().receive_count, Some(0));
+        let (e, _) = peeked.into_iter().next().unwrap().ack().await.unwrap_err();
+        assert!(matches!(e, QueueError::Unsupported(_)));
+
+        // Peeking leaves the messages in the queue.
+        let d = c.receive().await.unwrap();
+        assert_eq!(d.borrow_payload().unwrap(), b"1");
+        assert_eq!(d.metadata().receive_count, Some(1));
+        assert_eq!(c.peek(10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_channel_send_after_receiver_dropped() {
+        let (tx, rx) = channel();
+        tx.send(message(0)).unwrap();
+        drop(rx);
+        assert!(tx.is_closed());
+        assert!(tx.send(message(1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_channel_wakes_waiting_receiver() {
+        let (tx, mut rx) = channel();
+
+        // A receive that times out must not swallow the next wakeup
+        assert!(tokio::time::timeout(Duration::from_millis(10), rx.recv())
+            .await
+            .is_err());
+
+        let recv = tokio::spawn(async move { rx.recv().await.id });
+        tokio::task::yield_now().await;
+        let sent = message(0);
+        let id = sent.id;
+        tx.send(sent).unwrap();
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(5), recv)
+                .await
+                .unwrap()
+                .unwrap(),
+            id
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_channel_concurrent_sends() {
+        const SENDERS: usize = 8;
+        const MESSAGES: usize = 250;
+
+        let (tx, mut rx) = channel();
+        for _ in 0..SENDERS {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                for _ in 0..MESSAGES {
+                    tx.send(message(0)).unwrap();
+                    tokio::task::yield_now().await;
+                }
+            });
+        }
+
+        let mut ids = HashSet::new();
+        for _ in 0..SENDERS * MESSAGES {
+            let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .expect("receiver missed a wakeup");
+            ids.insert(message.id);
+        }
+        assert_eq!(ids.len(), SENDERS * MESSAGES);
+        assert!(rx.try_recv().is_none());
+    }
+
     #[tokio::test]
     async fn test_scheduled() {
         let payload1 = ExType { a: 1 };
@@ -376,4 +882,20 @@ mod tests {
         assert!(now.elapsed() < delay * 2);
         assert_eq!(Some(payload1), delivery.payload_serde_json().unwrap());
     }
+
+    #[tokio::test]
+    async fn test_dyn_scheduled() {
+        let (p, mut c) = InMemoryBackend::builder()
+            .make_dynamic()
+            .build_scheduled_pair()
+            .await
+            .unwrap();
+
+        let delay = Duration::from_millis(100);
+        let now = Instant::now();
+        p.send_raw_scheduled(b"delayed", delay).await.unwrap();
+        let delivery = c.receive().await.unwrap();
+        assert!(now.elapsed() >= delay);
+        assert_eq!(delivery.borrow_payload().unwrap(), b"delayed");
+    }
 }
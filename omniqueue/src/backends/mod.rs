@@ -12,16 +12,22 @@ pub mod redis;
 pub mod sqs;
 
 #[cfg(feature = "azure_queue_storage")]
-pub use azure_queue_storage::{AqsBackend, AqsConfig, AqsConsumer, AqsProducer};
+pub use azure_queue_storage::{AqsAdmin, AqsBackend, AqsConfig, AqsConsumer, AqsProducer};
 #[cfg(feature = "gcp_pubsub")]
-pub use gcp_pubsub::{GcpPubSubBackend, GcpPubSubConfig, GcpPubSubConsumer, GcpPubSubProducer};
+pub use gcp_pubsub::{
+    GcpPubSubAdmin, GcpPubSubBackend, GcpPubSubConfig, GcpPubSubConsumer, GcpPubSubProducer,
+};
 #[cfg(feature = "in_memory")]
 pub use in_memory::{InMemoryBackend, InMemoryConsumer, InMemoryProducer};
 #[cfg(feature = "rabbitmq")]
-pub use rabbitmq::{RabbitMqBackend, RabbitMqConfig, RabbitMqConsumer, RabbitMqProducer};
+pub use rabbitmq::{
+    RabbitMqAdmin, RabbitMqBackend, RabbitMqConfig, RabbitMqConsumer, RabbitMqProducer,
+};
 #[cfg(feature = "redis")]
-pub use redis::{RedisBackend, RedisBackendBuilder, RedisConfig, RedisConsumer, RedisProducer};
+pub use redis::{
+    RedisAdmin, RedisBackend, RedisBackendBuilder, RedisConfig, RedisConsumer, RedisProducer,
+};
 #[cfg(feature = "redis_cluster")]
 pub use redis::{RedisClusterBackend, RedisClusterBackendBuilder};
 #[cfg(feature = "sqs")]
-pub use sqs::{SqsBackend, SqsConfig, SqsConsumer, SqsProducer};
+pub use sqs::{SqsAdmin, SqsBackend, SqsConfig, SqsConsumer, SqsProducer};
@@ -1,7 +1,10 @@
-use std::time::{Duration, Instant};
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use futures_util::{FutureExt, StreamExt};
-use lapin::types::AMQPValue;
 pub use lapin::{
     acker::Acker as LapinAcker,
     options::{
@@ -11,15 +14,57 @@ pub use lapin::{
     types::FieldTable,
     BasicProperties, Channel, Connection, ConnectionProperties, Consumer,
 };
+use lapin::{
+    options::{QueueDeclareOptions, QueueDeleteOptions, QueuePurgeOptions},
+    protocol::{AMQPErrorKind, AMQPHardError, AMQPSoftError},
+    types::AMQPValue,
+    ChannelState,
+};
 use serde::Serialize;
+use time::OffsetDateTime;
 
 #[allow(deprecated)]
 use crate::{
     builder::{QueueBuilder, Static},
-    queue::{Acker, Delivery, QueueBackend},
-    QueueError, Result,
+    metrics::QueueMetrics,
+    queue::{Acker, Delivery, DeliveryMetadata, QueueBackend},
+    Attributes, Capabilities, ErrorKind, MessageOrdering, QueueAdmin, QueueError, QueueStats,
+    Result,
 };
 
+/// Wraps an error returned by lapin as a [`QueueError::Backend`] for the given
+/// operation.
+fn rabbitmq_error(operation: &'static str) -> impl FnOnce(lapin::Error) -> QueueError {
+    move |e| {
+        let kind = match &e {
+            lapin::Error::IOError(_)
+            | lapin::Error::InvalidChannelState(_)
+            | lapin::Error::InvalidConnectionState(_)
+            | lapin::Error::MissingHeartbeatError => ErrorKind::ConnectionLost,
+            lapin::Error::ProtocolError(e) => match e.kind() {
+                AMQPErrorKind::Soft(AMQPSoftError::ACCESSREFUSED)
+                | AMQPErrorKind::Hard(AMQPHardError::NOTALLOWED) => ErrorKind::AuthFailure,
+                AMQPErrorKind::Soft(AMQPSoftError::NOTFOUND) => ErrorKind::QueueNotFound,
+                AMQPErrorKind::Hard(AMQPHardError::CONNECTIONFORCED) => ErrorKind::ConnectionLost,
+                AMQPErrorKind::Hard(AMQPHardError::RESOURCEERROR) => ErrorKind::Throttled,
+                _ => ErrorKind::Other,
+            },
+            _ => ErrorKind::Other,
+        };
+        QueueError::backend(kind, "rabbitmq", operation, e)
+    }
+}
+
+/// Fails unless `channel` is open.
+fn check_channel(channel: &Channel) -> Result<()> {
+    match channel.status().state() {
+        ChannelState::Connected => Ok(()),
+        state => Err(rabbitmq_error("health_check")(
+            lapin::Error::InvalidChannelState(state),
+        )),
+    }
+}
+
 #[derive(Clone)]
 pub struct RabbitMqConfig {
     pub uri: String,
@@ -37,6 +82,17 @@ pub struct RabbitMqConfig {
 
     pub consume_prefetch_count: Option<u16>,
     pub requeue_on_nack: bool,
+
+    /// Whether `publish_exchange` is an `x-delayed-message` exchange of the
+    /// [delayed message exchange plugin], which delays messages by their
+    /// `x-delay` header.
+    ///
+    /// [`Delivery::nack_with_delay`] requires this, and returns
+    /// [`QueueError::Unsupported`] otherwise. Consumers only keep a copy of
+    /// received messages to republish them when this is set.
+    ///
+    /// [delayed message exchange plugin]: https://github.com/rabbitmq/rabbitmq-delayed-message-exchange
+    pub delayed_message_exchange: bool,
 }
 
 pub struct RabbitMqBackend;
@@ -49,14 +105,37 @@ impl RabbitMqBackend {
     }
 }
 
-async fn consumer(conn: &Connection, cfg: RabbitMqConfig) -> Result<RabbitMqConsumer> {
-    let channel_rx = conn.create_channel().await.map_err(QueueError::generic)?;
+impl<S, L> QueueBuilder<RabbitMqBackend, S, L> {
+    /// Builds a [`RabbitMqAdmin`] for the configured consume queue.
+    pub async fn build_admin(self) -> Result<RabbitMqAdmin> {
+        let conn = Connection::connect(&self.config.uri, self.config.connection_properties)
+            .await
+            .map_err(rabbitmq_error("connect"))?;
+
+        Ok(RabbitMqAdmin {
+            connection: conn,
+            queue: self.config.consume_queue,
+        })
+    }
+}
+
+/// With a delayed message exchange, the consumer also publishes messages
+/// through `publisher` to implement `nack_with_delay`.
+async fn consumer(
+    conn: Arc<Connection>,
+    cfg: RabbitMqConfig,
+    publisher: Option<Arc<RabbitMqProducer>>,
+) -> Result<RabbitMqConsumer> {
+    let channel_rx = conn
+        .create_channel()
+        .await
+        .map_err(rabbitmq_error("connect"))?;
 
     if let Some(n) = cfg.consume_prefetch_count {
         channel_rx
             .basic_qos(n, BasicQosOptions::default())
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(rabbitmq_error("connect"))?;
     }
 
     Ok(RabbitMqConsumer {
@@ -68,20 +147,41 @@ async fn consumer(conn: &Connection, cfg: RabbitMqConfig) -> Result<RabbitMqCons
                 cfg.consume_arguments.clone(),
             )
             .await
-            .map_err(QueueError::generic)?,
+            .map_err(rabbitmq_error("connect"))?,
+        channel: channel_rx,
+        connection: conn,
         requeue_on_nack: cfg.requeue_on_nack,
+        metrics: QueueMetrics::new("rabbitmq", &cfg.consume_queue),
+        publisher,
     })
 }
 
-async fn producer(conn: &Connection, cfg: RabbitMqConfig) -> Result<RabbitMqProducer> {
-    let channel_tx = conn.create_channel().await.map_err(QueueError::generic)?;
-    Ok(RabbitMqProducer {
-        channel: channel_tx,
+async fn producer(conn: &Connection, cfg: &RabbitMqConfig) -> Result<RabbitMqProducer> {
+    let channel_tx = conn
+        .create_channel()
+        .await
+        .map_err(rabbitmq_error("connect"))?;
+    Ok(publisher(channel_tx, cfg))
+}
+
+/// Creates a producer that publishes on `channel`, which may be shared with
+/// other producers.
+fn publisher(channel: Channel, cfg: &RabbitMqConfig) -> RabbitMqProducer {
+    // Messages published to the default exchange are routed to the queue
+    // named by the routing key
+    let queue = match cfg.publish_exchange.as_str() {
+        "" => &cfg.publish_routing_key,
+        exchange => exchange,
+    };
+    RabbitMqProducer {
+        channel,
         exchange: cfg.publish_exchange.clone(),
         routing_key: cfg.publish_routing_key.clone(),
         options: cfg.publish_options,
         properties: cfg.publish_properties.clone(),
-    })
+        delayed_message_exchange: cfg.delayed_message_exchange,
+        metrics: QueueMetrics::new("rabbitmq", queue),
+    }
 }
 
 #[allow(deprecated)]
@@ -97,28 +197,37 @@ impl QueueBackend for RabbitMqBackend {
     async fn new_pair(cfg: RabbitMqConfig) -> Result<(RabbitMqProducer, RabbitMqConsumer)> {
         let conn = Connection::connect(&cfg.uri, cfg.connection_properties.clone())
             .await
-            .map_err(QueueError::generic)?;
-
-        Ok((
-            producer(&conn, cfg.clone()).await?,
-            consumer(&conn, cfg.clone()).await?,
-        ))
+            .map_err(rabbitmq_error("connect"))?;
+        let conn = Arc::new(conn);
+
+        let producer = producer(&conn, &cfg).await?;
+        // The consumer republishes messages on the producer's channel
+        let publisher = cfg
+            .delayed_message_exchange
+            .then(|| Arc::new(publisher(producer.channel.clone(), &cfg)));
+        let consumer = consumer(conn, cfg, publisher).await?;
+        Ok((producer, consumer))
     }
 
     async fn producing_half(cfg: RabbitMqConfig) -> Result<RabbitMqProducer> {
         let conn = Connection::connect(&cfg.uri, cfg.connection_properties.clone())
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(rabbitmq_error("connect"))?;
 
-        producer(&conn, cfg.clone()).await
+        producer(&conn, &cfg).await
     }
 
     async fn consuming_half(cfg: RabbitMqConfig) -> Result<RabbitMqConsumer> {
         let conn = Connection::connect(&cfg.uri, cfg.connection_properties.clone())
             .await
-            .map_err(QueueError::generic)?;
-
-        consumer(&conn, cfg.clone()).await
+            .map_err(rabbitmq_error("connect"))?;
+        let conn = Arc::new(conn);
+
+        let publisher = match cfg.delayed_message_exchange {
+            true => Some(Arc::new(producer(&conn, &cfg).await?)),
+            false => None,
+        };
+        consumer(conn, cfg, publisher).await
     }
 }
 
@@ -128,6 +237,8 @@ pub struct RabbitMqProducer {
     routing_key: String,
     options: BasicPublishOptions,
     properties: BasicProperties,
+    delayed_message_exchange: bool,
+    metrics: QueueMetrics,
 }
 
 impl RabbitMqProducer {
@@ -136,15 +247,26 @@ impl RabbitMqProducer {
         payload: &[u8],
         headers: Option<FieldTable>,
     ) -> Result<()> {
+        self.metrics
+            .send([payload.len()], self.publish(payload, headers))
+            .await
+    }
+
+    /// Publishes a message without recording it as sent, which is also used
+    /// by consumers to redeliver messages.
+    async fn publish(&self, payload: &[u8], headers: Option<FieldTable>) -> Result<()> {
         let mut properties = self.properties.clone();
         #[cfg(feature = "rabbitmq-with-message-ids")]
         {
             use svix_ksuid::{KsuidLike as _, KsuidMs};
-            use time::OffsetDateTime;
 
             let id = &KsuidMs::new(Some(OffsetDateTime::now_utc()), None);
             properties = properties.with_message_id(id.to_string().into());
         }
+        if properties.timestamp().is_none() {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            properties = properties.with_timestamp(now.try_into().unwrap_or_default());
+        }
         if let Some(headers) = headers {
             properties = properties.with_headers(headers);
         }
@@ -158,7 +280,7 @@ impl RabbitMqProducer {
                 properties,
             )
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(rabbitmq_error("send"))?;
 
         Ok(())
     }
@@ -177,6 +299,38 @@ impl RabbitMqProducer {
         self.send_raw(&payload).await
     }
 
+    /// Sends a raw message with the given attributes added to the configured
+    /// message headers.
+    #[tracing::instrument(
+        name = "send",
+        skip_all,
+        fields(payload_size = payload.len())
+    )]
+    pub async fn send_raw_with_attributes(
+        &self,
+        payload: &[u8],
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let mut headers = self.properties.headers().clone().unwrap_or_default();
+        for (key, value) in attributes {
+            headers.insert(
+                key.as_str().into(),
+                AMQPValue::LongString(value.as_str().into()),
+            );
+        }
+
+        self.send_raw_with_headers(payload, Some(headers)).await
+    }
+
+    pub async fn send_serde_json_with_attributes<P: Serialize + Sync>(
+        &self,
+        payload: &P,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = serde_json::to_vec(payload)?;
+        self.send_raw_with_attributes(&payload, attributes).await
+    }
+
     #[tracing::instrument(
         name = "send",
         skip_all,
@@ -208,11 +362,27 @@ impl RabbitMqProducer {
             "redrive_dlq is not supported by RabbitMqBackend",
         ))
     }
+
+    /// Checks that the channel used for publishing is open.
+    pub async fn health_check(&self) -> Result<()> {
+        check_channel(&self.channel)
+    }
 }
 
 impl crate::QueueProducer for RabbitMqProducer {
     type Payload = Vec<u8>;
-    omni_delegate!(send_raw, send_serde_json, redrive_dlq);
+    omni_delegate!(
+        send_raw,
+        send_serde_json,
+        send_raw_with_attributes,
+        send_serde_json_with_attributes,
+        redrive_dlq,
+        health_check
+    );
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities(self.delayed_message_exchange)
+    }
 }
 impl crate::ScheduledQueueProducer for RabbitMqProducer {
     omni_delegate!(send_raw_scheduled, send_serde_json_scheduled);
@@ -220,26 +390,53 @@ impl crate::ScheduledQueueProducer for RabbitMqProducer {
 
 pub struct RabbitMqConsumer {
     consumer: Consumer,
+    channel: Channel,
+    // For opening channels for operations that may fail, see `stats`
+    connection: Arc<Connection>,
     requeue_on_nack: bool,
+    publisher: Option<Arc<RabbitMqProducer>>,
+    metrics: QueueMetrics,
 }
 
 impl RabbitMqConsumer {
     fn wrap_delivery(&self, delivery: lapin::message::Delivery) -> Delivery {
+        let attributes = delivery
+            .properties
+            .headers()
+            .as_ref()
+            .map(headers_to_attributes)
+            .unwrap_or_default();
+        let metadata = delivery_metadata(&delivery);
+        let redelivery = self.publisher.as_ref().map(|publisher| Redelivery {
+            publisher: publisher.clone(),
+            payload: delivery.data.clone(),
+            headers: delivery.properties.headers().clone().unwrap_or_default(),
+            receive_count: metadata.receive_count,
+        });
+
         Delivery::new(
             delivery.data,
             RabbitMqAcker {
                 acker: Some(delivery.acker),
                 requeue_on_nack: self.requeue_on_nack,
+                redelivery,
             },
         )
+        .with_metadata(metadata)
+        .with_attributes(attributes)
     }
 
     pub async fn receive(&mut self) -> Result<Delivery> {
+        let result = self.receive_inner().await;
+        self.metrics.receive(result)
+    }
+
+    async fn receive_inner(&mut self) -> Result<Delivery> {
         let mut stream =
             self.consumer
                 .clone()
                 .map(|l: Result<lapin::message::Delivery, lapin::Error>| {
-                    let l = l.map_err(QueueError::generic)?;
+                    let l = l.map_err(rabbitmq_error("receive"))?;
                     Ok(self.wrap_delivery(l))
                 });
 
@@ -250,10 +447,19 @@ impl RabbitMqConsumer {
         &mut self,
         max_messages: usize,
         deadline: Duration,
+    ) -> Result<Vec<Delivery>> {
+        let result = self.receive_all_inner(max_messages, deadline).await;
+        self.metrics.receive_all(result)
+    }
+
+    async fn receive_all_inner(
+        &mut self,
+        max_messages: usize,
+        deadline: Duration,
     ) -> Result<Vec<Delivery>> {
         let mut stream = self.consumer.clone().map(
             |l: Result<lapin::message::Delivery, lapin::Error>| -> Result<Delivery> {
-                let l = l.map_err(QueueError::generic)?;
+                let l = l.map_err(rabbitmq_error("receive"))?;
                 Ok(self.wrap_delivery(l))
             },
         );
@@ -278,16 +484,150 @@ impl RabbitMqConsumer {
         }
         Ok(out)
     }
+
+    /// Checks that the consumer is still active, and that its channel and the
+    /// channel used to republish messages for `nack_with_delay`, if any, are
+    /// open.
+    pub async fn health_check(&self) -> Result<()> {
+        check_channel(&self.channel)?;
+        if let Some(publisher) = &self.publisher {
+            check_channel(&publisher.channel)?;
+        }
+        if !self.consumer.state().is_active() {
+            return Err(QueueError::backend(
+                ErrorKind::Other,
+                "rabbitmq",
+                "health_check",
+                format!("consumer {} was cancelled", self.consumer.tag()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of ready messages in the consumed queue, using a
+    /// passive `queue_declare`.
+    ///
+    /// RabbitMQ doesn't report unacknowledged messages through this, so the
+    /// in-flight count is left as `None`.
+    ///
+    /// This runs on a channel of its own, since the broker closes the channel
+    /// if the queue doesn't exist or can't be accessed, which would stop the
+    /// consumer if it ran on the consumer's channel.
+    pub async fn stats(&self) -> Result<QueueStats> {
+        let queue = self
+            .connection
+            .create_channel()
+            .await
+            .map_err(rabbitmq_error("stats"))?
+            .queue_declare(
+                self.consumer.queue().as_str(),
+                QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(rabbitmq_error("stats"))?;
+
+        Ok(QueueStats {
+            visible: Some(queue.message_count().into()),
+            ..Default::default()
+        })
+    }
+}
+
+/// Converts the string-valued message headers to attributes.
+///
+/// Headers of other types, such as the `x-delay` header used for scheduled
+/// messages, are skipped.
+fn headers_to_attributes(headers: &FieldTable) -> Attributes {
+    headers
+        .inner()
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                AMQPValue::LongString(s) => String::from_utf8(s.as_bytes().to_owned()).ok()?,
+                AMQPValue::ShortString(s) => s.as_str().to_owned(),
+                _ => return None,
+            };
+            Some((key.as_str().to_owned(), value))
+        })
+        .collect()
+}
+
+/// The header [`Delivery::nack_with_delay`] uses to carry a message's receive
+/// count over to the copy it republishes.
+const RECEIVE_COUNT_HEADER: &str = "x-omniqueue-receive-count";
+
+fn header_u32(headers: Option<&FieldTable>, key: &str) -> Option<u32> {
+    match headers?.inner().get(key)? {
+        AMQPValue::ShortShortUInt(n) => Some(u32::from(*n)),
+        AMQPValue::ShortUInt(n) => Some(u32::from(*n)),
+        AMQPValue::LongUInt(n) => Some(*n),
+        AMQPValue::LongInt(n) => (*n).try_into().ok(),
+        AMQPValue::LongLongInt(n) => (*n).try_into().ok(),
+        _ => None,
+    }
+}
+
+fn delivery_metadata(delivery: &lapin::message::Delivery) -> DeliveryMetadata {
+    let headers = delivery.properties.headers().as_ref();
+    // Quorum queues count previous deliveries in the `x-delivery-count`
+    // header; otherwise the most we know is whether this is a redelivery.
+    let delivery_count = header_u32(headers, "x-delivery-count");
+    let previous_receives = header_u32(headers, RECEIVE_COUNT_HEADER).unwrap_or(0);
+    let receive_count = match delivery_count {
+        Some(count) => Some(count.saturating_add(1)),
+        None if !delivery.redelivered => Some(1),
+        None => None,
+    }
+    .map(|count| count.saturating_add(previous_receives));
+
+    DeliveryMetadata {
+        message_id: delivery
+            .properties
+            .message_id()
+            .as_ref()
+            .map(|id| id.as_str().to_owned()),
+        receive_count,
+        enqueued_at: (*delivery.properties.timestamp())
+            .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs.try_into().ok()?).ok()),
+    }
 }
 
 impl crate::QueueConsumer for RabbitMqConsumer {
     type Payload = Vec<u8>;
-    omni_delegate!(receive, receive_all);
+    omni_delegate!(receive, receive_all, health_check, stats);
+
+    fn capabilities(&self) -> Capabilities {
+        // Consumers only have a publisher with the delayed message exchange
+        capabilities(self.publisher.is_some())
+    }
+}
+
+/// Scheduling requires the delayed message exchange plugin, see
+/// [`RabbitMqConfig::delayed_message_exchange`].
+fn capabilities(delayed_message_exchange: bool) -> Capabilities {
+    Capabilities {
+        scheduling: delayed_message_exchange,
+        ordering: MessageOrdering::Fifo,
+        ..Default::default()
+    }
 }
 
 struct RabbitMqAcker {
     acker: Option<LapinAcker>,
     requeue_on_nack: bool,
+    redelivery: Option<Redelivery>,
+}
+
+/// A copy of a received message, to republish it with a delay.
+struct Redelivery {
+    publisher: Arc<RabbitMqProducer>,
+    payload: Vec<u8>,
+    headers: FieldTable,
+    receive_count: Option<u32>,
 }
 
 impl Acker for RabbitMqAcker {
@@ -298,7 +638,7 @@ impl Acker for RabbitMqAcker {
             .ack(BasicAckOptions { multiple: false })
             .await
             .map(|_| ())
-            .map_err(QueueError::generic)
+            .map_err(rabbitmq_error("ack"))
     }
 
     async fn nack(&mut self) -> Result<()> {
@@ -311,7 +651,51 @@ impl Acker for RabbitMqAcker {
             })
             .await
             .map(|_| ())
-            .map_err(QueueError::generic)
+            .map_err(rabbitmq_error("nack"))
+    }
+
+    /// RabbitMQ can't delay redelivery of a message, so this publishes a copy
+    /// of it with an `x-delay` header through the configured exchange, then
+    /// acks the original.
+    ///
+    /// This requires the exchange to be a delayed message exchange, see
+    /// [`RabbitMqConfig::delayed_message_exchange`]. The copy keeps the
+    /// original's headers and receive count, but is otherwise a new message.
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        if self.acker.is_none() {
+            return Err(QueueError::CannotAckOrNackTwice);
+        }
+        let Some(redelivery) = &self.redelivery else {
+            return Err(QueueError::Unsupported(
+                "nack_with_delay requires RabbitMqConfig::delayed_message_exchange",
+            ));
+        };
+
+        let delay_ms: u32 = delay
+            .as_millis()
+            .try_into()
+            .map_err(|_| QueueError::Generic("delay is too large".into()))?;
+        let mut headers: FieldTable = redelivery
+            .headers
+            .inner()
+            .iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "x-delivery-count" | RECEIVE_COUNT_HEADER))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<BTreeMap<_, _>>()
+            .into();
+        headers.insert("x-delay".into(), AMQPValue::LongUInt(delay_ms));
+        if let Some(receive_count) = redelivery.receive_count {
+            headers.insert(
+                RECEIVE_COUNT_HEADER.into(),
+                AMQPValue::LongUInt(receive_count),
+            );
+        }
+
+        redelivery
+            .publisher
+            .publish(&redelivery.payload, Some(headers))
+            .await?;
+        self.ack().await
     }
 
     async fn set_ack_deadline(&mut self, _duration: Duration) -> Result<()> {
@@ -320,3 +704,84 @@ impl Acker for RabbitMqAcker {
         ))
     }
 }
+
+/// Administers the configured consume queue.
+///
+/// Queues are declared durable, with no extra arguments. Exchanges and
+/// bindings are not managed.
+///
+/// Created with [`QueueBuilder::build_admin`].
+pub struct RabbitMqAdmin {
+    connection: Connection,
+    queue: String,
+}
+
+impl RabbitMqAdmin {
+    /// Opens a channel for a single operation, since the broker closes the
+    /// channel when an operation on it fails.
+    async fn channel(&self, operation: &'static str) -> Result<Channel> {
+        self.connection
+            .create_channel()
+            .await
+            .map_err(rabbitmq_error(operation))
+    }
+
+    pub async fn create_queue(&self) -> Result<()> {
+        self.channel("create_queue")
+            .await?
+            .queue_declare(
+                &self.queue,
+                QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(rabbitmq_error("create_queue"))?;
+        Ok(())
+    }
+
+    pub async fn delete_queue(&self) -> Result<()> {
+        self.channel("delete_queue")
+            .await?
+            .queue_delete(&self.queue, QueueDeleteOptions::default())
+            .await
+            .map_err(rabbitmq_error("delete_queue"))?;
+        Ok(())
+    }
+
+    pub async fn purge(&self) -> Result<()> {
+        self.channel("purge")
+            .await?
+            .queue_purge(&self.queue, QueuePurgeOptions::default())
+            .await
+            .map_err(rabbitmq_error("purge"))?;
+        Ok(())
+    }
+
+    pub async fn exists(&self) -> Result<bool> {
+        let res = self
+            .channel("exists")
+            .await?
+            .queue_declare(
+                &self.queue,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(rabbitmq_error("exists"));
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::QueueNotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl QueueAdmin for RabbitMqAdmin {
+    omni_delegate!(create_queue, delete_queue, purge, exists);
+}
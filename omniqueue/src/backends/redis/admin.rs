@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use bb8::ManageConnection;
+use redis::{
+    streams::{
+        StreamInfoGroupsReply, StreamPendingCountReply, StreamTrimOptions, StreamTrimmingMode,
+    },
+    AsyncCommands, RedisResult,
+};
+
+use super::{redis_error, RedisConnection};
+use crate::{QueueAdmin, QueueError, Result};
+
+/// The number of pending entries to acknowledge at once when purging a stream.
+const PURGE_ACK_BATCH_SIZE: usize = 1000;
+
+/// Administers the keys used by a redis queue.
+///
+/// With redis streams, the queue is the main stream together with its
+/// consumer group. With the fallback implementation, the main and processing
+/// queues are lists, which redis creates when the first message is pushed and
+/// deletes when they are empty, so [`create_queue`](Self::create_queue) does
+/// nothing and [`exists`](Self::exists) is unsupported.
+///
+/// Deleting or purging the queue also removes its delayed messages, but not
+/// the dead-letter queue.
+///
+/// Created with [`RedisBackendBuilder::build_admin`].
+///
+/// [`RedisBackendBuilder::build_admin`]: super::RedisBackendBuilder::build_admin
+pub struct RedisAdmin<M: ManageConnection> {
+    pub(super) redis: bb8::Pool<M>,
+    pub(super) queue_key: String,
+    pub(super) delayed_queue_key: String,
+    pub(super) processing_queue_key: String,
+    pub(super) consumer_group: String,
+    pub(super) use_redis_streams: bool,
+}
+
+impl<R: RedisConnection> RedisAdmin<R> {
+    /// Creates the stream and its consumer group, unless they already exist.
+    pub async fn create_queue(&self) -> Result<()> {
+        if !self.use_redis_streams {
+            return Ok(());
+        }
+
+        let mut conn = self
+            .redis
+            .get()
+            .await
+            .map_err(redis_error("create_queue"))?;
+        let res: RedisResult<()> = conn
+            .xgroup_create_mkstream(&self.queue_key, &self.consumer_group, 0i8)
+            .await;
+        match res {
+            Err(e) if e.code() != Some("BUSYGROUP") => Err(redis_error("create_queue")(e)),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn delete_queue(&self) -> Result<()> {
+        let mut conn = self
+            .redis
+            .get()
+            .await
+            .map_err(redis_error("delete_queue"))?;
+
+        let mut keys = vec![&self.queue_key];
+        if !self.use_redis_streams {
+            keys.push(&self.processing_queue_key);
+        }
+        if !self.delayed_queue_key.is_empty() {
+            keys.push(&self.delayed_queue_key);
+        }
+        // Delete the keys one at a time, since they may live on different
+        // nodes of a cluster.
+        for key in keys {
+            let _: () = conn.del(key).await.map_err(redis_error("delete_queue"))?;
+        }
+        Ok(())
+    }
+
+    /// Deletes all messages from the queue, including delayed messages.
+    ///
+    /// With redis streams, this trims the entries added up to now from the
+    /// stream and acknowledges those that were delivered but not yet acked,
+    /// keeping the stream and its consumer group. Messages sent while this
+    /// runs are kept, so consumers can keep receiving while this happens.
+    pub async fn purge(&self) -> Result<()> {
+        if !self.use_redis_streams {
+            return self.delete_queue().await;
+        }
+
+        let mut conn = self.redis.get().await.map_err(redis_error("purge"))?;
+        let info: HashMap<String, redis::Value> = conn
+            .xinfo_stream(&self.queue_key)
+            .await
+            .map_err(redis_error("purge"))?;
+        let last_id: String = info
+            .get("last-generated-id")
+            .map(redis::from_redis_value)
+            .transpose()
+            .map_err(redis_error("purge"))?
+            .ok_or_else(|| QueueError::Generic("missing last-generated-id".into()))?;
+
+        // MINID removes the entries with lower IDs, so trim up to the one
+        // after the last generated ID
+        let _: () = conn
+            .xtrim_options(
+                &self.queue_key,
+                &StreamTrimOptions::minid(StreamTrimmingMode::Exact, next_stream_id(&last_id)?),
+            )
+            .await
+            .map_err(redis_error("purge"))?;
+
+        // Trimming doesn't remove the entries from the pending entries list of
+        // the consumer group
+        loop {
+            let pending: StreamPendingCountReply = conn
+                .xpending_count(
+                    &self.queue_key,
+                    &self.consumer_group,
+                    "-",
+                    &last_id,
+                    PURGE_ACK_BATCH_SIZE,
+                )
+                .await
+                .map_err(redis_error("purge"))?;
+            if pending.ids.is_empty() {
+                break;
+            }
+
+            let ids: Vec<_> = pending.ids.iter().map(|id| &id.id).collect();
+            let _: () = conn
+                .xack(&self.queue_key, &self.consumer_group, &ids)
+                .await
+                .map_err(redis_error("purge"))?;
+        }
+
+        if !self.delayed_queue_key.is_empty() {
+            let _: () = conn
+                .del(&self.delayed_queue_key)
+                .await
+                .map_err(redis_error("purge"))?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the stream and its consumer group exist.
+    ///
+    /// Returns [`QueueError::Unsupported`] with the fallback implementation,
+    /// whose lists only exist while they hold messages.
+    pub async fn exists(&self) -> Result<bool> {
+        if !self.use_redis_streams {
+            return Err(QueueError::Unsupported(
+                "exists is not supported by redis fallback backend",
+            ));
+        }
+
+        let mut conn = self.redis.get().await.map_err(redis_error("exists"))?;
+        let exists: bool = conn
+            .exists(&self.queue_key)
+            .await
+            .map_err(redis_error("exists"))?;
+        if !exists {
+            return Ok(false);
+        }
+
+        let info: StreamInfoGroupsReply = conn
+            .xinfo_groups(&self.queue_key)
+            .await
+            .map_err(redis_error("exists"))?;
+        Ok(info
+            .groups
+            .iter()
+            .any(|group| group.name == self.consumer_group))
+    }
+}
+
+/// Returns the smallest stream ID that is greater than `id`.
+fn next_stream_id(id: &str) -> Result<String> {
+    let invalid = || QueueError::Generic(format!("invalid stream ID: {id}").into());
+    let (ms, seq) = id.split_once('-').ok_or_else(invalid)?;
+    let ms: u64 = ms.parse().map_err(|_| invalid())?;
+    let seq: u64 = seq.parse().map_err(|_| invalid())?;
+    Ok(match seq.checked_add(1) {
+        Some(seq) => format!("{ms}-{seq}"),
+        None => format!("{}-0", ms + 1),
+    })
+}
+
+impl<R: RedisConnection> QueueAdmin for RedisAdmin<R> {
+    omni_delegate!(create_queue, delete_queue, purge, exists);
+}
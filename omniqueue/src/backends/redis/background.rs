@@ -0,0 +1,260 @@
+//! Supervision of the background tasks that move delayed messages to the main
+//! queue and re-enqueue messages whose ack deadline has passed.
+
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    pin::pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use futures_util::future::select;
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinSet,
+};
+use tracing::error;
+
+use crate::{metrics::QueueMetrics, QueueError, Result};
+
+/// The default for how long the background tasks wait before polling again
+/// when there was nothing to do, or after a failed iteration.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The shortest poll interval of the background tasks. Shorter intervals are
+/// raised to this, so an idle queue isn't polled in a busy loop.
+pub(super) const MIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The default maximum number of pending messages to reinsert into the queue
+/// after becoming stale per iteration.
+const DEFAULT_PENDING_BATCH_SIZE: usize = 1000;
+
+/// Settings of the background tasks, shared by [`RedisBackendBuilder`] and
+/// [`RedisMaintenanceBuilder`].
+///
+/// [`RedisBackendBuilder`]: super::RedisBackendBuilder
+/// [`RedisMaintenanceBuilder`]: super::RedisMaintenanceBuilder
+#[derive(Clone)]
+pub(super) struct BackgroundConfig {
+    pub(super) delayed_poll_interval: Duration,
+    pub(super) reenqueue_poll_interval: Duration,
+    pub(super) pending_batch_size: NonZeroUsize,
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            delayed_poll_interval: DEFAULT_POLL_INTERVAL,
+            reenqueue_poll_interval: DEFAULT_POLL_INTERVAL,
+            pending_batch_size: NonZeroUsize::new(DEFAULT_PENDING_BATCH_SIZE).unwrap(),
+        }
+    }
+}
+
+/// The status of the background tasks of a redis queue client, as returned by
+/// [`RedisProducer::background_status`] and
+/// [`RedisConsumer::background_status`].
+///
+/// [`RedisProducer::background_status`]: super::RedisProducer::background_status
+/// [`RedisConsumer::background_status`]: super::RedisConsumer::background_status
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BackgroundStatus {
+    /// The task that moves messages from the delayed queue to the main queue
+    /// once they are due.
+    ///
+    /// `None` if no delayed queue is configured, or if background tasks are
+    /// disabled.
+    pub delayed_mover: Option<BackgroundTaskStatus>,
+    /// The task that puts messages whose ack deadline has passed back on the
+    /// main queue, or moves them to the dead-letter queue.
+    ///
+    /// `None` if background tasks are disabled.
+    pub reenqueuer: Option<BackgroundTaskStatus>,
+}
+
+/// The status of a single background task.
+///
+/// A task that is running, but hasn't had a successful iteration for a while
+/// (see [`last_success`](Self::last_success)), is likely stuck.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BackgroundTaskStatus {
+    /// Whether the task is still running. Tasks only stop after a shutdown,
+    /// or if they panic.
+    pub running: bool,
+    /// When the task last completed an iteration successfully.
+    pub last_success: Option<SystemTime>,
+    /// The number of iterations that failed since the last successful one.
+    pub consecutive_failures: u32,
+    /// The error of the last failed iteration.
+    pub last_error: Option<String>,
+}
+
+/// Which of the fields of [`BackgroundStatus`] a task reports to.
+#[derive(Clone, Copy)]
+pub(super) enum TaskKind {
+    DelayedMover,
+    Reenqueuer,
+}
+
+impl TaskKind {
+    fn status_mut(self, status: &mut BackgroundStatus) -> &mut Option<BackgroundTaskStatus> {
+        match self {
+            Self::DelayedMover => &mut status.delayed_mover,
+            Self::Reenqueuer => &mut status.reenqueuer,
+        }
+    }
+
+    /// The operation that failures of the task are recorded under.
+    fn operation(self) -> &'static str {
+        match self {
+            Self::DelayedMover => "enqueue_delayed",
+            Self::Reenqueuer => "reenqueue_timed_out",
+        }
+    }
+}
+
+/// The background tasks shared by the producers and consumers created by one
+/// `build_*` call, or run by a [`RedisMaintenance`](super::RedisMaintenance).
+///
+/// Dropping this aborts the tasks.
+pub(super) struct BackgroundTasks {
+    tasks: Mutex<JoinSet<()>>,
+    shutdown: watch::Sender<bool>,
+    status: Arc<watch::Sender<BackgroundStatus>>,
+}
+
+impl BackgroundTasks {
+    pub(super) fn new() -> Self {
+        Self {
+            tasks: Mutex::new(JoinSet::new()),
+            shutdown: watch::Sender::new(false),
+            status: Arc::new(watch::Sender::new(BackgroundStatus::default())),
+        }
+    }
+
+    /// Spawns a task that loops until shutdown, using the given
+    /// [`BackgroundTask`] to run its iterations.
+    pub(super) fn spawn_loop<F, Fut>(
+        &mut self,
+        kind: TaskKind,
+        poll_interval: Duration,
+        metrics: QueueMetrics,
+        f: F,
+    ) where
+        F: FnOnce(BackgroundTask) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.status.send_modify(|status| {
+            *kind.status_mut(status) = Some(BackgroundTaskStatus {
+                running: true,
+                ..Default::default()
+            });
+        });
+        let task = BackgroundTask {
+            kind,
+            poll_interval,
+            shutdown: self.shutdown.subscribe(),
+            status: self.status.clone(),
+            metrics,
+        };
+        self.tasks.get_mut().spawn(f(task));
+    }
+
+    /// Spawns a task that runs once, and isn't reported in the
+    /// [`BackgroundStatus`].
+    pub(super) fn spawn(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        self.tasks.get_mut().spawn(task);
+    }
+
+    pub(super) fn status(&self) -> BackgroundStatus {
+        self.status.borrow().clone()
+    }
+
+    pub(super) fn watch_status(&self) -> watch::Receiver<BackgroundStatus> {
+        self.status.subscribe()
+    }
+
+    /// Signals the tasks to stop, and waits for them to finish their current
+    /// iteration.
+    pub(super) async fn shutdown(&self) -> Result<()> {
+        self.shutdown.send_replace(true);
+
+        let mut tasks = self.tasks.lock().await;
+        let mut result = Ok(());
+        while let Some(res) = tasks.join_next().await {
+            if let Err(e) = res {
+                error!("redis background task failed: {e}");
+                result = Err(QueueError::generic(e));
+            }
+        }
+        result
+    }
+}
+
+/// The handle a background task uses to check for shutdown and to report on
+/// its iterations.
+///
+/// Marks the task as stopped when dropped, which includes the task panicking.
+pub(super) struct BackgroundTask {
+    kind: TaskKind,
+    poll_interval: Duration,
+    shutdown: watch::Receiver<bool>,
+    status: Arc<watch::Sender<BackgroundStatus>>,
+    metrics: QueueMetrics,
+}
+
+impl BackgroundTask {
+    pub(super) fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Records the result of an iteration, then waits before the next one
+    /// unless the iteration found work to do, in which case there may be
+    /// more.
+    ///
+    /// Waiting ends early on shutdown.
+    pub(super) async fn after_iteration(&mut self, result: Result<bool>) {
+        let found_work = match result {
+            Ok(found_work) => {
+                self.update(|status| {
+                    status.last_success = Some(SystemTime::now());
+                    status.consecutive_failures = 0;
+                });
+                found_work
+            }
+            Err(err) => {
+                error!("{err}");
+                self.metrics.error(self.kind.operation(), &err);
+                self.update(|status| {
+                    status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+                    status.last_error = Some(err.to_string());
+                });
+                false
+            }
+        };
+
+        if !found_work {
+            // The shutdown flag only ever changes to `true`.
+            let sleep = pin!(tokio::time::sleep(self.poll_interval));
+            let shutdown = pin!(self.shutdown.changed());
+            select(sleep, shutdown).await;
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut BackgroundTaskStatus)) {
+        self.status.send_modify(|status| {
+            if let Some(task_status) = self.kind.status_mut(status) {
+                f(task_status);
+            }
+        });
+    }
+}
+
+impl Drop for BackgroundTask {
+    fn drop(&mut self) {
+        self.update(|status| status.running = false);
+    }
+}
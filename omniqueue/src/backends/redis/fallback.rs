@@ -1,19 +1,24 @@
 //! Implementation of the main queue using two lists instead of redis streams,
 //! for compatibility with redis versions older than 6.2.0.
 
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use bb8::ManageConnection;
 use redis::AsyncCommands;
-use svix_ksuid::{KsuidLike as _, KsuidMs};
+use svix_ksuid::{Ksuid, KsuidLike as _, KsuidMs};
 use time::OffsetDateTime;
-use tracing::{error, trace, warn};
+use tracing::{trace, warn};
 
 use super::{
-    internal_from_list, internal_to_list_payload, DeadLetterQueueConfig, InternalPayload,
-    InternalPayloadOwned, RawPayload, RedisConnection, RedisConsumer, RedisProducer,
+    background::BackgroundTask, internal_from_list, internal_to_list_payload, redis_error,
+    unix_timestamp, DeadLetterQueueConfig, InternalPayload, InternalPayloadOwned, RawPayload,
+    RedisConnection, RedisConsumer, RedisProducer,
+};
+use crate::{
+    metrics::QueueMetrics,
+    queue::{Acker, PeekAcker},
+    Delivery, DeliveryMetadata, QueueError, Result,
 };
-use crate::{queue::Acker, Delivery, QueueError, Result};
 
 pub(super) async fn send_raw<R: RedisConnection>(
     producer: &RedisProducer<R>,
@@ -23,13 +28,13 @@ pub(super) async fn send_raw<R: RedisConnection>(
         .redis
         .get()
         .await
-        .map_err(QueueError::generic)?
+        .map_err(redis_error("send"))?
         .lpush(
             &producer.queue_key,
             internal_to_list_payload(InternalPayload::new(payload)),
         )
         .await
-        .map_err(QueueError::generic)
+        .map_err(redis_error("send"))
 }
 
 pub(super) async fn receive<R: RedisConnection>(consumer: &RedisConsumer<R>) -> Result<Delivery> {
@@ -55,7 +60,7 @@ async fn receive_with_timeout<R: RedisConnection>(
         .redis
         .get()
         .await
-        .map_err(QueueError::generic)?
+        .map_err(redis_error("receive"))?
         .brpoplpush(
             &consumer.queue_key,
             &consumer.processing_queue_key,
@@ -65,7 +70,7 @@ async fn receive_with_timeout<R: RedisConnection>(
             timeout.as_secs_f64(),
         )
         .await
-        .map_err(QueueError::generic)?;
+        .map_err(redis_error("receive"))?;
 
     match payload {
         Some(old_payload) => Some(internal_to_delivery(
@@ -78,30 +83,93 @@ async fn receive_with_timeout<R: RedisConnection>(
     }
 }
 
+/// Returns the first `max_messages` entries of the main queue, which are at
+/// the end of the list.
+pub(super) async fn peek<R: RedisConnection>(
+    consumer: &RedisConsumer<R>,
+    max_messages: usize,
+) -> Result<Vec<Delivery>> {
+    if max_messages == 0 {
+        return Ok(Vec::new());
+    }
+    let max_messages = isize::try_from(max_messages).unwrap_or(isize::MAX);
+
+    let entries: Vec<Vec<u8>> = consumer
+        .redis
+        .get()
+        .await
+        .map_err(redis_error("peek"))?
+        .lrange(&consumer.queue_key, -max_messages, -1)
+        .await
+        .map_err(redis_error("peek"))?;
+
+    entries
+        .iter()
+        .rev()
+        .map(|entry| {
+            let InternalPayload {
+                payload,
+                num_receives,
+                ..
+            } = internal_from_list(entry)?;
+            // `internal_from_list` counts the receive that is about to
+            // happen, which peeking doesn't do.
+            let metadata = list_payload_metadata(entry, num_receives - 1);
+            Ok(Delivery::new(payload.to_vec(), PeekAcker).with_metadata(metadata))
+        })
+        .collect()
+}
+
 fn internal_to_delivery<R: RedisConnection>(
     InternalPayloadOwned {
         payload,
         num_receives,
+        ..
     }: InternalPayloadOwned,
     consumer: &RedisConsumer<R>,
     old_payload: Vec<u8>,
 ) -> Result<Delivery> {
+    let metadata = list_payload_metadata(&old_payload, num_receives);
     Ok(Delivery::new(
         payload,
         RedisFallbackAcker {
             redis: consumer.redis.clone(),
             processing_queue_key: consumer.processing_queue_key.clone(),
+            delayed_queue_key: consumer.delayed_queue_key.clone(),
             old_payload,
             already_acked_or_nacked: false,
             num_receives,
             dlq_config: consumer.dlq_config.clone(),
+            metrics: consumer.metrics.clone(),
         },
-    ))
+    )
+    .with_metadata(metadata))
+}
+
+/// Extracts the KSUID prefix of a list entry as the message ID.
+///
+/// Note that the KSUID is regenerated whenever an entry is put back on the
+/// queue, so both it and the derived enqueue time refer to the latest
+/// (re)insertion rather than the original send.
+fn list_payload_metadata(old_payload: &[u8], num_receives: usize) -> DeliveryMetadata {
+    let id = old_payload
+        .iter()
+        .position(|&byte| byte == b'#' || byte == b'|')
+        .and_then(|id_end_pos| std::str::from_utf8(&old_payload[..id_end_pos]).ok());
+
+    DeliveryMetadata {
+        message_id: id.map(ToOwned::to_owned),
+        receive_count: num_receives.try_into().ok(),
+        enqueued_at: id
+            .and_then(|id| Ksuid::from_base62(id).ok())
+            .map(|ksuid| ksuid.timestamp()),
+    }
 }
 
 struct RedisFallbackAcker<M: ManageConnection> {
     redis: bb8::Pool<M>,
     processing_queue_key: String,
+    delayed_queue_key: String,
     // We delete based on the payload -- and since the
     // `num_receives` changes after receiving it's the
     // `old_payload`, since `num_receives` is part of the
@@ -112,6 +180,7 @@ struct RedisFallbackAcker<M: ManageConnection> {
 
     num_receives: usize,
     dlq_config: Option<DeadLetterQueueConfig>,
+    metrics: QueueMetrics,
 }
 
 impl<R: RedisConnection> Acker for RedisFallbackAcker<R> {
@@ -124,10 +193,10 @@ impl<R: RedisConnection> Acker for RedisFallbackAcker<R> {
             .redis
             .get()
             .await
-            .map_err(QueueError::generic)?
+            .map_err(redis_error("ack"))?
             .lrem(&self.processing_queue_key, 1, &self.old_payload)
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(redis_error("ack"))?;
 
         self.already_acked_or_nacked = true;
 
@@ -148,7 +217,7 @@ impl<R: RedisConnection> Acker for RedisFallbackAcker<R> {
                         &self.old_payload
                     }
                 };
-                send_to_dlq(&self.redis, dlq_config, payload).await?;
+                send_to_dlq(&self.redis, dlq_config, payload, &self.metrics).await?;
                 return self.ack().await;
             }
         }
@@ -162,6 +231,52 @@ impl<R: RedisConnection> Acker for RedisFallbackAcker<R> {
         Ok(())
     }
 
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        if let Some(dlq_config) = &self.dlq_config {
+            if dlq_config.max_retries_reached(self.num_receives) {
+                return self.nack().await;
+            }
+        }
+
+        if self.already_acked_or_nacked {
+            return Err(QueueError::CannotAckOrNackTwice);
+        }
+        if self.delayed_queue_key.is_empty() {
+            return Err(QueueError::Unsupported(
+                "nack_with_delay requires a delayed_queue_key",
+            ));
+        }
+
+        let InternalPayload { payload, .. } = internal_from_list(&self.old_payload)?;
+        let timestamp = unix_timestamp(SystemTime::now() + delay).map_err(QueueError::generic)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .zadd(
+                &self.delayed_queue_key,
+                internal_to_list_payload(InternalPayload {
+                    num_receives: self.num_receives,
+                    ..InternalPayload::new(payload)
+                }),
+                timestamp,
+            )
+            .lrem(&self.processing_queue_key, 1, &self.old_payload);
+
+        let mut conn = self
+            .redis
+            .get()
+            .await
+            .map_err(redis_error("nack_with_delay"))?;
+        let _: () = pipe
+            .query_async(&mut *conn)
+            .await
+            .map_err(redis_error("nack_with_delay"))?;
+
+        self.already_acked_or_nacked = true;
+
+        Ok(())
+    }
+
     async fn set_ack_deadline(&mut self, _duration: Duration) -> Result<()> {
         Err(QueueError::Unsupported(
             "set_ack_deadline is not yet supported by redis fallback backend",
@@ -174,44 +289,42 @@ pub(super) async fn add_to_main_queue(
     main_queue_name: &str,
     conn: &mut impl AsyncCommands,
 ) -> Result<()> {
-    // We don't care about existing `num_receives`
-    // since we're pushing onto a different queue.
+    // Keep `num_receives`, so messages that were nacked with a delay still
+    // end up in the DLQ after the configured number of receives.
     let new_keys = keys
         .into_iter()
-        // So reset it to avoid carrying state over:
-        .map(|x| InternalPayload::new(x.payload))
         .map(internal_to_list_payload)
         .collect::<Vec<_>>();
     let _: () = conn
         .lpush(main_queue_name, new_keys)
         .await
-        .map_err(QueueError::generic)?;
+        .map_err(redis_error("enqueue_delayed"))?;
     Ok(())
 }
 
 pub(super) async fn background_task_processing<R: RedisConnection>(
+    mut task: BackgroundTask,
     pool: bb8::Pool<R>,
     queue_key: String,
     processing_queue_key: String,
     ack_deadline_ms: i64,
     dlq_config: Option<DeadLetterQueueConfig>,
-) -> Result<()> {
+    metrics: QueueMetrics,
+) {
     // FIXME: ack_deadline_ms should be unsigned
     let ack_deadline = Duration::from_millis(ack_deadline_ms as _);
-    loop {
-        if let Err(err) = reenqueue_timed_out_messages(
+    while !task.is_shutting_down() {
+        let result = reenqueue_timed_out_messages(
             &pool,
             &queue_key,
             &processing_queue_key,
             ack_deadline,
             &dlq_config,
+            &metrics,
         )
         .await
-        {
-            error!("{err}");
-            tokio::time::sleep(Duration::from_millis(500)).await;
-            continue;
-        }
+        .map_err(QueueError::Generic);
+        task.after_iteration(result).await;
     }
 }
 
@@ -219,27 +332,31 @@ async fn send_to_dlq<R: RedisConnection>(
     redis: &bb8::Pool<R>,
     dlq_config: &DeadLetterQueueConfig,
     payload: &[u8],
+    metrics: &QueueMetrics,
 ) -> Result<()> {
     let DeadLetterQueueConfig { queue_key: dlq, .. } = dlq_config;
 
     let _: () = redis
         .get()
         .await
-        .map_err(QueueError::generic)?
+        .map_err(redis_error("send_to_dlq"))?
         .rpush(dlq, payload)
         .await
-        .map_err(QueueError::generic)?;
+        .map_err(redis_error("send_to_dlq"))?;
 
+    metrics.dead_lettered(1);
     Ok(())
 }
 
+/// Returns whether any timed out messages were found.
 async fn reenqueue_timed_out_messages<R: RedisConnection>(
     pool: &bb8::Pool<R>,
     queue_key: &str,
     processing_queue_key: &str,
     ack_deadline: Duration,
     dlq_config: &Option<DeadLetterQueueConfig>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    metrics: &QueueMetrics,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
     const BATCH_SIZE: isize = 50;
 
     let mut conn = pool.get().await?;
@@ -264,7 +381,7 @@ async fn reenqueue_timed_out_messages<R: RedisConnection>(
                             num_receives = num_receives,
                             "Maximum attempts reached for message, moving item to DLQ",
                         );
-                        send_to_dlq(pool, dlq_config, internal.payload).await?;
+                        send_to_dlq(pool, dlq_config, internal.payload, metrics).await?;
                     }
                     _ => {
                         trace!(
@@ -281,10 +398,9 @@ async fn reenqueue_timed_out_messages<R: RedisConnection>(
                 let _: () = conn.lrem(processing_queue_key, 1, &key).await?;
             }
         }
+
+        Ok(true)
     } else {
-        // Sleep before attempting to fetch again if nothing was found
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        Ok(false)
     }
-
-    Ok(())
 }
@@ -0,0 +1,182 @@
+//! A standalone runner for the background tasks of a redis queue.
+
+use std::{marker::PhantomData, num::NonZeroUsize, time::Duration};
+
+use tokio::sync::watch;
+
+#[cfg(feature = "redis_cluster")]
+use super::RedisClusterConnectionManager;
+#[cfg(feature = "redis_sentinel")]
+use super::RedisSentinelConnectionManager;
+use super::{
+    background::{BackgroundConfig, BackgroundTasks, MIN_POLL_INTERVAL},
+    connect, processing_queue_key, start_background_tasks, BackgroundStatus, RedisConfig,
+    RedisConnection, RedisConnectionManager,
+};
+use crate::{metrics::QueueMetrics, Result};
+
+/// Runs the background tasks of a redis queue, which move delayed messages to
+/// the main queue and re-enqueue messages whose ack deadline has passed.
+///
+/// By default, every producer and consumer runs these tasks itself. If you
+/// have many of them, disable that with
+/// <code>[`RedisBackendBuilder::background_tasks`](super::RedisBackendBuilder::background_tasks)(false)</code>
+/// and run a single `RedisMaintenance` per queue instead.
+///
+/// Dropping this stops the tasks without waiting for their current
+/// iteration; use [`shutdown`](Self::shutdown) to stop them cleanly.
+pub struct RedisMaintenance {
+    tasks: BackgroundTasks,
+}
+
+impl RedisMaintenance {
+    /// Creates a new builder for running the background tasks of the queue
+    /// with the given configuration.
+    pub fn builder(config: RedisConfig) -> RedisMaintenanceBuilder {
+        RedisMaintenanceBuilder::new(config)
+    }
+
+    #[cfg(feature = "redis_cluster")]
+    /// Creates a new builder for running the background tasks of the redis
+    /// cluster queue with the given configuration.
+    pub fn cluster_builder(
+        config: RedisConfig,
+    ) -> RedisMaintenanceBuilder<RedisClusterConnectionManager> {
+        RedisMaintenanceBuilder::new(config)
+    }
+
+    #[cfg(feature = "redis_sentinel")]
+    /// Creates a new builder for running the background tasks of the redis
+    /// sentinel queue with the given configuration.
+    pub fn sentinel_builder(
+        config: RedisConfig,
+    ) -> RedisMaintenanceBuilder<RedisSentinelConnectionManager> {
+        RedisMaintenanceBuilder::new(config)
+    }
+
+    /// Returns the current status of the background tasks.
+    pub fn status(&self) -> BackgroundStatus {
+        self.tasks.status()
+    }
+
+    /// Returns a receiver that is notified whenever the status of the
+    /// background tasks changes.
+    pub fn watch_status(&self) -> watch::Receiver<BackgroundStatus> {
+        self.tasks.watch_status()
+    }
+
+    /// Stops the background tasks, waiting for them to finish their current
+    /// iteration.
+    ///
+    /// Returns an error if any of the tasks panicked.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.tasks.shutdown().await
+    }
+}
+
+/// A builder for [`RedisMaintenance`].
+///
+/// The settings that also exist on
+/// [`RedisBackendBuilder`](super::RedisBackendBuilder) must match those of the
+/// queue's producers and consumers. Use
+/// [`RedisBackendBuilder::maintenance`](super::RedisBackendBuilder::maintenance)
+/// to copy them over.
+pub struct RedisMaintenanceBuilder<R = RedisConnectionManager> {
+    pub(super) config: RedisConfig,
+    pub(super) use_redis_streams: bool,
+    pub(super) processing_queue_key: Option<String>,
+    pub(super) background_config: BackgroundConfig,
+    pub(super) _phantom: PhantomData<fn() -> R>,
+}
+
+impl<R: RedisConnection> RedisMaintenanceBuilder<R> {
+    pub fn new(config: RedisConfig) -> Self {
+        Self {
+            config,
+            use_redis_streams: true,
+            processing_queue_key: None,
+            background_config: BackgroundConfig::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Set a custom [`RedisConnection`] manager to use.
+    ///
+    /// See [`RedisBackendBuilder::connection_manager`](super::RedisBackendBuilder::connection_manager).
+    pub fn connection_manager<R2>(self) -> RedisMaintenanceBuilder<R2> {
+        RedisMaintenanceBuilder {
+            config: self.config,
+            use_redis_streams: self.use_redis_streams,
+            processing_queue_key: self.processing_queue_key,
+            background_config: self.background_config,
+            _phantom: PhantomData,
+        }
+    }
+
+    #[cfg(feature = "redis_cluster")]
+    pub fn cluster(self) -> RedisMaintenanceBuilder<RedisClusterConnectionManager> {
+        self.connection_manager()
+    }
+
+    /// Whether the queue uses redis streams.
+    ///
+    /// See [`RedisBackendBuilder::use_redis_streams`](super::RedisBackendBuilder::use_redis_streams).
+    pub fn use_redis_streams(mut self, value: bool) -> Self {
+        self.use_redis_streams = value;
+        self
+    }
+
+    /// Set a custom redis key for the processing queue.
+    ///
+    /// See [`RedisBackendBuilder::processing_queue_key`](super::RedisBackendBuilder::processing_queue_key).
+    pub fn processing_queue_key(mut self, value: String) -> Self {
+        self.processing_queue_key = Some(value);
+        self
+    }
+
+    /// How long the task that moves delayed messages to the main queue waits
+    /// before polling again when no messages were due.
+    ///
+    /// Default: 500ms.\
+    /// Minimum: 10ms, shorter intervals are raised to this.
+    pub fn delayed_poll_interval(mut self, value: Duration) -> Self {
+        self.background_config.delayed_poll_interval = value.max(MIN_POLL_INTERVAL);
+        self
+    }
+
+    /// How long the task that re-enqueues messages whose ack deadline has
+    /// passed waits before polling again when there were no such messages.
+    ///
+    /// Default: 500ms.\
+    /// Minimum: 10ms, shorter intervals are raised to this.
+    pub fn reenqueue_poll_interval(mut self, value: Duration) -> Self {
+        self.background_config.reenqueue_poll_interval = value.max(MIN_POLL_INTERVAL);
+        self
+    }
+
+    /// The maximum number of messages whose ack deadline has passed to
+    /// re-enqueue per poll.
+    ///
+    /// Default: 1000.\
+    /// Only used with redis streams.
+    pub fn pending_batch_size(mut self, value: NonZeroUsize) -> Self {
+        self.background_config.pending_batch_size = value;
+        self
+    }
+
+    /// Connects to redis and starts the background tasks.
+    pub async fn start(self) -> Result<RedisMaintenance> {
+        let redis = connect::<R>(&self.config).await?;
+        let metrics = QueueMetrics::new("redis", &self.config.queue_key);
+        let tasks = start_background_tasks(
+            redis,
+            &self.config,
+            self.use_redis_streams,
+            processing_queue_key(&self.config, &self.processing_queue_key),
+            &self.background_config,
+            metrics,
+        );
+
+        Ok(RedisMaintenance { tasks })
+    }
+}
@@ -33,7 +33,9 @@
 #![allow(clippy::let_unit_value)]
 
 use std::{
+    borrow::Cow,
     marker::PhantomData,
+    num::NonZeroUsize,
     str,
     sync::Arc,
     time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH},
@@ -43,32 +45,80 @@ use bb8::ManageConnection;
 pub use bb8_redis::RedisConnectionManager;
 #[cfg(feature = "redis_sentinel")]
 use redis::{sentinel::SentinelNodeConnectionInfo, ProtocolVersion, RedisConnectionInfo, TlsMode};
-use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use redis::{
+    streams::StreamPendingReply, AsyncCommands, ExistenceCheck, RedisError, SetExpiry, SetOptions,
+};
 use serde::Serialize;
 use svix_ksuid::KsuidLike;
 use thiserror::Error;
-use tokio::task::JoinSet;
-use tracing::{debug, error, info, trace, warn};
+use tokio::sync::watch;
+use tracing::{debug, info, trace, warn};
 
 #[allow(deprecated)]
 use crate::{
     builder::{Dynamic, Static},
-    queue::{Delivery, QueueBackend},
-    DynConsumer, DynProducer, QueueConsumer as _, QueueError, QueueProducer as _, Result,
+    metrics::QueueMetrics,
+    queue::{Delivery, PeekAcker, QueueBackend},
+    Attributes, Capabilities, DynConsumer, DynProducer, DynScheduledProducer, ErrorKind,
+    MessageOrdering, QueueConsumer as _, QueueError, QueueProducer as _, QueueStats, Result,
+    ScheduledQueueProducer as _,
 };
 
+mod admin;
+mod background;
 #[cfg(feature = "redis_cluster")]
 mod cluster;
 mod fallback;
+mod maintenance;
 #[cfg(feature = "redis_sentinel")]
 mod sentinel;
 mod streams;
 
+pub use admin::RedisAdmin;
+use background::{BackgroundConfig, BackgroundTasks, TaskKind, MIN_POLL_INTERVAL};
+pub use background::{BackgroundStatus, BackgroundTaskStatus};
 #[cfg(feature = "redis_cluster")]
 pub use cluster::RedisClusterConnectionManager;
+pub use maintenance::{RedisMaintenance, RedisMaintenanceBuilder};
 #[cfg(feature = "redis_sentinel")]
 pub use sentinel::RedisSentinelConnectionManager;
 
+/// Wraps an error from redis or the connection pool as a
+/// [`QueueError::Backend`] for the given operation.
+fn redis_error<E: std::error::Error + Send + Sync + 'static>(
+    operation: &'static str,
+) -> impl FnOnce(E) -> QueueError {
+    move |e| QueueError::backend(redis_error_kind(&e), "redis", operation, e)
+}
+
+fn redis_error_kind(e: &(dyn std::error::Error + 'static)) -> ErrorKind {
+    // All of our connection managers use `RedisError` as their error type.
+    let e = match e.downcast_ref::<bb8::RunError<RedisError>>() {
+        Some(bb8::RunError::TimedOut) => return ErrorKind::Timeout,
+        Some(bb8::RunError::User(e)) => e,
+        None => match e.downcast_ref::<RedisError>() {
+            Some(e) => e,
+            None => return ErrorKind::Other,
+        },
+    };
+
+    if e.is_timeout() {
+        ErrorKind::Timeout
+    } else if e.is_connection_dropped() || e.is_connection_refusal() || e.is_io_error() {
+        ErrorKind::ConnectionLost
+    } else {
+        match e.kind() {
+            redis::ErrorKind::AuthenticationFailed => ErrorKind::AuthFailure,
+            redis::ErrorKind::BusyLoadingError
+            | redis::ErrorKind::TryAgain
+            | redis::ErrorKind::ClusterDown
+            | redis::ErrorKind::MasterDown
+            | redis::ErrorKind::ClusterConnectionNotFound => ErrorKind::ConnectionLost,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
 pub trait RedisConnection:
     ManageConnection<
     Connection: redis::aio::ConnectionLike + Send + Sync,
@@ -80,14 +130,14 @@ pub trait RedisConnection:
 
 impl RedisConnection for RedisConnectionManager {
     fn from_config(config: &RedisConfig) -> Result<Self> {
-        Self::new(config.dsn.as_str()).map_err(QueueError::generic)
+        Self::new(config.dsn.as_str()).map_err(redis_error("connect"))
     }
 }
 
 #[cfg(feature = "redis_cluster")]
 impl RedisConnection for RedisClusterConnectionManager {
     fn from_config(config: &RedisConfig) -> Result<Self> {
-        Self::new(config.dsn.as_str()).map_err(QueueError::generic)
+        Self::new(config.dsn.as_str()).map_err(redis_error("connect"))
     }
 }
 
@@ -118,16 +168,23 @@ impl RedisConnection for RedisSentinelConnectionManager {
                 }),
             }),
         )
-        .map_err(QueueError::generic)
+        .map_err(redis_error("connect"))
     }
 }
 
+/// Prefix of the stream fields that hold message attributes, keeping them
+/// apart from the payload and `num_receives` fields. Also used for the
+/// attributes of delayed messages.
+const ATTRIBUTE_PREFIX: &str = "attr:";
+
 // First element is the raw payload slice, second
 // is `num_receives`, the number of the times
 // the message has previously been received.
+// Attributes are only stored with redis streams.
 struct InternalPayload<'a> {
     payload: &'a [u8],
     num_receives: usize,
+    attributes: Cow<'a, Attributes>,
 }
 
 impl<'a> InternalPayload<'a> {
@@ -135,15 +192,18 @@ impl<'a> InternalPayload<'a> {
         Self {
             payload,
             num_receives: 0,
+            attributes: Cow::Owned(Attributes::new()),
         }
     }
 }
 
 // The same as `InternalPayload` but with an
-// owned payload.
+// owned payload, plus any message attributes.
+// Attributes are only stored with redis streams.
 struct InternalPayloadOwned {
     payload: Vec<u8>,
     num_receives: usize,
+    attributes: Attributes,
 }
 
 impl From<InternalPayload<'_>> for InternalPayloadOwned {
@@ -151,11 +211,13 @@ impl From<InternalPayload<'_>> for InternalPayloadOwned {
         InternalPayload {
             payload,
             num_receives,
+            attributes,
         }: InternalPayload,
     ) -> Self {
         Self {
             payload: payload.to_vec(),
             num_receives,
+            attributes: attributes.into_owned(),
         }
     }
 }
@@ -166,6 +228,10 @@ fn internal_from_list(payload: &[u8]) -> Result<InternalPayload<'_>> {
     // formatted task is delimited by a `|` So, take the key, then take the
     // optional receive count, then take the part after the `|` to get the
     // payload.
+    //
+    // Entries with attributes have the length of the attributes after the
+    // receive count, separated by a `@`. The attributes are stored as a JSON
+    // object of stream fields at the start of the part after the `|`.
     let count_sep_pos = payload.iter().position(|&byte| byte == b'#');
     let payload_sep_pos = payload
         .iter()
@@ -179,21 +245,48 @@ fn internal_from_list(payload: &[u8]) -> Result<InternalPayload<'_>> {
     let _id = str::from_utf8(&payload[..id_end_pos])
         .map_err(|_| QueueError::Generic("Non-UTF8 key ID".into()))?;
 
+    let improper_format = || QueueError::Generic("Improper key format".into());
+
     // This should be backward-compatible with messages that don't include
     // `num_receives`
-    let num_receives = if let Some(count_sep_pos) = count_sep_pos {
-        let num_receives = std::str::from_utf8(&payload[(count_sep_pos + 1)..payload_sep_pos])
-            .map_err(|_| QueueError::Generic("Improper key format".into()))?
+    let (num_receives, attributes_len) = if let Some(count_sep_pos) = count_sep_pos {
+        let count = std::str::from_utf8(&payload[(count_sep_pos + 1)..payload_sep_pos])
+            .map_err(|_| improper_format())?;
+        let (num_receives, attributes_len) = match count.split_once('@') {
+            Some((num_receives, len)) => {
+                (num_receives, len.parse().map_err(|_| improper_format())?)
+            }
+            None => (count, 0),
+        };
+        let num_receives = num_receives
             .parse::<usize>()
-            .map_err(|_| QueueError::Generic("Improper key format".into()))?;
-        num_receives + 1
+            .map_err(|_| improper_format())?;
+        (num_receives + 1, attributes_len)
+    } else {
+        (1, 0)
+    };
+
+    let rest = &payload[payload_sep_pos + 1..];
+    if rest.len() < attributes_len {
+        return Err(improper_format());
+    }
+    let (attributes, payload) = rest.split_at(attributes_len);
+    let attributes = if attributes.is_empty() {
+        Attributes::new()
     } else {
-        1
+        let fields: Attributes = serde_json::from_slice(attributes)?;
+        fields
+            .into_iter()
+            .filter_map(|(key, value)| {
+                Some((key.strip_prefix(ATTRIBUTE_PREFIX)?.to_owned(), value))
+            })
+            .collect()
     };
 
     Ok(InternalPayload {
-        payload: &payload[payload_sep_pos + 1..],
+        payload,
         num_receives,
+        attributes: Cow::Owned(attributes),
     })
 }
 
@@ -201,15 +294,35 @@ fn internal_to_list_payload(
     InternalPayload {
         payload,
         num_receives,
+        attributes,
     }: InternalPayload,
 ) -> Vec<u8> {
     let id = delayed_key_id();
     let num_receives = num_receives.to_string();
-    let mut result = Vec::with_capacity(id.len() + num_receives.len() + payload.len() + 3);
+    // Entries without attributes keep the format that older versions read
+    let attributes = if attributes.is_empty() {
+        Vec::new()
+    } else {
+        let fields: Attributes = attributes
+            .iter()
+            .map(|(key, value)| (format!("{ATTRIBUTE_PREFIX}{key}"), value.clone()))
+            .collect();
+        serde_json::to_vec(&fields).expect("serializing a string map can't fail")
+    };
+    let attributes_len = match attributes.len() {
+        0 => String::new(),
+        len => format!("@{len}"),
+    };
+
+    let mut result = Vec::with_capacity(
+        id.len() + num_receives.len() + attributes_len.len() + attributes.len() + payload.len() + 3,
+    );
     result.extend(id.as_bytes());
     result.push(b'#');
     result.extend(num_receives.as_bytes());
+    result.extend(attributes_len.as_bytes());
     result.push(b'|');
+    result.extend(attributes);
     result.extend(payload);
     result
 }
@@ -257,6 +370,7 @@ async fn check_eviction_policy<R: RedisConnection>(
     }
 }
 
+#[derive(Clone)]
 pub struct RedisConfig {
     pub dsn: String,
     pub max_connections: u16,
@@ -352,6 +466,8 @@ pub struct RedisBackendBuilder<R = RedisConnectionManager, S = Static> {
     config: RedisConfig,
     use_redis_streams: bool,
     processing_queue_key: Option<String>,
+    background_tasks: bool,
+    background_config: BackgroundConfig,
     _phantom: PhantomData<fn() -> (R, S)>,
 }
 
@@ -367,15 +483,8 @@ impl<R: RedisConnection> RedisBackendBuilder<R> {
             config,
             use_redis_streams: true,
             processing_queue_key: None,
-            _phantom: PhantomData,
-        }
-    }
-
-    fn map_phantom<R2, S2>(self) -> RedisBackendBuilder<R2, S2> {
-        RedisBackendBuilder {
-            config: self.config,
-            use_redis_streams: self.use_redis_streams,
-            processing_queue_key: self.processing_queue_key,
+            background_tasks: true,
+            background_config: BackgroundConfig::default(),
             _phantom: PhantomData,
         }
     }
@@ -421,32 +530,89 @@ impl<R: RedisConnection> RedisBackendBuilder<R> {
         self
     }
 
+    /// Whether producers and consumers run the background tasks that move
+    /// delayed messages to the main queue and re-enqueue messages whose ack
+    /// deadline has passed.
+    ///
+    /// Default: `true`.\
+    /// Every producer and consumer runs its own copy of these tasks, which
+    /// all poll redis. If you have many of them, you can set this to `false`
+    /// and run the tasks once per queue with a [`RedisMaintenance`] instead.
+    /// Without either, delayed messages are never delivered and messages that
+    /// aren't acked are never redelivered.
+    pub fn background_tasks(mut self, value: bool) -> Self {
+        self.background_tasks = value;
+        self
+    }
+
+    /// How long the task that moves delayed messages to the main queue waits
+    /// before polling again when no messages were due.
+    ///
+    /// Default: 500ms.\
+    /// Minimum: 10ms, shorter intervals are raised to this.
+    pub fn delayed_poll_interval(mut self, value: Duration) -> Self {
+        self.background_config.delayed_poll_interval = value.max(MIN_POLL_INTERVAL);
+        self
+    }
+
+    /// How long the task that re-enqueues messages whose ack deadline has
+    /// passed waits before polling again when there were no such messages.
+    ///
+    /// Default: 500ms.\
+    /// Minimum: 10ms, shorter intervals are raised to this.
+    pub fn reenqueue_poll_interval(mut self, value: Duration) -> Self {
+        self.background_config.reenqueue_poll_interval = value.max(MIN_POLL_INTERVAL);
+        self
+    }
+
+    /// The maximum number of messages whose ack deadline has passed to
+    /// re-enqueue per poll.
+    ///
+    /// Default: 1000.\
+    /// Only used with redis streams.
+    pub fn pending_batch_size(mut self, value: NonZeroUsize) -> Self {
+        self.background_config.pending_batch_size = value;
+        self
+    }
+
+    /// Creates a [`RedisMaintenanceBuilder`] with the same settings as this
+    /// builder, to run the background tasks for the configured queue
+    /// separately from its producers and consumers.
+    pub fn maintenance(&self) -> RedisMaintenanceBuilder<R> {
+        RedisMaintenanceBuilder {
+            config: self.config.clone(),
+            use_redis_streams: self.use_redis_streams,
+            processing_queue_key: self.processing_queue_key.clone(),
+            background_config: self.background_config.clone(),
+            _phantom: PhantomData,
+        }
+    }
+
     fn get_processing_queue_key(&self) -> String {
-        self.processing_queue_key
-            .clone()
-            .unwrap_or_else(|| format!("{}_processing", self.config.queue_key))
+        processing_queue_key(&self.config, &self.processing_queue_key)
+    }
+
+    async fn connect(&self) -> Result<bb8::Pool<R>> {
+        connect(&self.config).await
     }
 
     pub async fn build_pair(self) -> Result<(RedisProducer<R>, RedisConsumer<R>)> {
-        let redis = R::from_config(&self.config)?;
-        let redis = bb8::Pool::builder()
-            .max_size(self.config.max_connections.into())
-            .build(redis)
-            .await
-            .map_err(QueueError::generic)?;
+        let redis = self.connect().await?;
 
-        let background_tasks = self.start_background_tasks(redis.clone()).await;
+        let metrics = QueueMetrics::new("redis", &self.config.queue_key);
+        let background_tasks = self.start_background_tasks(redis.clone(), metrics.clone());
         let processing_queue_key = self.get_processing_queue_key();
 
         Ok((
             RedisProducer {
                 redis: redis.clone(),
                 queue_key: self.config.queue_key.clone(),
-                delayed_queue_key: self.config.delayed_queue_key,
+                delayed_queue_key: self.config.delayed_queue_key.clone(),
                 payload_key: self.config.payload_key.clone(),
                 use_redis_streams: self.use_redis_streams,
-                _background_tasks: background_tasks.clone(),
+                background_tasks: background_tasks.clone(),
                 dlq_config: self.config.dlq_config.clone(),
+                metrics: metrics.clone(),
             },
             RedisConsumer {
                 redis,
@@ -455,42 +621,38 @@ impl<R: RedisConnection> RedisBackendBuilder<R> {
                 consumer_group: self.config.consumer_group,
                 consumer_name: self.config.consumer_name,
                 payload_key: self.config.payload_key,
+                delayed_queue_key: self.config.delayed_queue_key,
+                ack_deadline_ms: self.config.ack_deadline_ms,
                 use_redis_streams: self.use_redis_streams,
-                _background_tasks: background_tasks.clone(),
+                background_tasks: background_tasks.clone(),
                 dlq_config: self.config.dlq_config.clone(),
+                metrics,
             },
         ))
     }
 
     pub async fn build_producer(self) -> Result<RedisProducer<R>> {
-        let redis = R::from_config(&self.config)?;
-        let redis = bb8::Pool::builder()
-            .max_size(self.config.max_connections.into())
-            .build(redis)
-            .await
-            .map_err(QueueError::generic)?;
+        let redis = self.connect().await?;
 
-        let _background_tasks = self.start_background_tasks(redis.clone()).await;
+        let metrics = QueueMetrics::new("redis", &self.config.queue_key);
+        let background_tasks = self.start_background_tasks(redis.clone(), metrics.clone());
         Ok(RedisProducer {
             redis,
             queue_key: self.config.queue_key,
             delayed_queue_key: self.config.delayed_queue_key,
             payload_key: self.config.payload_key,
             use_redis_streams: self.use_redis_streams,
-            _background_tasks,
+            background_tasks,
             dlq_config: self.config.dlq_config,
+            metrics,
         })
     }
 
     pub async fn build_consumer(self) -> Result<RedisConsumer<R>> {
-        let redis = R::from_config(&self.config)?;
-        let redis = bb8::Pool::builder()
-            .max_size(self.config.max_connections.into())
-            .build(redis)
-            .await
-            .map_err(QueueError::generic)?;
+        let redis = self.connect().await?;
 
-        let _background_tasks = self.start_background_tasks(redis.clone()).await;
+        let metrics = QueueMetrics::new("redis", &self.config.queue_key);
+        let background_tasks = self.start_background_tasks(redis.clone(), metrics.clone());
         let processing_queue_key = self.get_processing_queue_key();
 
         Ok(RedisConsumer {
@@ -500,9 +662,30 @@ impl<R: RedisConnection> RedisBackendBuilder<R> {
             consumer_group: self.config.consumer_group,
             consumer_name: self.config.consumer_name,
             payload_key: self.config.payload_key,
+            delayed_queue_key: self.config.delayed_queue_key,
+            ack_deadline_ms: self.config.ack_deadline_ms,
             use_redis_streams: self.use_redis_streams,
-            _background_tasks,
+            background_tasks,
             dlq_config: self.config.dlq_config,
+            metrics,
+        })
+    }
+
+    /// Builds a [`RedisAdmin`] for the configured queue.
+    ///
+    /// Unlike producers and consumers, this doesn't start any background
+    /// tasks.
+    pub async fn build_admin(self) -> Result<RedisAdmin<R>> {
+        let redis = self.connect().await?;
+        let processing_queue_key = self.get_processing_queue_key();
+
+        Ok(RedisAdmin {
+            redis,
+            queue_key: self.config.queue_key,
+            delayed_queue_key: self.config.delayed_queue_key,
+            processing_queue_key,
+            consumer_group: self.config.consumer_group,
+            use_redis_streams: self.use_redis_streams,
         })
     }
 
@@ -510,109 +693,191 @@ impl<R: RedisConnection> RedisBackendBuilder<R> {
         self.map_phantom()
     }
 
-    // FIXME(onelson): there's a trait, `SchedulerBackend`, but no obvious way to
-    // implement it in a way that makes good sense here.
-    // We need access to the pool, and various bits of config to spawn a task, but
-    // none of that is available where it matters right now.
-    // Doing my own thing for now - standalone function that takes what it needs.
-    async fn start_background_tasks(&self, redis: bb8::Pool<R>) -> Arc<JoinSet<Result<()>>> {
-        let mut join_set = JoinSet::new();
-
-        // FIXME(onelson): does it even make sense to treat delay support as optional
-        // here?
-        if self.config.delayed_queue_key.is_empty() {
-            warn!("no delayed_queue_key specified - delayed task scheduler disabled");
-        } else {
-            join_set.spawn({
-                let pool = redis.clone();
-                let queue_key = self.config.queue_key.to_owned();
-                let delayed_queue_key = self.config.delayed_queue_key.to_owned();
-                let delayed_lock_key = self.config.delayed_lock_key.to_owned();
-                let payload_key = self.config.payload_key.to_owned();
-                let use_redis_streams = self.use_redis_streams;
-
-                #[rustfmt::skip]
-                debug!(
-                    delayed_queue_key, delayed_lock_key,
-                    "spawning delayed task scheduler"
-                );
-
-                async move {
-                    loop {
-                        if let Err(err) = background_task_delayed(
-                            &pool,
-                            &queue_key,
-                            &delayed_queue_key,
-                            &delayed_lock_key,
-                            &payload_key,
-                            use_redis_streams,
-                        )
-                        .await
-                        {
-                            error!("{err}");
-                            tokio::time::sleep(Duration::from_millis(500)).await;
-                            continue;
-                        };
-                    }
-                }
-            });
-        }
-
-        if self.use_redis_streams {
-            join_set.spawn(streams::background_task_pending(
-                redis.clone(),
-                self.config.queue_key.to_owned(),
-                self.config.consumer_group.to_owned(),
-                self.config.consumer_name.to_owned(),
-                self.config.ack_deadline_ms,
-                self.config.payload_key.to_owned(),
-                self.config.dlq_config.clone(),
-            ));
-        } else {
-            join_set.spawn(fallback::background_task_processing(
-                redis.clone(),
-                self.config.queue_key.to_owned(),
-                self.get_processing_queue_key(),
-                self.config.ack_deadline_ms,
-                self.config.dlq_config.clone(),
-            ));
+    fn start_background_tasks(
+        &self,
+        redis: bb8::Pool<R>,
+        metrics: QueueMetrics,
+    ) -> Arc<BackgroundTasks> {
+        if !self.background_tasks {
+            return Arc::new(BackgroundTasks::new());
         }
 
-        join_set.spawn({
-            async move {
-                if let Err(e) = check_eviction_policy(redis.clone()).await {
-                    tracing::warn!("{e}");
-                }
-                Ok(())
-            }
-        });
+        Arc::new(start_background_tasks(
+            redis,
+            &self.config,
+            self.use_redis_streams,
+            self.get_processing_queue_key(),
+            &self.background_config,
+            metrics,
+        ))
+    }
+}
 
-        Arc::new(join_set)
+impl<R, S> RedisBackendBuilder<R, S> {
+    fn map_phantom<R2, S2>(self) -> RedisBackendBuilder<R2, S2> {
+        RedisBackendBuilder {
+            config: self.config,
+            use_redis_streams: self.use_redis_streams,
+            processing_queue_key: self.processing_queue_key,
+            background_tasks: self.background_tasks,
+            background_config: self.background_config,
+            _phantom: PhantomData,
+        }
     }
 }
 
 impl<R: RedisConnection> RedisBackendBuilder<R, Dynamic> {
     pub async fn build_pair(self) -> Result<(DynProducer, DynConsumer)> {
-        #[allow(deprecated)]
-        let (p, c) = RedisBackend::<R>::new_pair(self.config).await?;
+        let (p, c) = self.map_phantom::<R, Static>().build_pair().await?;
         Ok((p.into_dyn(), c.into_dyn()))
     }
 
     pub async fn build_producer(self) -> Result<DynProducer> {
-        #[allow(deprecated)]
-        let p = RedisBackend::<R>::producing_half(self.config).await?;
+        let p = self.map_phantom::<R, Static>().build_producer().await?;
         Ok(p.into_dyn())
     }
 
     pub async fn build_consumer(self) -> Result<DynConsumer> {
-        #[allow(deprecated)]
-        let c = RedisBackend::<R>::consuming_half(self.config).await?;
+        let c = self.map_phantom::<R, Static>().build_consumer().await?;
         Ok(c.into_dyn())
     }
+
+    /// Like [`build_pair`](Self::build_pair), but returns a
+    /// [`DynScheduledProducer`] that can send delayed messages.
+    pub async fn build_scheduled_pair(self) -> Result<(DynScheduledProducer, DynConsumer)> {
+        let (p, c) = self.map_phantom::<R, Static>().build_pair().await?;
+        Ok((p.into_dyn_scheduled(), c.into_dyn()))
+    }
+
+    /// Like [`build_producer`](Self::build_producer), but returns a
+    /// [`DynScheduledProducer`] that can send delayed messages.
+    pub async fn build_scheduled_producer(self) -> Result<DynScheduledProducer> {
+        let p = self.map_phantom::<R, Static>().build_producer().await?;
+        Ok(p.into_dyn_scheduled())
+    }
+}
+
+fn processing_queue_key(config: &RedisConfig, custom_key: &Option<String>) -> String {
+    custom_key
+        .clone()
+        .unwrap_or_else(|| format!("{}_processing", config.queue_key))
+}
+
+async fn connect<R: RedisConnection>(config: &RedisConfig) -> Result<bb8::Pool<R>> {
+    let redis = R::from_config(config)?;
+    bb8::Pool::builder()
+        .max_size(config.max_connections.into())
+        .build(redis)
+        .await
+        .map_err(redis_error("connect"))
+}
+
+// FIXME(onelson): there's a trait, `SchedulerBackend`, but no obvious way to
+// implement it in a way that makes good sense here.
+// We need access to the pool, and various bits of config to spawn a task, but
+// none of that is available where it matters right now.
+// Doing my own thing for now - standalone function that takes what it needs.
+fn start_background_tasks<R: RedisConnection>(
+    redis: bb8::Pool<R>,
+    config: &RedisConfig,
+    use_redis_streams: bool,
+    processing_queue_key: String,
+    background_config: &BackgroundConfig,
+    metrics: QueueMetrics,
+) -> BackgroundTasks {
+    let mut tasks = BackgroundTasks::new();
+
+    // FIXME(onelson): does it even make sense to treat delay support as optional
+    // here?
+    if config.delayed_queue_key.is_empty() {
+        warn!("no delayed_queue_key specified - delayed task scheduler disabled");
+    } else {
+        let pool = redis.clone();
+        let queue_key = config.queue_key.to_owned();
+        let delayed_queue_key = config.delayed_queue_key.to_owned();
+        let delayed_lock_key = config.delayed_lock_key.to_owned();
+        let payload_key = config.payload_key.to_owned();
+
+        #[rustfmt::skip]
+        debug!(
+            delayed_queue_key, delayed_lock_key,
+            "spawning delayed task scheduler"
+        );
+
+        tasks.spawn_loop(
+            TaskKind::DelayedMover,
+            background_config.delayed_poll_interval,
+            metrics.clone(),
+            |mut task| async move {
+                while !task.is_shutting_down() {
+                    let result = background_task_delayed(
+                        &pool,
+                        &queue_key,
+                        &delayed_queue_key,
+                        &delayed_lock_key,
+                        &payload_key,
+                        use_redis_streams,
+                    )
+                    .await;
+                    task.after_iteration(result).await;
+                }
+            },
+        );
+    }
+
+    let poll_interval = background_config.reenqueue_poll_interval;
+    if use_redis_streams {
+        tasks.spawn_loop(
+            TaskKind::Reenqueuer,
+            poll_interval,
+            metrics.clone(),
+            |task| {
+                streams::background_task_pending(
+                    task,
+                    redis.clone(),
+                    config.queue_key.to_owned(),
+                    config.consumer_group.to_owned(),
+                    config.consumer_name.to_owned(),
+                    config.ack_deadline_ms,
+                    background_config.pending_batch_size,
+                    config.payload_key.to_owned(),
+                    config.dlq_config.clone(),
+                    metrics,
+                )
+            },
+        );
+    } else {
+        tasks.spawn_loop(
+            TaskKind::Reenqueuer,
+            poll_interval,
+            metrics.clone(),
+            |task| {
+                fallback::background_task_processing(
+                    task,
+                    redis.clone(),
+                    config.queue_key.to_owned(),
+                    processing_queue_key,
+                    config.ack_deadline_ms,
+                    config.dlq_config.clone(),
+                    metrics,
+                )
+            },
+        );
+    }
+
+    tasks.spawn(async move {
+        if let Err(e) = check_eviction_policy(redis.clone()).await {
+            tracing::warn!("{e}");
+        }
+    });
+
+    tasks
 }
 
 /// Moves "due" messages from a sorted set, where delayed messages are shelved,
 /// back onto the main queue.
+///
+/// Returns whether any due messages were found.
 async fn background_task_delayed<R: RedisConnection>(
     pool: &bb8::Pool<R>,
     main_queue_name: &str,
@@ -620,10 +885,10 @@ async fn background_task_delayed<R: RedisConnection>(
     delayed_lock: &str,
     payload_key: &str,
     use_redis_streams: bool,
-) -> Result<()> {
+) -> Result<bool> {
     const BATCH_SIZE: isize = 50;
 
-    let mut conn = pool.get().await.map_err(QueueError::generic)?;
+    let mut conn = pool.get().await.map_err(redis_error("enqueue_delayed"))?;
 
     // There is a lock on the delayed queue processing to avoid race conditions.
     // So first try to acquire the lock should it not already exist. The lock
@@ -640,7 +905,7 @@ async fn background_task_delayed<R: RedisConnection>(
                 .with_expiration(SetExpiry::PX(5000)),
         )
         .await
-        .map_err(QueueError::generic)?;
+        .map_err(redis_error("enqueue_delayed"))?;
 
     if resp.as_deref() == Some("OK") {
         // First look for delayed keys whose time is up and add them to the main queue
@@ -653,12 +918,19 @@ async fn background_task_delayed<R: RedisConnection>(
         let old_keys: Vec<RawPayload> = conn
             .zrangebyscore_limit(delayed_queue_name, 0, timestamp, 0, BATCH_SIZE)
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(redis_error("enqueue_delayed"))?;
 
         if !old_keys.is_empty() {
             let new_keys = old_keys
                 .iter()
-                .map(|x| internal_from_list(x))
+                .map(|x| {
+                    // `internal_from_list` counts reading the entry as a receive,
+                    // which moving it between queues isn't.
+                    internal_from_list(x).map(|internal| InternalPayload {
+                        num_receives: internal.num_receives - 1,
+                        ..internal
+                    })
+                })
                 .collect::<Result<Vec<_>>>()?;
             trace!(
                 "Moving {} messages from delayed to main queue",
@@ -676,23 +948,28 @@ async fn background_task_delayed<R: RedisConnection>(
             let _: () = conn
                 .zrem(delayed_queue_name, old_keys)
                 .await
-                .map_err(QueueError::generic)?;
+                .map_err(redis_error("enqueue_delayed"))?;
 
             // Make sure to release the lock after done processing
-            let _: () = conn.del(delayed_lock).await.map_err(QueueError::generic)?;
+            let _: () = conn
+                .del(delayed_lock)
+                .await
+                .map_err(redis_error("enqueue_delayed"))?;
+
+            Ok(true)
         } else {
             // Make sure to release the lock before sleeping
-            let _: () = conn.del(delayed_lock).await.map_err(QueueError::generic)?;
+            let _: () = conn
+                .del(delayed_lock)
+                .await
+                .map_err(redis_error("enqueue_delayed"))?;
 
-            // Wait for half a second before attempting to fetch again if nothing was found
-            tokio::time::sleep(Duration::from_millis(500)).await;
+            Ok(false)
         }
     } else {
-        // Also sleep half a second if the lock could not be fetched
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        // Also sleep if the lock could not be fetched
+        Ok(false)
     }
-
-    Ok(())
 }
 
 pub struct RedisProducer<M: ManageConnection> {
@@ -701,8 +978,9 @@ pub struct RedisProducer<M: ManageConnection> {
     delayed_queue_key: String,
     payload_key: String,
     use_redis_streams: bool,
-    _background_tasks: Arc<JoinSet<Result<()>>>,
+    background_tasks: Arc<BackgroundTasks>,
     dlq_config: Option<DeadLetterQueueConfig>,
+    metrics: QueueMetrics,
 }
 
 impl<R: RedisConnection> RedisProducer<R> {
@@ -712,11 +990,14 @@ impl<R: RedisConnection> RedisProducer<R> {
         fields(payload_size = payload.len())
     )]
     pub async fn send_raw(&self, payload: &[u8]) -> Result<()> {
-        if self.use_redis_streams {
-            streams::send_raw(self, payload).await
-        } else {
-            fallback::send_raw(self, payload).await
-        }
+        let send = async {
+            if self.use_redis_streams {
+                streams::send_raw(self, payload).await
+            } else {
+                fallback::send_raw(self, payload).await
+            }
+        };
+        self.metrics.send([payload.len()], send).await
     }
 
     pub async fn send_serde_json<P: Serialize + Sync>(&self, payload: &P) -> Result<()> {
@@ -724,6 +1005,46 @@ impl<R: RedisConnection> RedisProducer<R> {
         self.send_raw(&payload).await
     }
 
+    /// Sends a raw message with the given attributes stored as additional
+    /// stream fields.
+    ///
+    /// Attributes are only supported with redis streams; the fallback
+    /// implementation returns [`QueueError::Unsupported`] if `attributes` is
+    /// non-empty. Attributes are also not retained when a message is moved to
+    /// the dead-letter queue.
+    #[tracing::instrument(
+        name = "send",
+        skip_all,
+        fields(payload_size = payload.len())
+    )]
+    pub async fn send_raw_with_attributes(
+        &self,
+        payload: &[u8],
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let send = async {
+            if self.use_redis_streams {
+                streams::send_raw_with_attributes(self, payload, attributes).await
+            } else if attributes.is_empty() {
+                fallback::send_raw(self, payload).await
+            } else {
+                Err(QueueError::Unsupported(
+                    "message attributes are only supported with redis streams",
+                ))
+            }
+        };
+        self.metrics.send([payload.len()], send).await
+    }
+
+    pub async fn send_serde_json_with_attributes<P: Serialize + Sync>(
+        &self,
+        payload: &P,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = serde_json::to_vec(payload)?;
+        self.send_raw_with_attributes(&payload, attributes).await
+    }
+
     #[tracing::instrument(
         name = "send",
         skip_all,
@@ -732,18 +1053,20 @@ impl<R: RedisConnection> RedisProducer<R> {
     pub async fn send_raw_scheduled(&self, payload: &[u8], delay: Duration) -> Result<()> {
         let timestamp = unix_timestamp(SystemTime::now() + delay).map_err(QueueError::generic)?;
 
-        let _: () = self
-            .redis
-            .get()
-            .await
-            .map_err(QueueError::generic)?
-            .zadd(
-                &self.delayed_queue_key,
-                internal_to_list_payload(InternalPayload::new(payload)),
-                timestamp,
-            )
-            .await
-            .map_err(QueueError::generic)?;
+        let send = async {
+            self.redis
+                .get()
+                .await
+                .map_err(redis_error("send_scheduled"))?
+                .zadd(
+                    &self.delayed_queue_key,
+                    internal_to_list_payload(InternalPayload::new(payload)),
+                    timestamp,
+                )
+                .await
+                .map_err(redis_error("send_scheduled"))
+        };
+        let _: () = self.metrics.send([payload.len()], send).await?;
 
         trace!(?delay, "event sent");
         Ok(())
@@ -767,11 +1090,11 @@ impl<R: RedisConnection> RedisProducer<R> {
             .ok_or(QueueError::Unsupported("Missing DeadLetterQueueConfig"))?;
 
         loop {
-            let mut conn = self.redis.get().await.map_err(QueueError::generic)?;
+            let mut conn = self.redis.get().await.map_err(redis_error("redrive_dlq"))?;
             let old_payloads: Vec<RawPayload> = conn
                 .lrange(dlq, 0, BATCH_SIZE)
                 .await
-                .map_err(QueueError::generic)?;
+                .map_err(redis_error("redrive_dlq"))?;
 
             if old_payloads.is_empty() {
                 break;
@@ -800,18 +1123,64 @@ impl<R: RedisConnection> RedisProducer<R> {
                 let _: () = conn
                     .lrem(dlq, 1, &payload)
                     .await
-                    .map_err(QueueError::generic)?;
+                    .map_err(redis_error("redrive_dlq"))?;
             }
             info!("Moved {payload_len} items from deadletter queue to main queue");
         }
 
         Ok(())
     }
+
+    /// Sends a `PING` to redis.
+    pub async fn health_check(&self) -> Result<()> {
+        ping(&self.redis).await
+    }
+
+    /// Returns the status of the background tasks shared by this producer and
+    /// the other clients from the same `build_*` call.
+    pub fn background_status(&self) -> BackgroundStatus {
+        self.background_tasks.status()
+    }
+
+    /// Returns a receiver that is notified whenever the
+    /// [`background_status`](Self::background_status) changes.
+    pub fn watch_background_status(&self) -> watch::Receiver<BackgroundStatus> {
+        self.background_tasks.watch_status()
+    }
+
+    /// Stops the background tasks, waiting for them to finish what they are
+    /// doing.
+    ///
+    /// The tasks are shared with the other clients from the same `build_*`
+    /// call, so this stops them for those as well. Without them, delayed
+    /// messages are no longer moved to the main queue, and messages that
+    /// aren't acked in time are no longer redelivered, unless another client
+    /// runs the tasks. Sending and receiving keep working.
+    ///
+    /// Fails if a task panicked.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.background_tasks.shutdown().await
+    }
 }
 
 impl<R: RedisConnection> crate::QueueProducer for RedisProducer<R> {
     type Payload = Vec<u8>;
-    omni_delegate!(send_raw, send_serde_json, redrive_dlq);
+    omni_delegate!(
+        send_raw,
+        send_serde_json,
+        send_raw_with_attributes,
+        send_serde_json_with_attributes,
+        redrive_dlq,
+        health_check
+    );
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities(
+            self.use_redis_streams,
+            &self.delayed_queue_key,
+            &self.dlq_config,
+        )
+    }
 }
 impl<R: RedisConnection> crate::ScheduledQueueProducer for RedisProducer<R> {
     omni_delegate!(send_raw_scheduled, send_serde_json_scheduled);
@@ -837,18 +1206,22 @@ pub struct RedisConsumer<M: ManageConnection> {
     consumer_group: String,
     consumer_name: String,
     payload_key: String,
+    delayed_queue_key: String,
+    ack_deadline_ms: i64,
     use_redis_streams: bool,
-    _background_tasks: Arc<JoinSet<Result<()>>>,
+    background_tasks: Arc<BackgroundTasks>,
     dlq_config: Option<DeadLetterQueueConfig>,
+    metrics: QueueMetrics,
 }
 
 impl<R: RedisConnection> RedisConsumer<R> {
     pub async fn receive(&mut self) -> Result<Delivery> {
-        if self.use_redis_streams {
+        let result = if self.use_redis_streams {
             streams::receive(self).await
         } else {
             fallback::receive(self).await
-        }
+        };
+        self.metrics.receive(result)
     }
 
     pub async fn receive_all(
@@ -856,15 +1229,195 @@ impl<R: RedisConnection> RedisConsumer<R> {
         max_messages: usize,
         deadline: Duration,
     ) -> Result<Vec<Delivery>> {
-        if self.use_redis_streams {
+        let result = if self.use_redis_streams {
             streams::receive_all(self, deadline, max_messages).await
         } else {
             fallback::receive_all(self, deadline, max_messages).await
+        };
+        self.metrics.receive_all(result)
+    }
+
+    /// Sends a `PING` to redis.
+    pub async fn health_check(&self) -> Result<()> {
+        ping(&self.redis).await
+    }
+
+    /// Returns the status of the background tasks shared by this consumer and
+    /// the other clients from the same `build_*` call.
+    pub fn background_status(&self) -> BackgroundStatus {
+        self.background_tasks.status()
+    }
+
+    /// Returns a receiver that is notified whenever the
+    /// [`background_status`](Self::background_status) changes.
+    pub fn watch_background_status(&self) -> watch::Receiver<BackgroundStatus> {
+        self.background_tasks.watch_status()
+    }
+
+    /// Stops the background tasks, waiting for them to finish what they are
+    /// doing.
+    ///
+    /// The tasks are shared with the other clients from the same `build_*`
+    /// call, so this stops them for those as well. Without them, delayed
+    /// messages are no longer moved to the main queue, and messages that
+    /// aren't acked in time are no longer redelivered, unless another client
+    /// runs the tasks. Sending and receiving keep working.
+    ///
+    /// Fails if a task panicked.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.background_tasks.shutdown().await
+    }
+
+    /// Returns the lengths of the main, processing, delayed and dead-letter
+    /// queues.
+    ///
+    /// With redis streams, the in-flight count is the number of pending
+    /// entries of the consumer group.
+    pub async fn stats(&self) -> Result<QueueStats> {
+        let mut conn = self.redis.get().await.map_err(redis_error("stats"))?;
+
+        // The keys may live on different nodes of a cluster, so they are
+        // queried one at a time.
+        let (visible, in_flight) = if self.use_redis_streams {
+            let len: u64 = conn
+                .xlen(&self.queue_key)
+                .await
+                .map_err(redis_error("stats"))?;
+            let pending: StreamPendingReply = conn
+                .xpending(&self.queue_key, &self.consumer_group)
+                .await
+                .map_err(redis_error("stats"))?;
+            // Entries are only deleted from the stream once they are acked.
+            let pending = pending.count() as u64;
+            (len.saturating_sub(pending), pending)
+        } else {
+            let len: u64 = conn
+                .llen(&self.queue_key)
+                .await
+                .map_err(redis_error("stats"))?;
+            let processing: u64 = conn
+                .llen(&self.processing_queue_key)
+                .await
+                .map_err(redis_error("stats"))?;
+            (len, processing)
+        };
+
+        let delayed = if self.delayed_queue_key.is_empty() {
+            None
+        } else {
+            let delayed: u64 = conn
+                .zcard(&self.delayed_queue_key)
+                .await
+                .map_err(redis_error("stats"))?;
+            Some(delayed)
+        };
+
+        let dead_lettered = match &self.dlq_config {
+            Some(dlq_config) => {
+                let len: u64 = conn
+                    .llen(&dlq_config.queue_key)
+                    .await
+                    .map_err(redis_error("stats"))?;
+                Some(len)
+            }
+            None => None,
+        };
+
+        Ok(QueueStats {
+            visible: Some(visible),
+            in_flight: Some(in_flight),
+            delayed,
+            dead_lettered,
+        })
+    }
+
+    /// Returns the first `max_messages` messages of the main queue.
+    ///
+    /// With redis streams, this includes messages that have been received,
+    /// but not yet acked. The receive counts of such messages don't include
+    /// their current delivery.
+    pub async fn peek(&self, max_messages: usize) -> Result<Vec<Delivery>> {
+        if self.use_redis_streams {
+            streams::peek(self, max_messages).await
+        } else {
+            fallback::peek(self, max_messages).await
         }
     }
+
+    /// Returns the first `max_messages` messages of the dead-letter queue.
+    ///
+    /// Returns [`QueueError::Unsupported`] if no dead-letter queue is
+    /// configured.
+    pub async fn peek_dlq(&self, max_messages: usize) -> Result<Vec<Delivery>> {
+        let Some(dlq_config) = &self.dlq_config else {
+            return Err(QueueError::Unsupported(
+                "no dead-letter queue is configured",
+            ));
+        };
+        if max_messages == 0 {
+            return Ok(Vec::new());
+        }
+        let max_messages = isize::try_from(max_messages).unwrap_or(isize::MAX);
+
+        // Messages are pushed to the end of the dead-letter queue.
+        let payloads: Vec<Vec<u8>> = self
+            .redis
+            .get()
+            .await
+            .map_err(redis_error("peek_dlq"))?
+            .lrange(&dlq_config.queue_key, 0, max_messages - 1)
+            .await
+            .map_err(redis_error("peek_dlq"))?;
+
+        Ok(payloads
+            .into_iter()
+            .map(|payload| Delivery::new(payload, PeekAcker))
+            .collect())
+    }
+}
+
+async fn ping<R: RedisConnection>(pool: &bb8::Pool<R>) -> Result<()> {
+    // Checking out a connection also runs the connection manager's `is_valid`,
+    // which for redis cluster pings all primaries.
+    let mut conn = pool.get().await.map_err(redis_error("health_check"))?;
+    let pong: String = redis::cmd("PING")
+        .query_async(&mut *conn)
+        .await
+        .map_err(redis_error("health_check"))?;
+    if pong != "PONG" {
+        return Err(QueueError::backend(
+            ErrorKind::Other,
+            "redis",
+            "health_check",
+            format!("unexpected reply to PING: {pong}"),
+        ));
+    }
+    Ok(())
 }
 
 impl<R: RedisConnection> crate::QueueConsumer for RedisConsumer<R> {
     type Payload = Vec<u8>;
-    omni_delegate!(receive, receive_all);
+    omni_delegate!(receive, receive_all, health_check, stats, peek);
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities(
+            self.use_redis_streams,
+            &self.delayed_queue_key,
+            &self.dlq_config,
+        )
+    }
+}
+
+fn capabilities(
+    use_redis_streams: bool,
+    delayed_queue_key: &str,
+    dlq_config: &Option<DeadLetterQueueConfig>,
+) -> Capabilities {
+    Capabilities {
+        scheduling: !delayed_queue_key.is_empty(),
+        dead_letter_queue: dlq_config.is_some(),
+        set_ack_deadline: use_redis_streams,
+        ordering: MessageOrdering::Fifo,
+        ..Default::default()
+    }
 }
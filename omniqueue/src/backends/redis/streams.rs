@@ -1,64 +1,91 @@
 //! Implementation of the main queue using redis streams.
 
-use std::time::Duration;
+use std::{
+    borrow::Cow,
+    num::NonZeroUsize,
+    time::{Duration, SystemTime},
+};
 
 use bb8::ManageConnection;
 use redis::{
     streams::{
-        StreamAutoClaimOptions, StreamClaimReply, StreamId, StreamRangeReply, StreamReadOptions,
-        StreamReadReply,
+        StreamAutoClaimOptions, StreamClaimOptions, StreamClaimReply, StreamId, StreamRangeReply,
+        StreamReadOptions, StreamReadReply,
     },
     AsyncCommands as _, FromRedisValue, RedisResult,
 };
-use tracing::{error, trace};
+use time::OffsetDateTime;
+use tracing::trace;
 
 use super::{
+    background::BackgroundTask, internal_to_list_payload, redis_error, unix_timestamp,
     DeadLetterQueueConfig, InternalPayload, InternalPayloadOwned, RedisConnection, RedisConsumer,
-    RedisProducer,
+    RedisProducer, ATTRIBUTE_PREFIX,
+};
+use crate::{
+    metrics::QueueMetrics,
+    queue::{Acker, PeekAcker},
+    Attributes, Delivery, DeliveryMetadata, ErrorKind, QueueError, Result,
 };
-use crate::{queue::Acker, Delivery, QueueError, Result};
 
 /// Special ID for XADD command's which generates a stream ID automatically
 const GENERATE_STREAM_ID: &str = "*";
 /// Special ID for XREADGROUP commands which reads any new messages
 const LISTEN_STREAM_ID: &str = ">";
 
-/// The maximum number of pending messages to reinsert into the queue after
-/// becoming stale per loop
-// FIXME(onelson): expose in config?
-const PENDING_BATCH_SIZE: usize = 1000;
-
-macro_rules! internal_to_stream_payload {
-    ($internal_payload:expr, $payload_key:expr) => {
-        &[
-            ($payload_key, $internal_payload.payload),
-            (
-                NUM_RECEIVES,
-                $internal_payload.num_receives.to_string().as_bytes(),
-            ),
-        ]
-    };
+fn internal_to_stream_payload<'a>(
+    InternalPayload {
+        payload,
+        num_receives,
+        attributes,
+    }: &'a InternalPayload<'_>,
+    payload_key: &'a str,
+) -> Vec<(Cow<'a, str>, Cow<'a, [u8]>)> {
+    let mut fields = Vec::with_capacity(attributes.len() + 2);
+    fields.push((Cow::Borrowed(payload_key), Cow::Borrowed(*payload)));
+    fields.push((
+        Cow::Borrowed(NUM_RECEIVES),
+        Cow::Owned(num_receives.to_string().into_bytes()),
+    ));
+    for (key, value) in attributes.iter() {
+        fields.push((
+            Cow::Owned(format!("{ATTRIBUTE_PREFIX}{key}")),
+            Cow::Borrowed(value.as_bytes()),
+        ));
+    }
+    fields
 }
 
 pub(super) async fn send_raw<R: RedisConnection>(
     producer: &RedisProducer<R>,
     payload: &[u8],
+) -> Result<()> {
+    send_raw_with_attributes(producer, payload, &Attributes::new()).await
+}
+
+pub(super) async fn send_raw_with_attributes<R: RedisConnection>(
+    producer: &RedisProducer<R>,
+    payload: &[u8],
+    attributes: &Attributes,
 ) -> Result<()> {
     producer
         .redis
         .get()
         .await
-        .map_err(QueueError::generic)?
+        .map_err(redis_error("send"))?
         .xadd(
             &producer.queue_key,
             GENERATE_STREAM_ID,
-            internal_to_stream_payload!(
-                InternalPayload::new(payload),
-                producer.payload_key.as_str()
+            &internal_to_stream_payload(
+                &InternalPayload {
+                    attributes: Cow::Borrowed(attributes),
+                    ..InternalPayload::new(payload)
+                },
+                &producer.payload_key,
             ),
         )
         .await
-        .map_err(QueueError::generic)
+        .map_err(redis_error("send"))
 }
 
 pub(super) async fn receive<R: RedisConnection>(consumer: &RedisConsumer<R>) -> Result<Delivery> {
@@ -67,7 +94,7 @@ pub(super) async fn receive<R: RedisConnection>(consumer: &RedisConsumer<R>) ->
         .redis
         .get()
         .await
-        .map_err(QueueError::generic)?
+        .map_err(redis_error("receive"))?
         .xread_options(
             &[&consumer.queue_key],
             &[LISTEN_STREAM_ID],
@@ -77,7 +104,7 @@ pub(super) async fn receive<R: RedisConnection>(consumer: &RedisConsumer<R>) ->
                 .count(1),
         )
         .await
-        .map_err(QueueError::generic)?;
+        .map_err(redis_error("receive"))?;
 
     let queue = read_out.keys.into_iter().next().ok_or(QueueError::NoData)?;
     let entry = queue.ids.into_iter().next().ok_or(QueueError::NoData)?;
@@ -95,7 +122,7 @@ pub(super) async fn receive_all<R: RedisConnection>(
         .redis
         .get()
         .await
-        .map_err(QueueError::generic)?
+        .map_err(redis_error("receive"))?
         .xread_options(
             &[&consumer.queue_key],
             &[LISTEN_STREAM_ID],
@@ -110,7 +137,7 @@ pub(super) async fn receive_all<R: RedisConnection>(
                 .count(max_messages),
         )
         .await
-        .map_err(QueueError::generic)?;
+        .map_err(redis_error("receive"))?;
 
     let mut out = Vec::with_capacity(max_messages);
 
@@ -124,6 +151,42 @@ pub(super) async fn receive_all<R: RedisConnection>(
     Ok(out)
 }
 
+/// Returns the first `max_messages` entries of the stream, including entries
+/// that are pending in the consumer group.
+pub(super) async fn peek<R: RedisConnection>(
+    consumer: &RedisConsumer<R>,
+    max_messages: usize,
+) -> Result<Vec<Delivery>> {
+    let StreamRangeReply { ids, .. } = consumer
+        .redis
+        .get()
+        .await
+        .map_err(redis_error("peek"))?
+        .xrange_count(&consumer.queue_key, "-", "+", max_messages)
+        .await
+        .map_err(redis_error("peek"))?;
+
+    ids.into_iter()
+        .map(|entry| {
+            let InternalPayloadOwned {
+                payload,
+                num_receives,
+                attributes,
+            } = internal_from_stream(&entry, &consumer.payload_key)?;
+            // `internal_from_stream` counts the receive that is about to
+            // happen, which peeking doesn't do.
+            let metadata = DeliveryMetadata {
+                message_id: Some(entry.id.clone()),
+                receive_count: (num_receives - 1).try_into().ok(),
+                enqueued_at: entry_id_timestamp(&entry.id),
+            };
+            Ok(Delivery::new(payload, PeekAcker)
+                .with_metadata(metadata)
+                .with_attributes(attributes))
+        })
+        .collect()
+}
+
 const NUM_RECEIVES: &str = "num_receives";
 
 fn internal_from_stream(stream_id: &StreamId, payload_key: &str) -> Result<InternalPayloadOwned> {
@@ -144,9 +207,22 @@ fn internal_from_stream(stream_id: &StreamId, payload_key: &str) -> Result<Inter
         .ok_or(QueueError::NoData)
         .and_then(|x| redis::from_redis_value(x).map_err(QueueError::generic))?;
 
+    let attributes = map
+        .iter()
+        .filter_map(|(key, value)| {
+            let key = key.strip_prefix(ATTRIBUTE_PREFIX)?;
+            Some(
+                redis::from_redis_value(value)
+                    .map(|value| (key.to_owned(), value))
+                    .map_err(QueueError::generic),
+            )
+        })
+        .collect::<Result<_>>()?;
+
     Ok(InternalPayloadOwned {
         payload,
         num_receives,
+        attributes,
     })
 }
 
@@ -154,35 +230,62 @@ fn internal_to_delivery<R: RedisConnection>(
     InternalPayloadOwned {
         payload,
         num_receives,
+        attributes,
     }: InternalPayloadOwned,
     consumer: &RedisConsumer<R>,
     entry_id: String,
 ) -> Delivery {
+    let metadata = DeliveryMetadata {
+        message_id: Some(entry_id.clone()),
+        receive_count: num_receives.try_into().ok(),
+        enqueued_at: entry_id_timestamp(&entry_id),
+    };
     Delivery::new(
         payload,
         RedisStreamsAcker {
             redis: consumer.redis.clone(),
             queue_key: consumer.queue_key.to_owned(),
             consumer_group: consumer.consumer_group.to_owned(),
+            consumer_name: consumer.consumer_name.to_owned(),
+            delayed_queue_key: consumer.delayed_queue_key.to_owned(),
+            ack_deadline_ms: consumer.ack_deadline_ms,
             entry_id,
             already_acked_or_nacked: false,
             num_receives,
             dlq_config: consumer.dlq_config.clone(),
             payload_key: consumer.payload_key.clone(),
+            metrics: consumer.metrics.clone(),
         },
     )
+    .with_metadata(metadata)
+    .with_attributes(attributes)
+}
+
+/// Stream entry IDs are of the form `<milliseconds>-<sequence number>`, where
+/// the first part is the time the entry was added to the stream.
+///
+/// Note that messages which are reinserted after timing out, or moved over
+/// from the delayed queue, get a new entry ID.
+fn entry_id_timestamp(entry_id: &str) -> Option<OffsetDateTime> {
+    let (millis, _) = entry_id.split_once('-')?;
+    let millis: i128 = millis.parse().ok()?;
+    OffsetDateTime::from_unix_timestamp_nanos(millis * 1_000_000).ok()
 }
 
 struct RedisStreamsAcker<M: ManageConnection> {
     redis: bb8::Pool<M>,
     queue_key: String,
     consumer_group: String,
+    consumer_name: String,
+    delayed_queue_key: String,
+    ack_deadline_ms: i64,
     entry_id: String,
     payload_key: String,
 
     already_acked_or_nacked: bool,
     num_receives: usize,
     dlq_config: Option<DeadLetterQueueConfig>,
+    metrics: QueueMetrics,
 }
 
 impl<R: RedisConnection> RedisStreamsAcker<R> {}
@@ -197,11 +300,11 @@ impl<R: RedisConnection> Acker for RedisStreamsAcker<R> {
         pipeline.xack(&self.queue_key, &self.consumer_group, &[&self.entry_id]);
         pipeline.xdel(&self.queue_key, &[&self.entry_id]);
 
-        let mut conn = self.redis.get().await.map_err(QueueError::generic)?;
+        let mut conn = self.redis.get().await.map_err(redis_error("ack"))?;
         let _: () = pipeline
             .query_async(&mut *conn)
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(redis_error("ack"))?;
 
         self.already_acked_or_nacked = true;
 
@@ -218,6 +321,7 @@ impl<R: RedisConnection> Acker for RedisStreamsAcker<R> {
                     dlq_config,
                     &self.entry_id,
                     &self.payload_key,
+                    &self.metrics,
                 )
                 .await?;
                 return self.ack().await;
@@ -233,10 +337,109 @@ impl<R: RedisConnection> Acker for RedisStreamsAcker<R> {
         Ok(())
     }
 
-    async fn set_ack_deadline(&mut self, _duration: Duration) -> Result<()> {
-        Err(QueueError::Unsupported(
-            "set_ack_deadline is not yet supported by redis streams backend",
-        ))
+    /// Moves the message to the delayed queue, from where it's reinserted into
+    /// the stream once `delay` has passed.
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        if let Some(dlq_config) = &self.dlq_config {
+            if dlq_config.max_retries_reached(self.num_receives) {
+                return self.nack().await;
+            }
+        }
+
+        if self.already_acked_or_nacked {
+            return Err(QueueError::CannotAckOrNackTwice);
+        }
+        if self.delayed_queue_key.is_empty() {
+            return Err(QueueError::Unsupported(
+                "nack_with_delay requires a delayed_queue_key",
+            ));
+        }
+
+        let InternalPayloadOwned {
+            payload,
+            attributes,
+            ..
+        } = get_entry(
+            &self.redis,
+            &self.queue_key,
+            &self.entry_id,
+            &self.payload_key,
+        )
+        .await?;
+        let timestamp = unix_timestamp(SystemTime::now() + delay).map_err(QueueError::generic)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .zadd(
+                &self.delayed_queue_key,
+                internal_to_list_payload(InternalPayload {
+                    payload: &payload,
+                    num_receives: self.num_receives,
+                    attributes: Cow::Owned(attributes),
+                }),
+                timestamp,
+            )
+            .xack(&self.queue_key, &self.consumer_group, &[&self.entry_id])
+            .xdel(&self.queue_key, &[&self.entry_id]);
+
+        let mut conn = self
+            .redis
+            .get()
+            .await
+            .map_err(redis_error("nack_with_delay"))?;
+        let _: () = pipe
+            .query_async(&mut *conn)
+            .await
+            .map_err(redis_error("nack_with_delay"))?;
+
+        self.already_acked_or_nacked = true;
+
+        Ok(())
+    }
+
+    /// Messages are reinserted into the stream once they've been pending for
+    /// `ack_deadline_ms`, so this sets the entry's idle time such that the
+    /// remaining time is `duration`.
+    ///
+    /// Redis doesn't allow negative idle times, so `duration` is capped at
+    /// `ack_deadline_ms`.
+    async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
+        if self.already_acked_or_nacked {
+            return Err(QueueError::CannotAckOrNackTwice);
+        }
+
+        let duration_ms = i64::try_from(duration.as_millis()).unwrap_or(i64::MAX);
+        let idle_ms = self.ack_deadline_ms.saturating_sub(duration_ms).max(0);
+
+        let claimed: Vec<String> = self
+            .redis
+            .get()
+            .await
+            .map_err(redis_error("set_ack_deadline"))?
+            .xclaim_options(
+                &self.queue_key,
+                &self.consumer_group,
+                &self.consumer_name,
+                0,
+                &[&self.entry_id],
+                StreamClaimOptions::default()
+                    .idle(idle_ms.try_into().unwrap_or_default())
+                    .with_justid(),
+            )
+            .await
+            .map_err(redis_error("set_ack_deadline"))?;
+
+        if claimed.is_empty() {
+            // The entry has already been reinserted into the stream or acked
+            return Err(QueueError::backend(
+                ErrorKind::InvalidReceiptHandle,
+                "redis",
+                "set_ack_deadline",
+                format!("entry {} is no longer pending", self.entry_id),
+            ));
+        }
+
+        Ok(())
     }
 }
 
@@ -247,19 +450,21 @@ pub(super) async fn add_to_main_queue(
     conn: &mut impl redis::aio::ConnectionLike,
 ) -> Result<()> {
     let mut pipe = redis::pipe();
-    // We don't care about existing `num_receives`
-    // since we're pushing onto a different queue.
-    for InternalPayload { payload, .. } in keys {
-        // So reset it to avoid carrying state over:
-        let internal = InternalPayload::new(payload);
+    // Keep `num_receives` and the attributes, so messages that were nacked
+    // with a delay still end up in the DLQ after the configured number of
+    // receives, and keep their attributes.
+    for internal in &keys {
         let _ = pipe.xadd(
             main_queue_name,
             GENERATE_STREAM_ID,
-            internal_to_stream_payload!(internal, payload_key),
+            &internal_to_stream_payload(internal, payload_key),
         );
     }
 
-    let _: () = pipe.query_async(conn).await.map_err(QueueError::generic)?;
+    let _: () = pipe
+        .query_async(conn)
+        .await
+        .map_err(redis_error("enqueue_delayed"))?;
 
     Ok(())
 }
@@ -287,31 +492,33 @@ impl FromRedisValue for StreamAutoclaimReply {
 
 /// Scoops up messages that have been claimed but not handled by a deadline,
 /// then re-queues them.
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn background_task_pending<R: RedisConnection>(
+    mut task: BackgroundTask,
     pool: bb8::Pool<R>,
     queue_key: String,
     consumer_group: String,
     consumer_name: String,
     ack_deadline_ms: i64,
+    pending_batch_size: NonZeroUsize,
     payload_key: String,
     dlq_config: Option<DeadLetterQueueConfig>,
-) -> Result<()> {
-    loop {
-        if let Err(err) = reenqueue_timed_out_messages(
+    metrics: QueueMetrics,
+) {
+    while !task.is_shutting_down() {
+        let result = reenqueue_timed_out_messages(
             &pool,
             &queue_key,
             &consumer_group,
             &consumer_name,
             ack_deadline_ms,
+            pending_batch_size,
             &payload_key,
             &dlq_config,
+            &metrics,
         )
-        .await
-        {
-            error!("{err}");
-            tokio::time::sleep(Duration::from_millis(500)).await;
-            continue;
-        }
+        .await;
+        task.after_iteration(result).await;
     }
 }
 
@@ -326,44 +533,69 @@ async fn send_to_dlq<R: RedisConnection>(
     dlq_config: &DeadLetterQueueConfig,
     entry_id: &str,
     payload_key: &str,
+    metrics: &QueueMetrics,
 ) -> Result<()> {
     let DeadLetterQueueConfig { queue_key: dlq, .. } = dlq_config;
-    let StreamRangeReply { ids, .. } = redis
+    let payload = get_payload(redis, main_queue_key, entry_id, payload_key).await?;
+
+    let _: () = redis
         .get()
         .await
-        .map_err(QueueError::generic)?
-        .xrange(main_queue_key, entry_id, entry_id)
+        .map_err(redis_error("send_to_dlq"))?
+        .rpush(dlq, &payload)
         .await
-        .map_err(QueueError::generic)?;
+        .map_err(redis_error("send_to_dlq"))?;
 
-    let payload = ids.first().ok_or_else(|| QueueError::NoData)?;
-    let payload: Vec<u8> = payload
-        .map
-        .get(payload_key)
-        .ok_or(QueueError::NoData)
-        .and_then(|x| redis::from_redis_value(x).map_err(QueueError::generic))?;
+    metrics.dead_lettered(1);
+    Ok(())
+}
 
-    let _: () = redis
+async fn get_payload<R: RedisConnection>(
+    redis: &bb8::Pool<R>,
+    main_queue_key: &str,
+    entry_id: &str,
+    payload_key: &str,
+) -> Result<Vec<u8>> {
+    let entry = get_entry(redis, main_queue_key, entry_id, payload_key).await?;
+    Ok(entry.payload)
+}
+
+/// Reads the payload and attributes of a stream entry.
+async fn get_entry<R: RedisConnection>(
+    redis: &bb8::Pool<R>,
+    main_queue_key: &str,
+    entry_id: &str,
+    payload_key: &str,
+) -> Result<InternalPayloadOwned> {
+    let StreamRangeReply { ids, .. } = redis
         .get()
         .await
-        .map_err(QueueError::generic)?
-        .rpush(dlq, &payload)
+        .map_err(redis_error("read_payload"))?
+        .xrange(main_queue_key, entry_id, entry_id)
         .await
-        .map_err(QueueError::generic)?;
+        .map_err(redis_error("read_payload"))?;
 
-    Ok(())
+    let entry = ids.first().ok_or_else(|| QueueError::NoData)?;
+    internal_from_stream(entry, payload_key)
 }
 
+/// Returns whether any timed out messages were found.
+#[allow(clippy::too_many_arguments)]
 async fn reenqueue_timed_out_messages<R: RedisConnection>(
     pool: &bb8::Pool<R>,
     main_queue_name: &str,
     consumer_group: &str,
     consumer_name: &str,
     ack_deadline_ms: i64,
+    pending_batch_size: NonZeroUsize,
     payload_key: &str,
     dlq_config: &Option<DeadLetterQueueConfig>,
-) -> Result<()> {
-    let mut conn = pool.get().await.map_err(QueueError::generic)?;
+    metrics: &QueueMetrics,
+) -> Result<bool> {
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(redis_error("reenqueue_timed_out"))?;
 
     // Every iteration checks whether the processing queue has items that should
     // be picked back up, claiming them in the process
@@ -374,10 +606,10 @@ async fn reenqueue_timed_out_messages<R: RedisConnection>(
             consumer_name,
             ack_deadline_ms,
             "-",
-            StreamAutoClaimOptions::default().count(PENDING_BATCH_SIZE),
+            StreamAutoClaimOptions::default().count(pending_batch_size.get()),
         )
         .await
-        .map_err(QueueError::generic)?;
+        .map_err(redis_error("reenqueue_timed_out"))?;
 
     if !ids.is_empty() {
         trace!("Moving {} unhandled messages back to the queue", ids.len());
@@ -389,6 +621,7 @@ async fn reenqueue_timed_out_messages<R: RedisConnection>(
             let InternalPayloadOwned {
                 payload,
                 num_receives,
+                attributes,
             } = internal_from_stream(stream_id, payload_key)?;
 
             if let Some(dlq_config) = &dlq_config {
@@ -403,6 +636,7 @@ async fn reenqueue_timed_out_messages<R: RedisConnection>(
                         dlq_config,
                         &stream_id.id,
                         payload_key,
+                        metrics,
                     )
                     .await?;
                     continue;
@@ -411,12 +645,13 @@ async fn reenqueue_timed_out_messages<R: RedisConnection>(
             let _ = pipe.xadd(
                 main_queue_name,
                 GENERATE_STREAM_ID,
-                internal_to_stream_payload!(
-                    InternalPayload {
+                &internal_to_stream_payload(
+                    &InternalPayload {
                         payload: payload.as_slice(),
-                        num_receives
+                        num_receives,
+                        attributes: Cow::Owned(attributes),
                     },
-                    payload_key
+                    payload_key,
                 ),
             );
         }
@@ -424,7 +659,7 @@ async fn reenqueue_timed_out_messages<R: RedisConnection>(
         let _: () = pipe
             .query_async(&mut *conn)
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(redis_error("reenqueue_timed_out"))?;
 
         // Acknowledge all the stale ones so the pending queue is cleared
         let ids: Vec<_> = ids.iter().map(|wrapped| &wrapped.id).collect();
@@ -436,11 +671,10 @@ async fn reenqueue_timed_out_messages<R: RedisConnection>(
         let _: () = pipe
             .query_async(&mut *conn)
             .await
-            .map_err(QueueError::generic)?;
+            .map_err(redis_error("reenqueue_timed_out"))?;
+
+        Ok(true)
     } else {
-        // Wait for half a second before attempting to fetch again if nothing was found
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        Ok(false)
     }
-
-    Ok(())
 }
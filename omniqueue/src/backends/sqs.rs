@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fmt::{self, Write},
     future::Future,
     num::NonZeroUsize,
@@ -6,24 +7,33 @@ use std::{
 };
 
 use aws_sdk_sqs::{
+    error::{ProvideErrorMetadata, SdkError},
     operation::delete_message::DeleteMessageError,
-    types::{error::ReceiptHandleIsInvalid, Message, SendMessageBatchRequestEntry},
+    types::{
+        error::ReceiptHandleIsInvalid, Message, MessageAttributeValue, MessageSystemAttributeName,
+        QueueAttributeName, SendMessageBatchRequestEntry,
+    },
     Client,
 };
 use futures_util::FutureExt as _;
 use serde::Serialize;
+use time::OffsetDateTime;
 
 #[allow(deprecated)]
 use crate::{
     builder::{QueueBuilder, Static},
-    queue::{Acker, Delivery, QueueBackend},
-    QueueError, Result,
+    metrics::QueueMetrics,
+    queue::{Acker, Delivery, DeliveryMetadata, QueueBackend},
+    Attributes, Capabilities, ErrorKind, MessageOrdering, QueueAdmin, QueueError, QueueStats,
+    Result,
 };
 
 /// https://docs.aws.amazon.com/AWSSimpleQueueService/latest/SQSDeveloperGuide/quotas-messages.html
 const MAX_PAYLOAD_SIZE: usize = 262_144;
 /// https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_SendMessageBatch.html
 const MAX_BATCH_SIZE: usize = 10;
+/// https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_SendMessage.html
+const MAX_DELAY: Duration = Duration::from_secs(15 * 60);
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct SqsConfig {
@@ -137,14 +147,18 @@ impl QueueBackend for SqsBackend {
         let aws_cfg = cfg.take_sqs_config().await;
         let client = Client::from_conf(aws_cfg);
 
+        let metrics = queue_metrics(&cfg.queue_dsn);
+
         let producer = SqsProducer {
             client: client.clone(),
             queue_dsn: cfg.queue_dsn.clone(),
+            metrics: metrics.clone(),
         };
 
         let consumer = SqsConsumer {
             client,
             queue_dsn: cfg.queue_dsn,
+            metrics,
         };
 
         Ok((producer, consumer))
@@ -156,6 +170,7 @@ impl QueueBackend for SqsBackend {
 
         let producer = SqsProducer {
             client,
+            metrics: queue_metrics(&cfg.queue_dsn),
             queue_dsn: cfg.queue_dsn,
         };
 
@@ -168,6 +183,7 @@ impl QueueBackend for SqsBackend {
 
         let consumer = SqsConsumer {
             client,
+            metrics: queue_metrics(&cfg.queue_dsn),
             queue_dsn: cfg.queue_dsn,
         };
 
@@ -175,6 +191,19 @@ impl QueueBackend for SqsBackend {
     }
 }
 
+/// Returns the queue name, which is the last segment of the DSN.
+fn queue_name(queue_dsn: &str) -> &str {
+    queue_dsn
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+}
+
+fn queue_metrics(queue_dsn: &str) -> QueueMetrics {
+    QueueMetrics::new("sqs", queue_name(queue_dsn))
+}
+
 impl QueueBuilder<SqsBackend> {
     /// Set the SQS configuration to use.
     ///
@@ -192,6 +221,17 @@ impl QueueBuilder<SqsBackend> {
     }
 }
 
+impl<S, L> QueueBuilder<SqsBackend, S, L> {
+    /// Builds a [`SqsAdmin`] for the configured queue.
+    pub async fn build_admin(mut self) -> Result<SqsAdmin> {
+        let aws_cfg = self.config.take_sqs_config().await;
+        Ok(SqsAdmin {
+            client: Client::from_conf(aws_cfg),
+            queue_dsn: self.config.queue_dsn,
+        })
+    }
+}
+
 struct SqsAcker {
     ack_client: Client,
     // FIXME: Cow/Arc this stuff?
@@ -216,7 +256,7 @@ impl Acker for SqsAcker {
                 // Segment the async state machine. send future is >5kb at the time of writing.
                 .boxed()
                 .await
-                .map_err(aws_to_queue_error)?;
+                .map_err(aws_to_queue_error("ack"))?;
 
             self.has_been_acked_or_nacked = true;
 
@@ -224,7 +264,10 @@ impl Acker for SqsAcker {
         } else {
             self.has_been_acked_or_nacked = true;
 
-            Err(QueueError::generic(
+            Err(QueueError::backend(
+                ErrorKind::InvalidReceiptHandle,
+                "sqs",
+                "ack",
                 DeleteMessageError::ReceiptHandleIsInvalid(
                     ReceiptHandleIsInvalid::builder()
                         .message("receipt handle must be Some to be acked")
@@ -238,6 +281,18 @@ impl Acker for SqsAcker {
         Ok(())
     }
 
+    /// Sets the message's visibility timeout to `delay`, which SQS caps at 12
+    /// hours.
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        if self.has_been_acked_or_nacked {
+            return Err(QueueError::CannotAckOrNackTwice);
+        }
+
+        self.set_ack_deadline(delay).await?;
+        self.has_been_acked_or_nacked = true;
+        Ok(())
+    }
+
     async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
         if let Some(receipt_handle) = &self.receipt_handle {
             let duration_secs = duration.as_secs().try_into().map_err(|e| {
@@ -254,11 +309,14 @@ impl Acker for SqsAcker {
                 // Segment the async state machine. send future is >5kb at the time of writing.
                 .boxed()
                 .await
-                .map_err(aws_to_queue_error)?;
+                .map_err(aws_to_queue_error("set_ack_deadline"))?;
 
             Ok(())
         } else {
-            Err(QueueError::generic(
+            Err(QueueError::backend(
+                ErrorKind::InvalidReceiptHandle,
+                "sqs",
+                "set_ack_deadline",
                 DeleteMessageError::ReceiptHandleIsInvalid(
                     ReceiptHandleIsInvalid::builder()
                         .message("receipt handle must be Some to set ack deadline")
@@ -272,6 +330,7 @@ impl Acker for SqsAcker {
 pub struct SqsProducer {
     client: Client,
     queue_dsn: String,
+    metrics: QueueMetrics,
 }
 
 impl SqsProducer {
@@ -293,6 +352,52 @@ impl SqsProducer {
         )
     )]
     pub async fn send_raw_scheduled(&self, payload: &str, delay: Duration) -> Result<()> {
+        self.send_inner(payload, delay, None).await
+    }
+
+    /// Sends a raw message with the given attributes set as SQS message
+    /// attributes of type `String`.
+    #[tracing::instrument(
+        name = "send",
+        skip_all,
+        fields(payload_size = payload.len())
+    )]
+    pub async fn send_raw_with_attributes(
+        &self,
+        payload: &str,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let attributes = attributes
+            .iter()
+            .map(|(key, value)| {
+                let value = MessageAttributeValue::builder()
+                    .data_type("String")
+                    .string_value(value)
+                    .build()
+                    .map_err(QueueError::generic)?;
+                Ok((key.clone(), value))
+            })
+            .collect::<Result<_>>()?;
+
+        self.send_inner(payload, Duration::ZERO, Some(attributes))
+            .await
+    }
+
+    pub async fn send_serde_json_with_attributes<P: Serialize + Sync>(
+        &self,
+        payload: &P,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = serde_json::to_string(payload)?;
+        self.send_raw_with_attributes(&payload, attributes).await
+    }
+
+    async fn send_inner(
+        &self,
+        payload: &str,
+        delay: Duration,
+        attributes: Option<HashMap<String, MessageAttributeValue>>,
+    ) -> Result<()> {
         if payload.len() > MAX_PAYLOAD_SIZE {
             return Err(QueueError::PayloadTooLarge {
                 limit: MAX_PAYLOAD_SIZE,
@@ -300,16 +405,22 @@ impl SqsProducer {
             });
         }
 
-        self.client
-            .send_message()
-            .queue_url(&self.queue_dsn)
-            .message_body(payload)
-            .delay_seconds(delay.as_secs().try_into().map_err(QueueError::generic)?)
-            .send()
-            // Segment the async state machine. send future is >5kb at the time of writing.
-            .boxed()
-            .await
-            .map_err(aws_to_queue_error)?;
+        self.metrics
+            .send([payload.len()], async {
+                self.client
+                    .send_message()
+                    .queue_url(&self.queue_dsn)
+                    .message_body(payload)
+                    .delay_seconds(delay.as_secs().try_into().map_err(QueueError::generic)?)
+                    .set_message_attributes(attributes)
+                    .send()
+                    // Segment the async state machine. send future is >5kb at the time of
+                    // writing.
+                    .boxed()
+                    .await
+                    .map_err(aws_to_queue_error("send"))
+            })
+            .await?;
 
         Ok(())
     }
@@ -358,15 +469,20 @@ impl SqsProducer {
                 })
                 .collect::<Result<_>>()?;
 
-            self.client
-                .send_message_batch()
-                .queue_url(&self.queue_dsn)
-                .set_entries(Some(entries))
-                .send()
-                // Segment the async state machine. send future is >5kb at the time of writing.
-                .boxed()
-                .await
-                .map_err(aws_to_queue_error)?;
+            self.metrics
+                .send(payloads.iter().map(String::len), async {
+                    self.client
+                        .send_message_batch()
+                        .queue_url(&self.queue_dsn)
+                        .set_entries(Some(entries))
+                        .send()
+                        // Segment the async state machine. send future is >5kb at the time of
+                        // writing.
+                        .boxed()
+                        .await
+                        .map_err(aws_to_queue_error("send_batch"))
+                })
+                .await?;
         }
 
         Ok(())
@@ -377,11 +493,22 @@ impl SqsProducer {
             "redrive_dlq is not supported by SqsBackend",
         ))
     }
+
+    pub async fn health_check(&self) -> Result<()> {
+        check_queue(&self.client, &self.queue_dsn).await
+    }
 }
 
 impl crate::QueueProducer for SqsProducer {
     type Payload = String;
-    omni_delegate!(send_raw, send_serde_json, redrive_dlq);
+    omni_delegate!(
+        send_raw,
+        send_serde_json,
+        send_raw_with_attributes,
+        send_serde_json_with_attributes,
+        redrive_dlq,
+        health_check
+    );
 
     /// This method is overwritten for the SQS backend to be more efficient
     /// than the default of sequentially publishing `payloads`.
@@ -400,7 +527,16 @@ impl crate::QueueProducer for SqsProducer {
     ) -> impl Future<Output = Result<()>> {
         self.send_batch_inner(payloads, |p| Ok(serde_json::to_string(&p)?))
     }
+
+    fn payload_is_text(&self) -> bool {
+        true
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities(&self.queue_dsn)
+    }
 }
+
 impl crate::ScheduledQueueProducer for SqsProducer {
     omni_delegate!(send_raw_scheduled, send_serde_json_scheduled);
 }
@@ -408,10 +544,31 @@ impl crate::ScheduledQueueProducer for SqsProducer {
 pub struct SqsConsumer {
     client: Client,
     queue_dsn: String,
+    metrics: QueueMetrics,
 }
 
 impl SqsConsumer {
     fn wrap_message(&self, message: &Message) -> Delivery {
+        let attributes = message
+            .message_attributes()
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, value)| Some((key.clone(), value.string_value()?.to_owned())))
+            .collect();
+
+        let system_attribute = |name| message.attributes().and_then(|attrs| attrs.get(&name));
+        let metadata = DeliveryMetadata {
+            message_id: message.message_id().map(ToOwned::to_owned),
+            receive_count: system_attribute(MessageSystemAttributeName::ApproximateReceiveCount)
+                .and_then(|count| count.parse().ok()),
+            // Epoch time in milliseconds
+            enqueued_at: system_attribute(MessageSystemAttributeName::SentTimestamp)
+                .and_then(|millis| millis.parse::<i128>().ok())
+                .and_then(|millis| {
+                    OffsetDateTime::from_unix_timestamp_nanos(millis * 1_000_000).ok()
+                }),
+        };
+
         Delivery::new(
             message.body().unwrap_or_default().as_bytes().to_owned(),
             SqsAcker {
@@ -421,19 +578,29 @@ impl SqsConsumer {
                 has_been_acked_or_nacked: false,
             },
         )
+        .with_metadata(metadata)
+        .with_attributes(attributes)
     }
 
     pub async fn receive(&self) -> Result<Delivery> {
+        let result = self.receive_inner().await;
+        self.metrics.receive(result)
+    }
+
+    async fn receive_inner(&self) -> Result<Delivery> {
         let out = self
             .client
             .receive_message()
             .set_max_number_of_messages(Some(1))
+            .message_attribute_names("All")
+            .message_system_attribute_names(MessageSystemAttributeName::ApproximateReceiveCount)
+            .message_system_attribute_names(MessageSystemAttributeName::SentTimestamp)
             .queue_url(&self.queue_dsn)
             .send()
             // Segment the async state machine. send future is >5kb at the time of writing.
             .boxed()
             .await
-            .map_err(aws_to_queue_error)?;
+            .map_err(aws_to_queue_error("receive"))?;
 
         out.messages()
             .iter()
@@ -446,6 +613,15 @@ impl SqsConsumer {
         &self,
         max_messages: usize,
         deadline: Duration,
+    ) -> Result<Vec<Delivery>> {
+        let result = self.receive_all_inner(max_messages, deadline).await;
+        self.metrics.receive_all(result)
+    }
+
+    async fn receive_all_inner(
+        &self,
+        max_messages: usize,
+        deadline: Duration,
     ) -> Result<Vec<Delivery>> {
         let out = self
             .client
@@ -454,38 +630,209 @@ impl SqsConsumer {
                 deadline.as_secs().try_into().map_err(QueueError::generic)?,
             ))
             .set_max_number_of_messages(Some(max_messages.try_into().map_err(QueueError::generic)?))
+            .message_attribute_names("All")
+            .message_system_attribute_names(MessageSystemAttributeName::ApproximateReceiveCount)
+            .message_system_attribute_names(MessageSystemAttributeName::SentTimestamp)
             .queue_url(&self.queue_dsn)
             .send()
             // Segment the async state machine. send future is >5kb at the time of writing.
             .boxed()
             .await
-            .map_err(aws_to_queue_error)?;
+            .map_err(aws_to_queue_error("receive"))?;
 
         out.messages()
             .iter()
             .map(|message| -> Result<Delivery> { Ok(self.wrap_message(message)) })
             .collect::<Result<Vec<_>, _>>()
     }
+
+    pub async fn health_check(&self) -> Result<()> {
+        check_queue(&self.client, &self.queue_dsn).await
+    }
+
+    /// Returns the `ApproximateNumberOfMessages*` attributes of the queue.
+    ///
+    /// Messages in a dead-letter queue configured through a redrive policy
+    /// aren't counted.
+    pub async fn stats(&self) -> Result<QueueStats> {
+        let output = self
+            .client
+            .get_queue_attributes()
+            .queue_url(&self.queue_dsn)
+            .attribute_names(QueueAttributeName::ApproximateNumberOfMessages)
+            .attribute_names(QueueAttributeName::ApproximateNumberOfMessagesNotVisible)
+            .attribute_names(QueueAttributeName::ApproximateNumberOfMessagesDelayed)
+            .send()
+            .await
+            .map_err(aws_to_queue_error("stats"))?;
+
+        let count = |name| {
+            output
+                .attributes()
+                .and_then(|attributes| attributes.get(&name))
+                .and_then(|count| count.parse().ok())
+        };
+        Ok(QueueStats {
+            visible: count(QueueAttributeName::ApproximateNumberOfMessages),
+            in_flight: count(QueueAttributeName::ApproximateNumberOfMessagesNotVisible),
+            delayed: count(QueueAttributeName::ApproximateNumberOfMessagesDelayed),
+            dead_lettered: None,
+        })
+    }
+}
+
+/// Checks that the queue exists and can be accessed, by fetching its
+/// attributes.
+async fn check_queue(client: &Client, queue_dsn: &str) -> Result<()> {
+    client
+        .get_queue_attributes()
+        .queue_url(queue_dsn)
+        .send()
+        .await
+        .map_err(aws_to_queue_error("health_check"))?;
+    Ok(())
 }
 
 impl crate::QueueConsumer for SqsConsumer {
     type Payload = String;
-    omni_delegate!(receive, receive_all);
+    omni_delegate!(receive, receive_all, health_check, stats);
 
     fn max_messages(&self) -> Option<NonZeroUsize> {
         // Not very clearly documented, but this doc mentions "batch of 10 messages" a
         // few times: https://docs.aws.amazon.com/AWSSimpleQueueService/latest/SQSDeveloperGuide/quotas-messages.html
         NonZeroUsize::new(10)
     }
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities(&self.queue_dsn)
+    }
+}
+
+fn capabilities(queue_dsn: &str) -> Capabilities {
+    Capabilities {
+        max_payload_size: Some(MAX_PAYLOAD_SIZE),
+        max_send_batch_size: NonZeroUsize::new(MAX_BATCH_SIZE),
+        max_receive_batch_size: NonZeroUsize::new(MAX_BATCH_SIZE),
+        scheduling: true,
+        max_delay: Some(MAX_DELAY),
+        set_ack_deadline: true,
+        ordering: if queue_dsn.ends_with(".fifo") {
+            MessageOrdering::Fifo
+        } else {
+            MessageOrdering::BestEffort
+        },
+        ..Default::default()
+    }
+}
+
+/// Administers the queue named by the last segment of the queue DSN.
+///
+/// Queues whose names end in `.fifo` are created as FIFO queues.
+///
+/// Created with [`QueueBuilder::build_admin`].
+pub struct SqsAdmin {
+    client: Client,
+    queue_dsn: String,
 }
 
-fn aws_to_queue_error<E>(err: aws_sdk_sqs::error::SdkError<E>) -> QueueError
+impl SqsAdmin {
+    pub async fn create_queue(&self) -> Result<()> {
+        let name = queue_name(&self.queue_dsn);
+        let mut request = self.client.create_queue().queue_name(name);
+        if name.ends_with(".fifo") {
+            request = request.attributes(QueueAttributeName::FifoQueue, "true");
+        }
+        request
+            .send()
+            .await
+            .map_err(aws_to_queue_error("create_queue"))?;
+        Ok(())
+    }
+
+    pub async fn delete_queue(&self) -> Result<()> {
+        self.client
+            .delete_queue()
+            .queue_url(&self.queue_dsn)
+            .send()
+            .await
+            .map_err(aws_to_queue_error("delete_queue"))?;
+        Ok(())
+    }
+
+    /// Deletes all messages from the queue.
+    ///
+    /// Note that SQS only allows one purge per queue every 60 seconds, and
+    /// that it may take up to 60 seconds for the purge to complete.
+    pub async fn purge(&self) -> Result<()> {
+        self.client
+            .purge_queue()
+            .queue_url(&self.queue_dsn)
+            .send()
+            .await
+            .map_err(aws_to_queue_error("purge"))?;
+        Ok(())
+    }
+
+    pub async fn exists(&self) -> Result<bool> {
+        match check_queue(&self.client, &self.queue_dsn).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::QueueNotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl QueueAdmin for SqsAdmin {
+    omni_delegate!(create_queue, delete_queue, purge, exists);
+}
+
+fn aws_to_queue_error<E>(operation: &'static str) -> impl FnOnce(SdkError<E>) -> QueueError
 where
-    E: std::error::Error + 'static,
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
 {
-    let mut message = String::new();
-    write_err(&mut message, &err).expect("Write to string never fails");
-    QueueError::Generic(message.into())
+    move |err| {
+        let kind = match &err {
+            SdkError::TimeoutError(_) => ErrorKind::Timeout,
+            SdkError::DispatchFailure(failure) if failure.is_timeout() => ErrorKind::Timeout,
+            SdkError::DispatchFailure(failure) if !failure.is_user() => ErrorKind::ConnectionLost,
+            SdkError::ServiceError(_) => sqs_error_kind(err.code()),
+            _ => ErrorKind::Other,
+        };
+
+        // `SdkError`'s own message is just its variant name, so keep the
+        // service error (whose message comes from SQS) or flatten the chain.
+        match err {
+            SdkError::ServiceError(err) => {
+                QueueError::backend(kind, "sqs", operation, err.into_err())
+            }
+            err => {
+                let mut message = String::new();
+                write_err(&mut message, &err).expect("Write to string never fails");
+                QueueError::backend(kind, "sqs", operation, message)
+            }
+        }
+    }
+}
+
+fn sqs_error_kind(code: Option<&str>) -> ErrorKind {
+    match code.unwrap_or_default() {
+        "AWS.SimpleQueueService.NonExistentQueue" | "QueueDoesNotExist" => ErrorKind::QueueNotFound,
+        "ReceiptHandleIsInvalid" | "AWS.SimpleQueueService.MessageNotInflight" => {
+            ErrorKind::InvalidReceiptHandle
+        }
+        "RequestThrottled" | "ThrottlingException" | "KmsThrottled" | "OverLimit" => {
+            ErrorKind::Throttled
+        }
+        "AccessDenied"
+        | "AccessDeniedException"
+        | "InvalidClientTokenId"
+        | "UnrecognizedClientException"
+        | "SignatureDoesNotMatch"
+        | "ExpiredToken"
+        | "InvalidSecurity"
+        | "MissingAuthenticationToken" => ErrorKind::AuthFailure,
+        _ => ErrorKind::Other,
+    }
 }
 
 fn write_err(s: &mut String, err: &dyn std::error::Error) -> fmt::Result {
@@ -3,7 +3,9 @@
 use std::marker::PhantomData;
 
 use crate::{
-    DynConsumer, DynProducer, QueueBackend, QueueConsumer as _, QueueProducer as _, Result,
+    layer::{Identity, ProducerLayer, Stack},
+    DynConsumer, DynProducer, DynScheduledProducer, QueueBackend, QueueConsumer as _,
+    QueueProducer as _, Result, ScheduledQueueProducer,
 };
 
 #[non_exhaustive]
@@ -18,8 +20,9 @@ pub struct Dynamic;
 /// [`MemoryQueueBackend::builder`][crate::backends::InMemoryBackend::builder],
 /// [`RedisQueueBackend::builder`][crate::backends::RedisBackend::builder] and
 /// so on.
-pub struct QueueBuilder<Q: QueueBackend, S = Static> {
+pub struct QueueBuilder<Q: QueueBackend, S = Static, L = Identity> {
     pub(crate) config: Q::Config,
+    layer: L,
 
     _pd: PhantomData<S>,
 }
@@ -34,40 +37,64 @@ impl<Q: QueueBackend> QueueBuilder<Q> {
     pub fn new(config: Q::Config) -> Self {
         Self {
             config,
+            layer: Identity,
             _pd: PhantomData,
         }
     }
+}
 
-    pub async fn build_pair(self) -> Result<(Q::Producer, Q::Consumer)> {
-        Q::new_pair(self.config).await
+impl<Q: QueueBackend, S, L> QueueBuilder<Q, S, L> {
+    /// Wraps the producers built by this builder in `layer`.
+    ///
+    /// Layers added earlier wrap the ones added later, so they see messages
+    /// first.
+    pub fn layer<L2>(self, layer: L2) -> QueueBuilder<Q, S, Stack<L2, L>> {
+        QueueBuilder {
+            config: self.config,
+            layer: Stack::new(layer, self.layer),
+            _pd: PhantomData,
+        }
     }
+}
 
-    pub async fn build_producer(self) -> Result<Q::Producer> {
-        Q::producing_half(self.config).await
+impl<Q: QueueBackend, L: ProducerLayer<Q::Producer>> QueueBuilder<Q, Static, L> {
+    pub async fn build_pair(self) -> Result<(L::Producer, Q::Consumer)> {
+        let (p, c) = Q::new_pair(self.config).await?;
+        Ok((self.layer.layer(p), c))
+    }
+
+    pub async fn build_producer(self) -> Result<L::Producer> {
+        let p = Q::producing_half(self.config).await?;
+        Ok(self.layer.layer(p))
     }
 
     pub async fn build_consumer(self) -> Result<Q::Consumer> {
         Q::consuming_half(self.config).await
     }
 
-    pub fn make_dynamic(self) -> QueueBuilder<Q, Dynamic> {
+    pub fn make_dynamic(self) -> QueueBuilder<Q, Dynamic, L> {
         QueueBuilder {
             config: self.config,
+            layer: self.layer,
             _pd: PhantomData,
         }
     }
 }
 
-impl<Q: QueueBackend + 'static> QueueBuilder<Q, Dynamic> {
+impl<Q, L> QueueBuilder<Q, Dynamic, L>
+where
+    Q: QueueBackend + 'static,
+    L: ProducerLayer<Q::Producer, Producer: 'static>,
+{
     pub async fn build_pair(self) -> Result<(DynProducer, DynConsumer)> {
         let (p, c) = Q::new_pair(self.config).await?;
-        Ok((p.into_dyn(), c.into_dyn()))
+        Ok((self.layer.layer(p).into_dyn(), c.into_dyn()))
     }
 
     pub async fn build_producer(self) -> Result<DynProducer> {
         let p = Q::producing_half(self.config).await?;
 
-        Ok(p.into_dyn())
+        Ok(self.layer.layer(p).into_dyn())
     }
 
     pub async fn build_consumer(self) -> Result<DynConsumer> {
@@ -76,3 +103,24 @@ impl<Q: QueueBackend + 'static> QueueBuilder<Q, Dynamic> {
         Ok(c.into_dyn())
     }
 }
+
+impl<Q, L> QueueBuilder<Q, Dynamic, L>
+where
+    Q: QueueBackend + 'static,
+    L: ProducerLayer<Q::Producer, Producer: ScheduledQueueProducer + 'static>,
+{
+    /// Like [`build_pair`](Self::build_pair), but returns a
+    /// [`DynScheduledProducer`] that can send delayed messages.
+    pub async fn build_scheduled_pair(self) -> Result<(DynScheduledProducer, DynConsumer)> {
+        let (p, c) = Q::new_pair(self.config).await?;
+        Ok((self.layer.layer(p).into_dyn_scheduled(), c.into_dyn()))
+    }
+
+    /// Like [`build_producer`](Self::build_producer), but returns a
+    /// [`DynScheduledProducer`] that can send delayed messages.
+    pub async fn build_scheduled_producer(self) -> Result<DynScheduledProducer> {
+        let p = Q::producing_half(self.config).await?;
+
+        Ok(self.layer.layer(p).into_dyn_scheduled())
+    }
+}
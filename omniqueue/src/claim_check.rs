@@ -0,0 +1,567 @@
+//! The claim-check pattern, for payloads that are too large for a queue.
+//!
+//! [`ClaimCheckProducer`] stores payloads above a size threshold in a
+//! [`BlobStore`] and sends a small reference to the stored payload instead.
+//! [`ClaimCheckConsumer`] resolves these references on receipt, so the stored
+//! payload is what [`Delivery::borrow_payload`] and [`Delivery::take_payload`]
+//! return:
+//!
+//! ```ignore
+//! use omniqueue::claim_check::{ClaimCheckConsumer, ClaimCheckProducer, FsBlobStore};
+//!
+//! let store = FsBlobStore::new("/mnt/shared/payloads");
+//! let (p, c) = SqsBackend::builder(cfg).build_pair().await?;
+//! let p = ClaimCheckProducer::new(p, store.clone(), 200 * 1024);
+//! let mut c = ClaimCheckConsumer::new(c, store).delete_on_ack(true);
+//! ```
+//!
+//! Only [`FsBlobStore`] is provided, for a directory that producers and
+//! consumers share. Object stores such as S3, GCS or Azure Blob Storage can be
+//! used by implementing [`BlobStore`] for their clients.
+//!
+//! When combining this with compression or encryption, wrap the claim-check
+//! producer and consumer in the other wrappers, so stored payloads are
+//! compressed or encrypted as well.
+
+use std::{future::Future, io, num::NonZeroUsize, path::PathBuf, sync::Arc, time::Duration};
+
+use tracing::{error, warn};
+
+use crate::{
+    framing::{self, Kind},
+    layer::ProducerLayer,
+    queue::{Acker, DynAcker},
+    Attributes, Capabilities, Delivery, ErrorKind, QueueConsumer, QueueError, QueuePayload,
+    QueueProducer, QueueStats, Result, ScheduledQueueProducer,
+};
+
+/// Storage for payloads that are sent by reference.
+///
+/// Keys are generated by [`ClaimCheckProducer`] and consist of ASCII letters,
+/// digits, `-` and `_`.
+pub trait BlobStore: Send + Sync + 'static {
+    /// Stores `body` under `key`.
+    fn put(&self, key: &str, body: &[u8]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Retrieves the body stored under `key`.
+    fn get(&self, key: &str) -> impl Future<Output = Result<Vec<u8>>> + Send;
+
+    /// Deletes the body stored under `key`.
+    fn delete(&self, key: &str) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// A [`BlobStore`] that keeps payloads as files in a local directory.
+///
+/// The directory is created on the first `put` if it doesn't exist yet.
+#[derive(Clone, Debug)]
+pub struct FsBlobStore {
+    dir: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Returns the path for `key`, making sure that the key can't escape the
+    /// directory, since consumers take keys from received messages.
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if !is_valid_key(key) {
+            return Err(QueueError::Generic(
+                format!("invalid blob key {key:?}").into(),
+            ));
+        }
+        Ok(self.dir.join(key))
+    }
+}
+
+fn fs_error(operation: &'static str) -> impl FnOnce(io::Error) -> QueueError {
+    move |e| {
+        let kind = match e.kind() {
+            io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            io::ErrorKind::PermissionDenied => ErrorKind::AuthFailure,
+            _ => ErrorKind::Other,
+        };
+        QueueError::backend(kind, "fs_blob_store", operation, e)
+    }
+}
+
+impl BlobStore for FsBlobStore {
+    async fn put(&self, key: &str, body: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(fs_error("put"))?;
+        tokio::fs::write(path, body).await.map_err(fs_error("put"))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.path(key)?)
+            .await
+            .map_err(fs_error("get"))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        tokio::fs::remove_file(self.path(key)?)
+            .await
+            .map_err(fs_error("delete"))
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Generates a random key, so keys of stored payloads can't be guessed by
+/// other users of the same store.
+fn generate_key() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Returns the blob key if `payload` is a reference to a stored payload.
+fn parse_reference(payload: &[u8]) -> Option<&str> {
+    let key = framing::strip_header(Kind::Reference, payload)?;
+    let key = std::str::from_utf8(key).ok()?;
+    is_valid_key(key).then_some(key)
+}
+
+/// A [`QueueProducer`] that stores payloads above a size threshold in a
+/// [`BlobStore`] and sends a reference to them instead.
+///
+/// Attributes are always sent along with the message.
+#[derive(Debug)]
+pub struct ClaimCheckProducer<P, S> {
+    inner: P,
+    store: Arc<S>,
+    threshold: usize,
+}
+
+impl<P: QueueProducer, S: BlobStore> ClaimCheckProducer<P, S> {
+    /// Creates a producer that stores payloads larger than `threshold` bytes
+    /// in `store`.
+    pub fn new(inner: P, store: S, threshold: usize) -> Self {
+        Self {
+            inner,
+            store: Arc::new(store),
+            threshold,
+        }
+    }
+
+    /// Returns the wrapped producer.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    /// Stores `payload` if it is over the threshold, returning its key and the
+    /// reference to send in its place.
+    async fn check_in(&self, payload: &P::Payload) -> Result<Option<(String, Box<P::Payload>)>> {
+        let bytes = payload.to_bytes_naive()?;
+        if bytes.len() <= self.threshold {
+            return Ok(None);
+        }
+
+        let key = generate_key();
+        let reference = [&framing::header(Kind::Reference), key.as_bytes()].concat();
+        let reference = P::Payload::from_bytes_naive(&reference)?;
+        self.store.put(&key, &bytes).await?;
+        Ok(Some((key, reference)))
+    }
+
+    /// Deletes the payloads stored under `keys` if sending their references
+    /// failed, since no message would ever refer to them.
+    async fn delete_if_unsent(&self, keys: &[String], result: Result<()>) -> Result<()> {
+        if result.is_err() {
+            for key in keys {
+                if let Err(e) = self.store.delete(key).await {
+                    warn!(error = ?e, key, "Failed to delete payload of unsent message");
+                }
+            }
+        }
+        result
+    }
+}
+
+impl<P: QueueProducer, S: BlobStore> QueueProducer for ClaimCheckProducer<P, S> {
+    type Payload = P::Payload;
+
+    async fn send_raw(&self, payload: &Self::Payload) -> Result<()> {
+        match self.check_in(payload).await? {
+            Some((key, reference)) => {
+                let result = self.inner.send_raw(&reference).await;
+                self.delete_if_unsent(&[key], result).await
+            }
+            None => self.inner.send_raw(payload).await,
+        }
+    }
+
+    async fn send_raw_with_attributes(
+        &self,
+        payload: &Self::Payload,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        match self.check_in(payload).await? {
+            Some((key, reference)) => {
+                let result = self
+                    .inner
+                    .send_raw_with_attributes(&reference, attributes)
+                    .await;
+                self.delete_if_unsent(&[key], result).await
+            }
+            None => {
+                self.inner
+                    .send_raw_with_attributes(payload, attributes)
+                    .await
+            }
+        }
+    }
+
+    async fn send_raw_batch(
+        &self,
+        payloads: impl IntoIterator<Item: AsRef<Self::Payload> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let mut keys = Vec::new();
+        let result = async {
+            let mut checked_in = Vec::new();
+            for payload in payloads {
+                let payload = payload.as_ref();
+                checked_in.push(match self.check_in(payload).await? {
+                    Some((key, reference)) => {
+                        keys.push(key);
+                        reference
+                    }
+                    None => P::Payload::from_bytes_naive(&payload.to_bytes_naive()?)?,
+                });
+            }
+            self.inner.send_raw_batch(checked_in).await
+        }
+        .await;
+        self.delete_if_unsent(&keys, result).await
+    }
+
+    async fn redrive_dlq(&self) -> Result<()> {
+        self.inner.redrive_dlq().await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    fn payload_is_text(&self) -> bool {
+        self.inner.payload_is_text()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // Payloads over the threshold are stored in the blob store, so there
+        // is no limit on their size.
+        Capabilities {
+            max_payload_size: None,
+            ..self.inner.capabilities()
+        }
+    }
+}
+
+impl<P: ScheduledQueueProducer, S: BlobStore> ScheduledQueueProducer for ClaimCheckProducer<P, S> {
+    async fn send_raw_scheduled(&self, payload: &Self::Payload, delay: Duration) -> Result<()> {
+        match self.check_in(payload).await? {
+            Some((key, reference)) => {
+                let result = self.inner.send_raw_scheduled(&reference, delay).await;
+                self.delete_if_unsent(&[key], result).await
+            }
+            None => self.inner.send_raw_scheduled(payload, delay).await,
+        }
+    }
+}
+
+/// A [`ProducerLayer`] that wraps producers in a [`ClaimCheckProducer`].
+#[derive(Debug)]
+pub struct ClaimCheckLayer<S> {
+    store: Arc<S>,
+    threshold: usize,
+}
+
+impl<S: BlobStore> ClaimCheckLayer<S> {
+    /// Creates a layer that stores payloads larger than `threshold` bytes in
+    /// `store`.
+    pub fn new(store: S, threshold: usize) -> Self {
+        Self {
+            store: Arc::new(store),
+            threshold,
+        }
+    }
+}
+
+impl<S> Clone for ClaimCheckLayer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            threshold: self.threshold,
+        }
+    }
+}
+
+impl<P: QueueProducer, S: BlobStore> ProducerLayer<P> for ClaimCheckLayer<S> {
+    type Producer = ClaimCheckProducer<P, S>;
+
+    fn layer(&self, inner: P) -> Self::Producer {
+        ClaimCheckProducer {
+            inner,
+            store: self.store.clone(),
+            threshold: self.threshold,
+        }
+    }
+}
+
+/// A [`QueueConsumer`] that replaces references sent by a
+/// [`ClaimCheckProducer`] with the payloads they refer to.
+///
+/// A stored payload that can't be retrieved doesn't fail the receive; the
+/// error is logged and the delivery's payload stays the reference.
+#[derive(Debug)]
+pub struct ClaimCheckConsumer<C, S> {
+    inner: C,
+    store: Arc<S>,
+    delete_on_ack: bool,
+}
+
+impl<C: QueueConsumer, S: BlobStore> ClaimCheckConsumer<C, S> {
+    pub fn new(inner: C, store: S) -> Self {
+        Self {
+            inner,
+            store: Arc::new(store),
+            delete_on_ack: false,
+        }
+    }
+
+    /// Deletes stored payloads once their deliveries are acked.
+    ///
+    /// This is off by default, since the payloads would be lost for any
+    /// other consumers of the same messages, e.g. other Pub/Sub
+    /// subscriptions.
+    pub fn delete_on_ack(mut self, delete: bool) -> Self {
+        self.delete_on_ack = delete;
+        self
+    }
+
+    /// Returns the wrapped consumer.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+/// Replaces the payload of `delivery` with the stored payload if it is a
+/// reference.
+async fn check_out<S: BlobStore>(
+    store: &Arc<S>,
+    delete_on_ack: bool,
+    mut delivery: Delivery,
+) -> Delivery {
+    let Some(key) = delivery.borrow_payload().and_then(parse_reference) else {
+        return delivery;
+    };
+    let key = key.to_owned();
+
+    match store.get(&key).await {
+        Ok(payload) => delivery.set_payload(payload),
+        Err(e) => {
+            error!(
+                error = ?e,
+                key,
+                metadata = ?delivery.metadata(),
+                "Failed to retrieve stored payload"
+            );
+            return delivery;
+        }
+    }
+
+    if delete_on_ack {
+        let store = store.clone();
+        delivery = delivery.wrap_acker(|inner| ClaimCheckAcker { inner, store, key });
+    }
+    delivery
+}
+
+impl<C: QueueConsumer, S: BlobStore> QueueConsumer for ClaimCheckConsumer<C, S> {
+    type Payload = C::Payload;
+
+    async fn receive(&mut self) -> Result<Delivery> {
+        let delivery = self.inner.receive().await?;
+        Ok(check_out(&self.store, self.delete_on_ack, delivery).await)
+    }
+
+    async fn receive_all(
+        &mut self,
+        max_messages: usize,
+        deadline: Duration,
+    ) -> Result<Vec<Delivery>> {
+        let deliveries = self.inner.receive_all(max_messages, deadline).await?;
+        let mut checked_out = Vec::with_capacity(deliveries.len());
+        for delivery in deliveries {
+            checked_out.push(check_out(&self.store, self.delete_on_ack, delivery).await);
+        }
+        Ok(checked_out)
+    }
+
+    fn max_messages(&self) -> Option<NonZeroUsize> {
+        self.inner.max_messages()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn health_check(&self) -> impl Future<Output = Result<()>> + Send {
+        // Not an `async fn`, as consumers don't have to be `Sync`
+        self.inner.health_check()
+    }
+
+    fn stats(&self) -> impl Future<Output = Result<QueueStats>> + Send {
+        self.inner.stats()
+    }
+
+    fn peek(&self, max_messages: usize) -> impl Future<Output = Result<Vec<Delivery>>> + Send {
+        let store = &self.store;
+        let peeked = self.inner.peek(max_messages);
+        async move {
+            let deliveries = peeked.await?;
+            let mut checked_out = Vec::with_capacity(deliveries.len());
+            for delivery in deliveries {
+                // Peeked deliveries can't be acked, so there is nothing to
+                // delete the stored payload on.
+                checked_out.push(check_out(store, false, delivery).await);
+            }
+            Ok(checked_out)
+        }
+    }
+}
+
+/// Deletes the stored payload of a delivery after acking it.
+struct ClaimCheckAcker<S> {
+    inner: DynAcker,
+    store: Arc<S>,
+    key: String,
+}
+
+impl<S: BlobStore> Acker for ClaimCheckAcker<S> {
+    async fn ack(&mut self) -> Result<()> {
+        self.inner.ack().await?;
+        // The message is gone at this point, so failing to delete its payload
+        // only leaves an orphaned blob behind.
+        if let Err(e) = self.store.delete(&self.key).await {
+            warn!(error = ?e, key = self.key, "Failed to delete stored payload");
+        }
+        Ok(())
+    }
+
+    async fn nack(&mut self) -> Result<()> {
+        self.inner.nack().await
+    }
+
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        self.inner.nack_with_delay(delay).await
+    }
+
+    async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
+        self.inner.set_ack_deadline(duration).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{parse_reference, BlobStore, FsBlobStore};
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("omniqueue-claim-check-{:016x}", fastrand::u64(..)))
+    }
+
+    #[tokio::test]
+    async fn test_fs_blob_store() {
+        let dir = temp_dir();
+        let store = FsBlobStore::new(&dir);
+
+        store.put("key", b"body").await.unwrap();
+        assert_eq!(store.get("key").await.unwrap(), b"body");
+        store.delete("key").await.unwrap();
+        assert!(store.get("key").await.is_err());
+
+        // Keys from received messages must not escape the directory
+        assert!(store.get("../key").await.is_err());
+        assert!(store.put("", b"body").await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unsent_payload_is_deleted() {
+        use super::ClaimCheckProducer;
+        use crate::{QueueError, QueueProducer, Result};
+
+        struct FailingProducer;
+
+        impl QueueProducer for FailingProducer {
+            type Payload = Vec<u8>;
+
+            async fn send_raw(&self, _payload: &Vec<u8>) -> Result<()> {
+                Err(QueueError::Generic("send failed".into()))
+            }
+
+            async fn redrive_dlq(&self) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        let dir = temp_dir();
+        let p = ClaimCheckProducer::new(FailingProducer, FsBlobStore::new(&dir), 16);
+
+        p.send_raw(&vec![b'a'; 64]).await.unwrap_err();
+        p.send_raw_batch([vec![b'a'; 64], vec![b'b'; 64]])
+            .await
+            .unwrap_err();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_reference() {
+        assert_eq!(parse_reference(b"~OQBabc-123"), Some("abc-123"));
+        assert_eq!(parse_reference(b"~OQB../etc/passwd"), None);
+        assert_eq!(parse_reference(b"~OQCabc-123"), None);
+        assert_eq!(parse_reference(b"abc-123"), None);
+    }
+
+    #[cfg(feature = "in_memory")]
+    #[tokio::test]
+    async fn test_producer_consumer() {
+        use std::time::Duration;
+
+        use super::{ClaimCheckConsumer, ClaimCheckProducer};
+        use crate::{backends::InMemoryBackend, QueueConsumer, QueueProducer};
+
+        let dir = temp_dir();
+        let store = FsBlobStore::new(&dir);
+        let (p, c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        let p = ClaimCheckProducer::new(p, store.clone(), 16);
+        let mut c = ClaimCheckConsumer::new(c, store).delete_on_ack(true);
+
+        let large = vec![b'a'; 64];
+        p.send_raw(&large).await.unwrap();
+        p.send_raw(&b"small".to_vec()).await.unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let mut deliveries = c.receive_all(2, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(deliveries[1].borrow_payload(), Some(&b"small"[..]));
+        assert_eq!(deliveries[0].take_payload(), Some(large));
+
+        for delivery in deliveries {
+            delivery.ack().await.unwrap();
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
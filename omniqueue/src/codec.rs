@@ -0,0 +1,254 @@
+//! Payload encodings for [`QueueProducer::send_encoded`] and
+//! [`Delivery::payload_decoded`].
+//!
+//! [`Json`] is always available. The binary formats are enabled by their
+//! cargo features:
+//!
+//! | Codec           | Feature    | Supported types                      |
+//! |-----------------|------------|--------------------------------------|
+//! | [`Json`]        | -          | [`Serialize`] + [`DeserializeOwned`] |
+//! | `MessagePack`   | `msgpack`  | [`Serialize`] + [`DeserializeOwned`] |
+//! | `Cbor`          | `cbor`     | [`Serialize`] + [`DeserializeOwned`] |
+//! | `Bincode`       | `bincode`  | [`Serialize`] + [`DeserializeOwned`] |
+//! | `Protobuf`      | `protobuf` | `prost::Message` + [`Default`]       |
+//!
+//! The SQS and Azure Queue Storage backends only carry text, so binary
+//! payloads have to be wrapped in `Base64` (feature `base64`) to be sent
+//! through them:
+//!
+//! ```ignore
+//! use omniqueue::codec::{Base64, MessagePack};
+//!
+//! producer.send_encoded::<Base64<MessagePack>, _>(&payload).await?;
+//! let payload: Option<Payload> = delivery.payload_decoded::<Base64<MessagePack>, _>()?;
+//! ```
+//!
+//! [`QueueProducer::send_encoded`]: crate::QueueProducer::send_encoded
+//! [`Delivery::payload_decoded`]: crate::Delivery::payload_decoded
+
+#[cfg(feature = "base64")]
+use std::marker::PhantomData;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(any(
+    feature = "msgpack",
+    feature = "cbor",
+    feature = "bincode",
+    feature = "protobuf",
+    feature = "base64"
+))]
+use crate::QueueError;
+use crate::Result;
+
+/// Converts values of type `T` to and from message payloads.
+///
+/// Codecs are zero-sized marker types that are only used as type parameters,
+/// so implementing a custom codec only takes a unit struct and an impl of this
+/// trait.
+pub trait Codec<T> {
+    /// Encodes `value` into a payload.
+    fn encode(value: &T) -> Result<Vec<u8>>;
+
+    /// Decodes a payload into a value.
+    fn decode(bytes: &[u8]) -> Result<T>;
+}
+
+/// Encodes payloads as JSON, like the `serde_json` methods do.
+#[derive(Clone, Copy, Debug)]
+pub struct Json;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for Json {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(Into::into)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        serde_json::from_slice(bytes).map_err(Into::into)
+    }
+}
+
+/// Encodes payloads as [MessagePack](https://msgpack.org/), with struct fields
+/// encoded by name.
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for MessagePack {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(QueueError::generic)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        rmp_serde::from_slice(bytes).map_err(QueueError::generic)
+    }
+}
+
+/// Encodes payloads as [CBOR](https://cbor.io/).
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for Cbor {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).map_err(QueueError::generic)?;
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        ciborium::from_reader(bytes).map_err(QueueError::generic)
+    }
+}
+
+/// Encodes payloads with [bincode](https://docs.rs/bincode/1).
+///
+/// Bincode isn't self-describing, so producers and consumers have to agree on
+/// the exact payload type.
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for Bincode {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        bincode::serialize(value).map_err(QueueError::generic)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        bincode::deserialize(bytes).map_err(QueueError::generic)
+    }
+}
+
+/// Encodes [`prost`] messages as [Protocol Buffers](https://protobuf.dev/).
+#[cfg(feature = "protobuf")]
+#[derive(Clone, Copy, Debug)]
+pub struct Protobuf;
+
+#[cfg(feature = "protobuf")]
+impl<T: prost::Message + Default> Codec<T> for Protobuf {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        T::decode(bytes).map_err(QueueError::generic)
+    }
+}
+
+/// Wraps the payloads encoded by `C` in standard base64, for sending binary
+/// payloads through backends that only carry text.
+#[cfg(feature = "base64")]
+#[derive(Clone, Copy, Debug)]
+pub struct Base64<C>(PhantomData<C>);
+
+#[cfg(feature = "base64")]
+impl<T, C: Codec<T>> Codec<T> for Base64<C> {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        use base64::Engine as _;
+
+        let bytes = C::encode(value)?;
+        Ok(base64::engine::general_purpose::STANDARD
+            .encode(bytes)
+            .into_bytes())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        use base64::Engine as _;
+
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(bytes)
+            .map_err(QueueError::generic)?;
+        C::decode(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::{Codec, Json};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct ExType {
+        a: u8,
+        b: String,
+    }
+
+    fn roundtrip<C: Codec<ExType>>() {
+        let value = ExType {
+            a: 1,
+            b: "two".to_owned(),
+        };
+        let bytes = C::encode(&value).unwrap();
+        assert_eq!(C::decode(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn test_json() {
+        roundtrip::<Json>();
+        assert_eq!(
+            Json::encode(&ExType {
+                a: 1,
+                b: "two".to_owned()
+            })
+            .unwrap(),
+            br#"{"a":1,"b":"two"}"#
+        );
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack() {
+        roundtrip::<super::MessagePack>();
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor() {
+        roundtrip::<super::Cbor>();
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode() {
+        roundtrip::<super::Bincode>();
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[test]
+    fn test_protobuf() {
+        #[derive(Clone, PartialEq, prost::Message)]
+        struct ExMessage {
+            #[prost(uint32, tag = "1")]
+            a: u32,
+            #[prost(string, tag = "2")]
+            b: String,
+        }
+
+        let value = ExMessage {
+            a: 1,
+            b: "two".to_owned(),
+        };
+        let bytes = super::Protobuf::encode(&value).unwrap();
+        assert_eq!(
+            <super::Protobuf as Codec<ExMessage>>::decode(&bytes).unwrap(),
+            value
+        );
+    }
+
+    #[cfg(all(feature = "base64", feature = "msgpack"))]
+    #[test]
+    fn test_base64() {
+        roundtrip::<super::Base64<super::MessagePack>>();
+
+        let bytes = super::Base64::<super::MessagePack>::encode(&ExType {
+            a: 1,
+            b: "two".to_owned(),
+        })
+        .unwrap();
+        assert!(String::from_utf8(bytes).is_ok());
+    }
+}
@@ -0,0 +1,510 @@
+//! Transparent payload compression.
+//!
+//! [`CompressingProducer`] compresses payloads above a size threshold before
+//! handing them to the wrapped producer, and [`DecompressingConsumer`]
+//! decompresses them again on receipt:
+//!
+//! ```ignore
+//! use omniqueue::compression::{
+//!     Algorithm, CompressingProducer, CompressionConfig, DecompressingConsumer,
+//! };
+//!
+//! let (p, c) = SqsBackend::builder(cfg).build_pair().await?;
+//! let p = CompressingProducer::new(p, CompressionConfig::new(Algorithm::Zstd));
+//! let mut c = DecompressingConsumer::new(c);
+//! ```
+//!
+//! Compressed payloads start with a short, printable header that identifies
+//! the algorithm, so a `DecompressingConsumer` passes payloads without the marker
+//! through unchanged. This allows enabling compression on producers after
+//! their consumers have been upgraded, without draining the queue first.
+//!
+//! For producers that can only send text (SQS and Azure Queue Storage), the
+//! compressed data is base64-encoded.
+
+use std::{borrow::Cow, future::Future, io::Read, num::NonZeroUsize, time::Duration};
+
+use tracing::warn;
+
+use crate::{
+    framing::{self, Kind},
+    layer::ProducerLayer,
+    Attributes, Capabilities, Delivery, QueueConsumer, QueueError, QueuePayload, QueueProducer,
+    QueueStats, Result, ScheduledQueueProducer,
+};
+
+const DEFAULT_THRESHOLD: usize = 1024;
+const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// A compression algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Algorithm {
+    /// Gzip, via `flate2`. Requires the `gzip` feature.
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// Zstandard. Requires the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Algorithm {
+    fn id(self) -> u8 {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => b'g',
+            #[cfg(feature = "zstd")]
+            Self::Zstd => b'z',
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            #[cfg(feature = "gzip")]
+            b'g' => Some(Self::Gzip),
+            #[cfg(feature = "zstd")]
+            b'z' => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    fn compress(self, payload: &[u8], level: Option<i32>) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                use std::io::Write as _;
+
+                let level = level.map_or(flate2::Compression::default(), |level| {
+                    flate2::Compression::new(level.clamp(0, 9) as u32)
+                });
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                encoder.write_all(payload).map_err(QueueError::generic)?;
+                encoder.finish().map_err(QueueError::generic)
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => {
+                zstd::encode_all(payload, level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL))
+                    .map_err(QueueError::generic)
+            }
+        }
+    }
+
+    /// Decompresses `compressed`, failing if the result would be larger than
+    /// `max_size` bytes.
+    fn decompress(self, compressed: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => Box::new(flate2::read::GzDecoder::new(compressed)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Box::new(zstd::Decoder::new(compressed).map_err(QueueError::generic)?),
+        };
+
+        // Read one byte more than allowed to detect payloads that are too large
+        // without decompressing all of them.
+        let mut out = Vec::new();
+        decoder
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut out)
+            .map_err(QueueError::generic)?;
+        if out.len() > max_size {
+            return Err(QueueError::Generic(
+                format!("decompressed payload is larger than {max_size} bytes").into(),
+            ));
+        }
+        Ok(out)
+    }
+}
+
+/// Configures how [`CompressingProducer`] compresses payloads.
+#[derive(Clone, Debug)]
+pub struct CompressionConfig {
+    algorithm: Algorithm,
+    threshold: usize,
+    level: Option<i32>,
+}
+
+impl CompressionConfig {
+    /// Compresses payloads of at least 1 KiB with `algorithm`, at its default
+    /// level.
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            threshold: DEFAULT_THRESHOLD,
+            level: None,
+        }
+    }
+
+    /// Sets the size in bytes below which payloads are sent uncompressed.
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets the compression level, from 0 to 9 for gzip and from 1 to 22 for
+    /// zstd.
+    pub fn level(mut self, level: i32) -> Self {
+        self.level = Some(level);
+        self
+    }
+}
+
+/// Wraps producers in a [`CompressingProducer`] with this configuration.
+impl<P: QueueProducer> ProducerLayer<P> for CompressionConfig {
+    type Producer = CompressingProducer<P>;
+
+    fn layer(&self, inner: P) -> Self::Producer {
+        CompressingProducer::new(inner, self.clone())
+    }
+}
+
+/// Compresses `payload` according to `config`, prefixed by the compression
+/// header.
+///
+/// Returns `None` if the payload is below the threshold or doesn't get
+/// smaller by compressing it.
+fn compress(config: &CompressionConfig, payload: &[u8], text: bool) -> Result<Option<Vec<u8>>> {
+    if payload.len() < config.threshold {
+        return Ok(None);
+    }
+
+    let compressed = config.algorithm.compress(payload, config.level)?;
+    let out = framing::encode(Kind::Compressed, config.algorithm.id(), &compressed, text);
+    Ok((out.len() < payload.len()).then_some(out))
+}
+
+/// Decompresses a payload produced by a [`CompressingProducer`], failing if
+/// it decompresses to more than `max_size` bytes.
+///
+/// Payloads without the compression header are returned unchanged.
+pub fn decompress(payload: &[u8], max_size: usize) -> Result<Cow<'_, [u8]>> {
+    let Some((algorithm, data)) = framing::decode(Kind::Compressed, payload)? else {
+        return Ok(Cow::Borrowed(payload));
+    };
+    let algorithm = Algorithm::from_id(algorithm).ok_or_else(|| {
+        QueueError::Generic(
+            format!(
+                "payload was compressed with an unsupported algorithm ({})",
+                char::from(algorithm)
+            )
+            .into(),
+        )
+    })?;
+    algorithm.decompress(&data, max_size).map(Cow::Owned)
+}
+
+/// A [`QueueProducer`] that compresses payloads before passing them on to the
+/// wrapped producer.
+///
+/// Attributes are sent as-is.
+#[derive(Debug)]
+pub struct CompressingProducer<P> {
+    inner: P,
+    config: CompressionConfig,
+}
+
+impl<P: QueueProducer> CompressingProducer<P> {
+    pub fn new(inner: P, config: CompressionConfig) -> Self {
+        Self { inner, config }
+    }
+
+    /// Returns the wrapped producer.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    /// Returns the compressed version of `payload`, or `None` if it should be
+    /// sent as-is.
+    fn compress_payload(&self, payload: &P::Payload) -> Result<Option<Box<P::Payload>>> {
+        let bytes = payload.to_bytes_naive()?;
+        compress(&self.config, &bytes, self.inner.payload_is_text())?
+            .map(|compressed| P::Payload::from_bytes_naive(&compressed))
+            .transpose()
+    }
+}
+
+impl<P: QueueProducer> QueueProducer for CompressingProducer<P> {
+    type Payload = P::Payload;
+
+    async fn send_raw(&self, payload: &Self::Payload) -> Result<()> {
+        match self.compress_payload(payload)? {
+            Some(compressed) => self.inner.send_raw(&compressed).await,
+            None => self.inner.send_raw(payload).await,
+        }
+    }
+
+    async fn send_raw_with_attributes(
+        &self,
+        payload: &Self::Payload,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        match self.compress_payload(payload)? {
+            Some(compressed) => {
+                self.inner
+                    .send_raw_with_attributes(&compressed, attributes)
+                    .await
+            }
+            None => {
+                self.inner
+                    .send_raw_with_attributes(payload, attributes)
+                    .await
+            }
+        }
+    }
+
+    async fn send_raw_batch(
+        &self,
+        payloads: impl IntoIterator<Item: AsRef<Self::Payload> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let payloads: Vec<_> = payloads
+            .into_iter()
+            .map(|payload| {
+                let payload = payload.as_ref();
+                Ok(match self.compress_payload(payload)? {
+                    Some(compressed) => compressed,
+                    None => P::Payload::from_bytes_naive(&payload.to_bytes_naive()?)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        self.inner.send_raw_batch(payloads).await
+    }
+
+    async fn redrive_dlq(&self) -> Result<()> {
+        self.inner.redrive_dlq().await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    fn payload_is_text(&self) -> bool {
+        self.inner.payload_is_text()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+}
+
+impl<P: ScheduledQueueProducer> ScheduledQueueProducer for CompressingProducer<P> {
+    async fn send_raw_scheduled(&self, payload: &Self::Payload, delay: Duration) -> Result<()> {
+        match self.compress_payload(payload)? {
+            Some(compressed) => self.inner.send_raw_scheduled(&compressed, delay).await,
+            None => self.inner.send_raw_scheduled(payload, delay).await,
+        }
+    }
+}
+
+/// A [`QueueConsumer`] that decompresses the payloads of deliveries received
+/// by the wrapped consumer.
+///
+/// Payloads that weren't compressed are passed through unchanged. Deliveries
+/// whose payload fails to decompress, or decompresses to more than
+/// [`max_decompressed_size`](Self::max_decompressed_size), are logged and
+/// returned with the compressed payload.
+#[derive(Debug)]
+pub struct DecompressingConsumer<C> {
+    inner: C,
+    max_decompressed_size: usize,
+}
+
+impl<C: QueueConsumer> DecompressingConsumer<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Sets the size in bytes that payloads may decompress to at most, which
+    /// protects consumers against small messages that decompress to huge
+    /// payloads.
+    ///
+    /// Default: 64 MiB.
+    pub fn max_decompressed_size(mut self, max_size: usize) -> Self {
+        self.max_decompressed_size = max_size;
+        self
+    }
+
+    /// Returns the wrapped consumer.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+fn decompress_delivery(max_size: usize, delivery: &mut Delivery) {
+    let decompressed = match delivery
+        .borrow_payload()
+        .map(|payload| decompress(payload, max_size))
+    {
+        Some(Ok(Cow::Owned(decompressed))) => decompressed,
+        Some(Ok(Cow::Borrowed(_))) | None => return,
+        Some(Err(e)) => {
+            warn!(
+                error = ?e,
+                metadata = ?delivery.metadata(),
+                "Failed to decompress payload, passing it on as-is"
+            );
+            return;
+        }
+    };
+    delivery.set_payload(decompressed);
+}
+
+impl<C: QueueConsumer> QueueConsumer for DecompressingConsumer<C> {
+    type Payload = C::Payload;
+
+    async fn receive(&mut self) -> Result<Delivery> {
+        let mut delivery = self.inner.receive().await?;
+        decompress_delivery(self.max_decompressed_size, &mut delivery);
+        Ok(delivery)
+    }
+
+    async fn receive_all(
+        &mut self,
+        max_messages: usize,
+        deadline: Duration,
+    ) -> Result<Vec<Delivery>> {
+        let mut deliveries = self.inner.receive_all(max_messages, deadline).await?;
+        for delivery in &mut deliveries {
+            decompress_delivery(self.max_decompressed_size, delivery);
+        }
+        Ok(deliveries)
+    }
+
+    fn max_messages(&self) -> Option<NonZeroUsize> {
+        self.inner.max_messages()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn health_check(&self) -> impl Future<Output = Result<()>> + Send {
+        // Not an `async fn`, as consumers don't have to be `Sync`
+        self.inner.health_check()
+    }
+
+    fn stats(&self) -> impl Future<Output = Result<QueueStats>> + Send {
+        self.inner.stats()
+    }
+
+    fn peek(&self, max_messages: usize) -> impl Future<Output = Result<Vec<Delivery>>> + Send {
+        let max_size = self.max_decompressed_size;
+        let peeked = self.inner.peek(max_messages);
+        async move {
+            let mut deliveries = peeked.await?;
+            for delivery in &mut deliveries {
+                decompress_delivery(max_size, delivery);
+            }
+            Ok(deliveries)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        compress, decompress, Algorithm, CompressionConfig, DEFAULT_MAX_DECOMPRESSED_SIZE,
+    };
+    #[cfg(feature = "in_memory")]
+    use crate::{backends::InMemoryBackend, QueueConsumer, QueueProducer};
+
+    fn algorithms() -> Vec<Algorithm> {
+        vec![
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip,
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd,
+        ]
+    }
+
+    fn large_payload() -> Vec<u8> {
+        br#"{"key":"value"}"#.repeat(200)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let payload = large_payload();
+        for algorithm in algorithms() {
+            for text in [false, true] {
+                let config = CompressionConfig::new(algorithm);
+                let compressed = compress(&config, &payload, text).unwrap().unwrap();
+                assert!(compressed.len() < payload.len());
+                if text {
+                    assert!(compressed.iter().all(u8::is_ascii_graphic));
+                }
+                assert_eq!(
+                    decompress(&compressed, DEFAULT_MAX_DECOMPRESSED_SIZE).unwrap(),
+                    payload.as_slice()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_threshold() {
+        let payload = large_payload();
+        for algorithm in algorithms() {
+            let config = CompressionConfig::new(algorithm).threshold(payload.len() + 1);
+            assert!(compress(&config, &payload, false).unwrap().is_none());
+
+            // Tiny payloads don't get smaller by compressing them
+            let config = CompressionConfig::new(algorithm).threshold(0);
+            assert!(compress(&config, b"{}", false).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_uncompressed_passthrough() {
+        let payload = br#"{"key":"value"}"#;
+        assert_eq!(
+            decompress(payload, DEFAULT_MAX_DECOMPRESSED_SIZE).unwrap(),
+            payload.as_slice()
+        );
+        assert!(decompress(b"~OQCz", DEFAULT_MAX_DECOMPRESSED_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_max_decompressed_size() {
+        let payload = vec![0; 1024 * 1024];
+        for algorithm in algorithms() {
+            let config = CompressionConfig::new(algorithm);
+            let compressed = compress(&config, &payload, false).unwrap().unwrap();
+            assert_eq!(
+                decompress(&compressed, payload.len()).unwrap(),
+                payload.as_slice()
+            );
+            assert!(decompress(&compressed, payload.len() - 1).is_err());
+        }
+    }
+
+    #[cfg(feature = "in_memory")]
+    #[tokio::test]
+    async fn test_producer_consumer() {
+        #[cfg(feature = "zstd")]
+        let algorithm = Algorithm::Zstd;
+        #[cfg(not(feature = "zstd"))]
+        let algorithm = Algorithm::Gzip;
+
+        use std::time::Duration;
+
+        use super::{CompressingProducer, DecompressingConsumer};
+
+        let (p, c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        let p = CompressingProducer::new(p, CompressionConfig::new(algorithm));
+        let mut c = DecompressingConsumer::new(c);
+
+        let payload = large_payload();
+        p.send_raw(&payload).await.unwrap();
+        p.send_raw(&b"small".to_vec()).await.unwrap();
+        // Messages from producers that don't compress yet are still accepted
+        p.into_inner().send_raw(&payload).await.unwrap();
+
+        let deliveries = c.receive_all(3, Duration::from_millis(100)).await.unwrap();
+        let payloads: Vec<_> = deliveries
+            .iter()
+            .map(|d| d.borrow_payload().unwrap())
+            .collect();
+        assert_eq!(payloads, [&payload[..], b"small", &payload[..]]);
+    }
+}
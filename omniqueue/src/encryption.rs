@@ -0,0 +1,556 @@
+//! Client-side payload encryption.
+//!
+//! [`EncryptingProducer`] encrypts payloads with the primary key of a
+//! [`Keyring`] before handing them to the wrapped producer, and
+//! [`DecryptingConsumer`] decrypts them again on receipt:
+//!
+//! ```ignore
+//! use omniqueue::encryption::{
+//!     Cipher, DecryptingConsumer, EncryptingProducer, Key, Keyring,
+//! };
+//!
+//! let keyring = Keyring::new(Key::new("2024-06", Cipher::Aes256Gcm, key_bytes)?);
+//! let (p, c) = SqsBackend::builder(cfg).make_dynamic().build_pair().await?;
+//! let p = EncryptingProducer::new(p, keyring.clone());
+//! let mut c = DecryptingConsumer::new(c, keyring);
+//! ```
+//!
+//! Each encrypted payload records the ID of the key it was encrypted with. To
+//! rotate keys, make the new key the primary one and keep the old keys in the
+//! keyring, so consumers can still decrypt messages that were sent before the
+//! rotation:
+//!
+//! ```ignore
+//! let keyring = Keyring::new(new_key).with_key(old_key);
+//! ```
+//!
+//! Only payloads are encrypted, attributes are sent as-is. For producers that
+//! can only send text (SQS and Azure Queue Storage), the encrypted data is
+//! base64-encoded.
+
+use std::{collections::HashMap, fmt, future::Future, num::NonZeroUsize, time::Duration};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use chacha20poly1305::ChaCha20Poly1305;
+use tracing::{error, warn};
+
+use crate::{
+    framing::{self, Kind},
+    layer::ProducerLayer,
+    Attributes, Capabilities, Delivery, QueueConsumer, QueueError, QueuePayload, QueueProducer,
+    QueueStats, Result, ScheduledQueueProducer,
+};
+
+const NONCE_LEN: usize = 12;
+
+/// An authenticated encryption algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Cipher {
+    /// AES-256 in Galois/Counter Mode.
+    Aes256Gcm,
+    /// ChaCha20-Poly1305, which is faster than AES-GCM on CPUs without AES
+    /// instructions.
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn id(self) -> u8 {
+        match self {
+            Self::Aes256Gcm => b'a',
+            Self::ChaCha20Poly1305 => b'c',
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            b'a' => Some(Self::Aes256Gcm),
+            b'c' => Some(Self::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum KeyCipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+/// A 256-bit encryption key, identified by an ID that is stored alongside the
+/// payloads it encrypts.
+#[derive(Clone)]
+pub struct Key {
+    id: String,
+    cipher: KeyCipher,
+}
+
+impl Key {
+    /// Creates a key for `cipher` from 32 bytes of key material.
+    ///
+    /// Fails if `id` is empty or longer than 255 bytes.
+    pub fn new(id: impl Into<String>, cipher: Cipher, key: [u8; 32]) -> Result<Self> {
+        let id = id.into();
+        if id.is_empty() || id.len() > u8::MAX.into() {
+            return Err(QueueError::Generic(
+                "encryption key IDs must be between 1 and 255 bytes long".into(),
+            ));
+        }
+
+        let cipher = match cipher {
+            Cipher::Aes256Gcm => KeyCipher::Aes256Gcm(Box::new(Aes256Gcm::new(&key.into()))),
+            Cipher::ChaCha20Poly1305 => {
+                KeyCipher::ChaCha20Poly1305(ChaCha20Poly1305::new(&key.into()))
+            }
+        };
+        Ok(Self { id, cipher })
+    }
+
+    /// The ID of this key.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The cipher this key is used with.
+    pub fn cipher(&self) -> Cipher {
+        match self.cipher {
+            KeyCipher::Aes256Gcm(_) => Cipher::Aes256Gcm,
+            KeyCipher::ChaCha20Poly1305(_) => Cipher::ChaCha20Poly1305,
+        }
+    }
+
+    /// The length-prefixed key ID, which starts the body of the payloads
+    /// encrypted with this key.
+    fn id_prefix(&self) -> Vec<u8> {
+        let mut prefix = vec![self.id.len() as u8];
+        prefix.extend_from_slice(self.id.as_bytes());
+        prefix
+    }
+
+    /// The additional authenticated data for payloads encrypted with this key,
+    /// which binds the ciphertext to its header and key ID.
+    fn aad(&self) -> Vec<u8> {
+        let mut aad = framing::header(Kind::Encrypted);
+        aad.push(self.cipher().id());
+        aad.extend_from_slice(&self.id_prefix());
+        aad
+    }
+
+    /// Encrypts `plaintext` with a random nonce, which is prepended to the
+    /// ciphertext.
+    fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        let ciphertext = match &self.cipher {
+            KeyCipher::Aes256Gcm(cipher) => cipher.encrypt(&nonce, payload),
+            KeyCipher::ChaCha20Poly1305(cipher) => cipher.encrypt(&nonce, payload),
+        }
+        .map_err(|_| QueueError::Generic("failed to encrypt payload".into()))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn decrypt(&self, nonce_and_ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let (nonce, ciphertext) = nonce_and_ciphertext.split_at(NONCE_LEN);
+        let nonce = Nonce::from_slice(nonce);
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        match &self.cipher {
+            KeyCipher::Aes256Gcm(cipher) => cipher.decrypt(nonce, payload),
+            KeyCipher::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce, payload),
+        }
+        .map_err(|_| {
+            QueueError::Generic(format!("failed to decrypt payload with key {:?}", self.id).into())
+        })
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("id", &self.id)
+            .field("cipher", &self.cipher())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The keys used to encrypt and decrypt payloads.
+///
+/// Payloads are encrypted with the primary key. Decryption uses whichever key
+/// a payload was encrypted with, which may be any key in the keyring.
+#[derive(Clone, Debug)]
+pub struct Keyring {
+    primary: Key,
+    keys: HashMap<String, Key>,
+}
+
+impl Keyring {
+    pub fn new(primary: Key) -> Self {
+        Self {
+            keys: HashMap::from([(primary.id.clone(), primary.clone())]),
+            primary,
+        }
+    }
+
+    /// Adds a key that is only used for decryption, such as a previous primary
+    /// key.
+    ///
+    /// A key with the same ID as the primary key is ignored.
+    pub fn with_key(mut self, key: Key) -> Self {
+        if key.id != self.primary.id {
+            self.keys.insert(key.id.clone(), key);
+        }
+        self
+    }
+
+    /// The ID of the key new payloads are encrypted with.
+    pub fn primary_key_id(&self) -> &str {
+        &self.primary.id
+    }
+
+    /// Encrypts `payload` with the primary key, prefixed by the encryption
+    /// header.
+    fn encrypt(&self, payload: &[u8], text: bool) -> Result<Vec<u8>> {
+        let key = &self.primary;
+        let body = [key.id_prefix(), key.encrypt(payload, &key.aad())?].concat();
+        Ok(framing::encode(
+            Kind::Encrypted,
+            key.cipher().id(),
+            &body,
+            text,
+        ))
+    }
+
+    /// Decrypts a payload produced by an [`EncryptingProducer`].
+    ///
+    /// Returns `Ok(None)` if the payload isn't encrypted.
+    pub fn decrypt(&self, payload: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some((cipher, body)) = framing::decode(Kind::Encrypted, payload)? else {
+            return Ok(None);
+        };
+        let invalid = || QueueError::Generic("invalid encrypted payload".into());
+
+        let cipher = Cipher::from_id(cipher).ok_or_else(invalid)?;
+        let [key_id_len, body @ ..] = &*body else {
+            return Err(invalid());
+        };
+        let key_id_len = usize::from(*key_id_len);
+        if body.len() < key_id_len + NONCE_LEN {
+            return Err(invalid());
+        }
+        let (key_id, nonce_and_ciphertext) = body.split_at(key_id_len);
+
+        let key_id = std::str::from_utf8(key_id).map_err(|_| invalid())?;
+        let key = self.keys.get(key_id).ok_or_else(|| {
+            QueueError::Generic(format!("unknown encryption key {key_id:?}").into())
+        })?;
+        if key.cipher() != cipher {
+            return Err(invalid());
+        }
+        key.decrypt(nonce_and_ciphertext, &key.aad()).map(Some)
+    }
+}
+
+/// Wraps producers in an [`EncryptingProducer`] with this keyring.
+impl<P: QueueProducer> ProducerLayer<P> for Keyring {
+    type Producer = EncryptingProducer<P>;
+
+    fn layer(&self, inner: P) -> Self::Producer {
+        EncryptingProducer::new(inner, self.clone())
+    }
+}
+
+/// A [`QueueProducer`] that encrypts payloads before passing them on to the
+/// wrapped producer.
+#[derive(Debug)]
+pub struct EncryptingProducer<P> {
+    inner: P,
+    keyring: Keyring,
+}
+
+impl<P: QueueProducer> EncryptingProducer<P> {
+    pub fn new(inner: P, keyring: Keyring) -> Self {
+        Self { inner, keyring }
+    }
+
+    /// Returns the wrapped producer.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    fn encrypt_payload(&self, payload: &P::Payload) -> Result<Box<P::Payload>> {
+        let bytes = payload.to_bytes_naive()?;
+        let encrypted = self.keyring.encrypt(&bytes, self.inner.payload_is_text())?;
+        P::Payload::from_bytes_naive(&encrypted)
+    }
+}
+
+impl<P: QueueProducer> QueueProducer for EncryptingProducer<P> {
+    type Payload = P::Payload;
+
+    async fn send_raw(&self, payload: &Self::Payload) -> Result<()> {
+        let payload = self.encrypt_payload(payload)?;
+        self.inner.send_raw(&payload).await
+    }
+
+    async fn send_raw_with_attributes(
+        &self,
+        payload: &Self::Payload,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = self.encrypt_payload(payload)?;
+        self.inner
+            .send_raw_with_attributes(&payload, attributes)
+            .await
+    }
+
+    async fn send_raw_batch(
+        &self,
+        payloads: impl IntoIterator<Item: AsRef<Self::Payload> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let payloads: Vec<_> = payloads
+            .into_iter()
+            .map(|payload| self.encrypt_payload(payload.as_ref()))
+            .collect::<Result<_>>()?;
+        self.inner.send_raw_batch(payloads).await
+    }
+
+    async fn redrive_dlq(&self) -> Result<()> {
+        self.inner.redrive_dlq().await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    fn payload_is_text(&self) -> bool {
+        self.inner.payload_is_text()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+}
+
+impl<P: ScheduledQueueProducer> ScheduledQueueProducer for EncryptingProducer<P> {
+    async fn send_raw_scheduled(&self, payload: &Self::Payload, delay: Duration) -> Result<()> {
+        let payload = self.encrypt_payload(payload)?;
+        self.inner.send_raw_scheduled(&payload, delay).await
+    }
+}
+
+/// A [`QueueConsumer`] that decrypts the payloads of deliveries received by
+/// the wrapped consumer.
+///
+/// Undecryptable payloads don't fail the receive: the error is logged and the
+/// delivery is returned without its payload. By default, the same happens to
+/// payloads that weren't encrypted at all; see
+/// [`allow_plaintext`](Self::allow_plaintext).
+#[derive(Debug)]
+pub struct DecryptingConsumer<C> {
+    inner: C,
+    keyring: Keyring,
+    allow_plaintext: bool,
+}
+
+impl<C: QueueConsumer> DecryptingConsumer<C> {
+    pub fn new(inner: C, keyring: Keyring) -> Self {
+        Self {
+            inner,
+            keyring,
+            allow_plaintext: false,
+        }
+    }
+
+    /// Passes payloads that weren't encrypted through unchanged.
+    ///
+    /// This allows enabling encryption on producers after their consumers have
+    /// been upgraded, without draining the queue first.
+    pub fn allow_plaintext(mut self, allow: bool) -> Self {
+        self.allow_plaintext = allow;
+        self
+    }
+
+    /// Returns the wrapped consumer.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+/// Decrypts the payload of `delivery`, discarding it if it can't be decrypted.
+fn decrypt_delivery(keyring: &Keyring, allow_plaintext: bool, delivery: &mut Delivery) {
+    let Some(payload) = delivery.borrow_payload() else {
+        return;
+    };
+    match keyring.decrypt(payload) {
+        Ok(Some(decrypted)) => delivery.set_payload(decrypted),
+        Ok(None) if allow_plaintext => {}
+        Ok(None) => {
+            warn!(
+                metadata = ?delivery.metadata(),
+                "Received unencrypted payload, discarding it"
+            );
+            delivery.take_payload();
+        }
+        Err(e) => {
+            error!(
+                error = ?e,
+                metadata = ?delivery.metadata(),
+                "Failed to decrypt payload, discarding it"
+            );
+            delivery.take_payload();
+        }
+    }
+}
+
+impl<C: QueueConsumer> QueueConsumer for DecryptingConsumer<C> {
+    type Payload = C::Payload;
+
+    async fn receive(&mut self) -> Result<Delivery> {
+        let mut delivery = self.inner.receive().await?;
+        decrypt_delivery(&self.keyring, self.allow_plaintext, &mut delivery);
+        Ok(delivery)
+    }
+
+    async fn receive_all(
+        &mut self,
+        max_messages: usize,
+        deadline: Duration,
+    ) -> Result<Vec<Delivery>> {
+        let mut deliveries = self.inner.receive_all(max_messages, deadline).await?;
+        for delivery in &mut deliveries {
+            decrypt_delivery(&self.keyring, self.allow_plaintext, delivery);
+        }
+        Ok(deliveries)
+    }
+
+    fn max_messages(&self) -> Option<NonZeroUsize> {
+        self.inner.max_messages()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn health_check(&self) -> impl Future<Output = Result<()>> + Send {
+        // Not an `async fn`, as consumers don't have to be `Sync`
+        self.inner.health_check()
+    }
+
+    fn stats(&self) -> impl Future<Output = Result<QueueStats>> + Send {
+        self.inner.stats()
+    }
+
+    fn peek(&self, max_messages: usize) -> impl Future<Output = Result<Vec<Delivery>>> + Send {
+        let (keyring, allow_plaintext) = (&self.keyring, self.allow_plaintext);
+        let peeked = self.inner.peek(max_messages);
+        async move {
+            let mut deliveries = peeked.await?;
+            for delivery in &mut deliveries {
+                decrypt_delivery(keyring, allow_plaintext, delivery);
+            }
+            Ok(deliveries)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cipher, Key, Keyring};
+
+    fn key(id: &str, cipher: Cipher) -> Key {
+        Key::new(id, cipher, [id.len() as u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let payload = br#"{"key":"value"}"#;
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let keyring = Keyring::new(key("k1", cipher));
+            for text in [false, true] {
+                let encrypted = keyring.encrypt(payload, text).unwrap();
+                assert_ne!(encrypted, payload);
+                if text {
+                    assert!(encrypted.iter().all(u8::is_ascii_graphic));
+                }
+                assert_eq!(keyring.decrypt(&encrypted).unwrap().unwrap(), payload);
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = Keyring::new(key("old", Cipher::Aes256Gcm));
+        let encrypted = old.encrypt(b"payload", false).unwrap();
+
+        let new = Keyring::new(key("new-key", Cipher::ChaCha20Poly1305))
+            .with_key(key("old", Cipher::Aes256Gcm));
+        assert_eq!(new.primary_key_id(), "new-key");
+        assert_eq!(new.decrypt(&encrypted).unwrap().unwrap(), b"payload");
+
+        // Once the old key is dropped, its payloads can't be decrypted anymore
+        let newest = Keyring::new(key("new-key", Cipher::ChaCha20Poly1305));
+        assert!(newest.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_tampering() {
+        let keyring = Keyring::new(key("k1", Cipher::Aes256Gcm));
+        let mut encrypted = keyring.encrypt(b"payload", false).unwrap();
+        *encrypted.last_mut().unwrap() ^= 1;
+        assert!(keyring.decrypt(&encrypted).is_err());
+
+        // Claiming a different cipher fails too
+        let mut encrypted = keyring.encrypt(b"payload", false).unwrap();
+        encrypted[4] = b'c';
+        assert!(keyring.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_plaintext() {
+        let keyring = Keyring::new(key("k1", Cipher::Aes256Gcm));
+        assert_eq!(keyring.decrypt(b"payload").unwrap(), None);
+        assert!(Key::new("", Cipher::Aes256Gcm, [0; 32]).is_err());
+    }
+
+    #[cfg(feature = "in_memory")]
+    #[tokio::test]
+    async fn test_producer_consumer() {
+        use std::time::Duration;
+
+        use super::{DecryptingConsumer, EncryptingProducer};
+        use crate::{backends::InMemoryBackend, QueueConsumer, QueueProducer};
+
+        let (p, c) = InMemoryBackend::builder()
+            .make_dynamic()
+            .build_pair()
+            .await
+            .unwrap();
+        let keyring = Keyring::new(key("k1", Cipher::ChaCha20Poly1305));
+        let p = EncryptingProducer::new(p, keyring.clone());
+        let mut c = DecryptingConsumer::new(c, keyring);
+
+        p.send_serde_json(&"secret").await.unwrap();
+        let p = p.into_inner();
+        p.send_raw(b"plain").await.unwrap();
+
+        let deliveries = c.receive_all(2, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(
+            deliveries[0].payload_serde_json::<String>().unwrap(),
+            Some("secret".to_owned())
+        );
+        assert_eq!(deliveries[1].borrow_payload(), None);
+
+        let mut c = c.allow_plaintext(true);
+        p.send_raw(b"plain").await.unwrap();
+        let delivery = c.receive().await.unwrap();
+        assert_eq!(delivery.borrow_payload(), Some(&b"plain"[..]));
+    }
+}
@@ -0,0 +1,109 @@
+//! The header that the compression, encryption and claim-check wrappers put in
+//! front of the payloads they produce.
+//!
+//! A header is [`PREFIX`], a byte for the [`Kind`] of payload and, for
+//! [`encode`]d payloads, a kind-specific tag byte and an encoding byte. All of
+//! these are printable ASCII, and the data following the header is
+//! base64-encoded for producers that can only send text, so framed payloads
+//! are valid for SQS and Azure Queue Storage. A `~` can't start a valid JSON
+//! document, so framed payloads can be told apart from JSON payloads.
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+use std::borrow::Cow;
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+use base64::Engine as _;
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+use crate::{QueueError, Result};
+
+const PREFIX: &[u8] = b"~OQ";
+
+/// The data following the header is stored as-is.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+const ENCODING_BINARY: u8 = b'b';
+/// The data following the header is stored as base64.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+const ENCODING_BASE64: u8 = b'6';
+
+/// What a framed payload holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    Compressed,
+    #[cfg(feature = "encryption")]
+    Encrypted,
+    #[cfg(feature = "claim_check")]
+    Reference,
+}
+
+impl Kind {
+    fn id(self) -> u8 {
+        match self {
+            #[cfg(any(feature = "gzip", feature = "zstd"))]
+            Self::Compressed => b'C',
+            #[cfg(feature = "encryption")]
+            Self::Encrypted => b'E',
+            #[cfg(feature = "claim_check")]
+            Self::Reference => b'B',
+        }
+    }
+}
+
+/// Returns the header that marks a payload of the given kind.
+pub(crate) fn header(kind: Kind) -> Vec<u8> {
+    let mut header = PREFIX.to_vec();
+    header.push(kind.id());
+    header
+}
+
+/// Returns the rest of `payload` if it starts with the header for `kind`.
+pub(crate) fn strip_header(kind: Kind, payload: &[u8]) -> Option<&[u8]> {
+    match payload.strip_prefix(PREFIX)? {
+        [id, rest @ ..] if *id == kind.id() => Some(rest),
+        _ => None,
+    }
+}
+
+/// Frames `data` as a payload of the given kind, with `tag` identifying how
+/// the data was produced.
+///
+/// If `text` is set, the data is base64-encoded.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+pub(crate) fn encode(kind: Kind, tag: u8, data: &[u8], text: bool) -> Vec<u8> {
+    let mut out = header(kind);
+    out.push(tag);
+    if text {
+        out.push(ENCODING_BASE64);
+        let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+        out.extend_from_slice(encoded.as_bytes());
+    } else {
+        out.push(ENCODING_BINARY);
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+/// Returns the tag and the data of a payload framed by [`encode`].
+///
+/// Returns `Ok(None)` if the payload isn't framed as the given kind.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+pub(crate) fn decode(kind: Kind, payload: &[u8]) -> Result<Option<(u8, Cow<'_, [u8]>)>> {
+    let Some(rest) = strip_header(kind, payload) else {
+        return Ok(None);
+    };
+    let [tag, encoding, data @ ..] = rest else {
+        return Err(QueueError::Generic("truncated payload header".into()));
+    };
+
+    let data = match *encoding {
+        ENCODING_BINARY => Cow::Borrowed(data),
+        ENCODING_BASE64 => Cow::Owned(
+            base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .map_err(QueueError::generic)?,
+        ),
+        _ => return Err(QueueError::Generic("unknown payload encoding".into())),
+    };
+    Ok(Some((*tag, data)))
+}
@@ -0,0 +1,235 @@
+//! Middleware for consumers.
+//!
+//! A [`ConsumerInterceptor`] sees every [`Delivery`] a consumer receives, and
+//! is told how and when each of them was acked or nacked:
+//!
+//! ```no_run
+//! # async {
+//! use omniqueue::{
+//!     backends::InMemoryBackend,
+//!     intercept::{ConsumerInterceptor, SettleEvent},
+//!     Delivery, QueueConsumer as _,
+//! };
+//!
+//! struct Metrics;
+//!
+//! impl ConsumerInterceptor for Metrics {
+//!     fn on_receive(&self, delivery: &mut Delivery) {
+//!         println!("received {:?}", delivery.metadata().message_id);
+//!     }
+//!
+//!     fn on_settle(&self, event: &SettleEvent<'_>) {
+//!         println!("{:?} after {:?}", event.settlement, event.held_for);
+//!     }
+//! }
+//!
+//! let mut c = InMemoryBackend::builder()
+//!     .build_consumer()
+//!     .await?
+//!     .with_interceptor(Metrics);
+//! # anyhow::Ok(())
+//! # };
+//! ```
+
+use std::{future::Future, num::NonZeroUsize, sync::Arc, time::Duration};
+
+use crate::{
+    queue::InterceptingAcker, Capabilities, Delivery, DeliveryMetadata, DynConsumer, QueueConsumer,
+    QueueError, QueueStats, Result,
+};
+
+/// Observes and transforms the deliveries received by a consumer.
+///
+/// All methods have empty default implementations.
+pub trait ConsumerInterceptor: Send + Sync + 'static {
+    /// Called with every delivery after it was received.
+    ///
+    /// This can inspect the delivery, or transform its payload with
+    /// [`Delivery::take_payload`] and [`Delivery::set_payload`].
+    fn on_receive(&self, delivery: &mut Delivery) {
+        let _ = delivery;
+    }
+
+    /// Called after a delivery was acked or nacked, whether that succeeded or
+    /// not.
+    fn on_settle(&self, event: &SettleEvent<'_>) {
+        let _ = event;
+    }
+}
+
+/// How a delivery was settled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Settlement {
+    /// [`Delivery::ack`]
+    Ack,
+    /// [`Delivery::nack`]
+    Nack,
+    /// [`Delivery::nack_with_delay`], with the requested delay.
+    NackWithDelay(Duration),
+}
+
+/// Information about an ack or nack, passed to
+/// [`ConsumerInterceptor::on_settle`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SettleEvent<'a> {
+    /// Whether the delivery was acked or nacked.
+    pub settlement: Settlement,
+    /// The metadata of the delivery.
+    pub metadata: &'a DeliveryMetadata,
+    /// The result of the ack or nack.
+    pub result: Result<(), &'a QueueError>,
+    /// How long the ack or nack took.
+    pub latency: Duration,
+    /// How long the delivery was held between being received and being acked
+    /// or nacked, which includes `latency`.
+    pub held_for: Duration,
+}
+
+/// A [`QueueConsumer`] that runs a [`ConsumerInterceptor`] on the deliveries
+/// received by the wrapped consumer.
+///
+/// Created with [`QueueConsumer::with_interceptor`].
+#[derive(Debug)]
+pub struct Intercepted<C, I> {
+    inner: C,
+    interceptor: Arc<I>,
+}
+
+impl<C: QueueConsumer, I: ConsumerInterceptor> Intercepted<C, I> {
+    pub fn new(inner: C, interceptor: I) -> Self {
+        Self {
+            inner,
+            interceptor: Arc::new(interceptor),
+        }
+    }
+
+    /// Returns the wrapped consumer.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+fn intercept<I: ConsumerInterceptor>(interceptor: &Arc<I>, mut delivery: Delivery) -> Delivery {
+    interceptor.on_receive(&mut delivery);
+    let metadata = delivery.metadata().clone();
+    let interceptor = interceptor.clone();
+    delivery.wrap_acker(|inner| InterceptingAcker::new(inner, interceptor, metadata))
+}
+
+impl<C: QueueConsumer, I: ConsumerInterceptor> QueueConsumer for Intercepted<C, I> {
+    type Payload = C::Payload;
+
+    async fn receive(&mut self) -> Result<Delivery> {
+        let delivery = self.inner.receive().await?;
+        Ok(intercept(&self.interceptor, delivery))
+    }
+
+    async fn receive_all(
+        &mut self,
+        max_messages: usize,
+        deadline: Duration,
+    ) -> Result<Vec<Delivery>> {
+        let deliveries = self.inner.receive_all(max_messages, deadline).await?;
+        Ok(deliveries
+            .into_iter()
+            .map(|delivery| intercept(&self.interceptor, delivery))
+            .collect())
+    }
+
+    fn max_messages(&self) -> Option<NonZeroUsize> {
+        self.inner.max_messages()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn health_check(&self) -> impl Future<Output = Result<()>> + Send {
+        // Not an `async fn`, as consumers don't have to be `Sync`
+        self.inner.health_check()
+    }
+
+    fn stats(&self) -> impl Future<Output = Result<QueueStats>> + Send {
+        self.inner.stats()
+    }
+
+    fn peek(&self, max_messages: usize) -> impl Future<Output = Result<Vec<Delivery>>> + Send {
+        self.inner.peek(max_messages)
+    }
+}
+
+impl DynConsumer {
+    /// Runs `interceptor` on the deliveries received by this consumer.
+    ///
+    /// See [`QueueConsumer::with_interceptor`].
+    pub fn with_interceptor(self, interceptor: impl ConsumerInterceptor) -> DynConsumer {
+        Intercepted::new(self, interceptor).into_dyn()
+    }
+}
+
+#[cfg(all(test, feature = "in_memory"))]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{ConsumerInterceptor, SettleEvent, Settlement};
+    use crate::{backends::InMemoryBackend, Delivery, QueueConsumer};
+
+    #[derive(Default)]
+    struct Recorder {
+        settlements: Mutex<Vec<(Settlement, bool)>>,
+    }
+
+    impl ConsumerInterceptor for Arc<Recorder> {
+        fn on_receive(&self, delivery: &mut Delivery) {
+            let mut payload = delivery.take_payload().unwrap();
+            payload.make_ascii_uppercase();
+            delivery.set_payload(payload);
+        }
+
+        fn on_settle(&self, event: &SettleEvent<'_>) {
+            assert!(event.held_for >= event.latency);
+            self.settlements
+                .lock()
+                .unwrap()
+                .push((event.settlement, event.result.is_ok()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interceptor() {
+        let recorder = Arc::new(Recorder::default());
+        let (p, c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        let mut c = c.into_dyn().with_interceptor(recorder.clone());
+
+        p.send_raw(b"one").await.unwrap();
+        p.send_raw(b"two").await.unwrap();
+
+        let mut deliveries = c
+            .receive_all(2, Duration::from_millis(100))
+            .await
+            .unwrap()
+            .into_iter();
+        let one = deliveries.next().unwrap();
+        assert_eq!(one.borrow_payload(), Some(&b"ONE"[..]));
+        one.ack().await.unwrap();
+        deliveries
+            .next()
+            .unwrap()
+            .nack_with_delay(Duration::from_millis(1))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *recorder.settlements.lock().unwrap(),
+            [
+                (Settlement::Ack, true),
+                (Settlement::NackWithDelay(Duration::from_millis(1)), true)
+            ]
+        );
+    }
+}
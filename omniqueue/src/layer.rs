@@ -0,0 +1,350 @@
+//! Middleware for producers.
+//!
+//! A [`ProducerLayer`] wraps a producer in another one that adds some
+//! behavior, like a [tower](https://docs.rs/tower) `Layer` does for services.
+//! Layers are applied to the producers built by a [`QueueBuilder`] with
+//! [`QueueBuilder::layer`], or to any producer with [`ProducerLayer::layer`]:
+//!
+//! ```no_run
+//! # async {
+//! use omniqueue::{backends::InMemoryBackend, layer::before_send, QueueError};
+//!
+//! let p = InMemoryBackend::builder()
+//!     .layer(before_send(|payload: &[u8], attributes: &mut omniqueue::Attributes| {
+//!         if payload.is_empty() {
+//!             return Err(QueueError::Generic("empty payload".into()));
+//!         }
+//!         attributes.insert("producer".to_owned(), "billing".to_owned());
+//!         Ok(())
+//!     }))
+//!     .make_dynamic()
+//!     .build_producer()
+//!     .await?;
+//! # anyhow::Ok(())
+//! # };
+//! ```
+//!
+//! The producer wrappers in this crate have layers as well: the compression
+//! config, the encryption keyring and the claim-check layer.
+//!
+//! [`QueueBuilder`]: crate::QueueBuilder
+//! [`QueueBuilder::layer`]: crate::QueueBuilder::layer
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    Attributes, Capabilities, QueueError, QueuePayload, QueueProducer, Result,
+    ScheduledQueueProducer,
+};
+
+/// Wraps a producer of type `P` in another producer.
+///
+/// The wrapping producer should forward all [`QueueProducer`] methods that it
+/// doesn't change, including [`send_raw_batch`](QueueProducer::send_raw_batch),
+/// [`payload_is_text`](QueueProducer::payload_is_text) and
+/// [`capabilities`](QueueProducer::capabilities), and implement
+/// [`ScheduledQueueProducer`] if `P` does.
+pub trait ProducerLayer<P> {
+    /// The wrapping producer.
+    type Producer: QueueProducer;
+
+    /// Wraps `inner`.
+    fn layer(&self, inner: P) -> Self::Producer;
+}
+
+/// A layer that returns the producer unchanged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Identity;
+
+impl<P: QueueProducer> ProducerLayer<P> for Identity {
+    type Producer = P;
+
+    fn layer(&self, inner: P) -> P {
+        inner
+    }
+}
+
+/// Two layers applied one after the other, with `Outer` wrapping the producer
+/// returned by `Inner`.
+#[derive(Clone, Debug)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<Inner, Outer> Stack<Inner, Outer> {
+    pub fn new(inner: Inner, outer: Outer) -> Self {
+        Self { inner, outer }
+    }
+}
+
+impl<P, Inner, Outer> ProducerLayer<P> for Stack<Inner, Outer>
+where
+    Inner: ProducerLayer<P>,
+    Outer: ProducerLayer<Inner::Producer>,
+{
+    type Producer = Outer::Producer;
+
+    fn layer(&self, inner: P) -> Self::Producer {
+        self.outer.layer(self.inner.layer(inner))
+    }
+}
+
+/// Returns a layer that wraps producers using `f`.
+pub fn layer_fn<F>(f: F) -> LayerFn<F> {
+    LayerFn { f }
+}
+
+/// A layer created with [`layer_fn`].
+#[derive(Clone, Copy, Debug)]
+pub struct LayerFn<F> {
+    f: F,
+}
+
+impl<P, F, Q> ProducerLayer<P> for LayerFn<F>
+where
+    F: Fn(P) -> Q,
+    Q: QueueProducer,
+{
+    type Producer = Q;
+
+    fn layer(&self, inner: P) -> Q {
+        (self.f)(inner)
+    }
+}
+
+/// Returns a layer that calls `f` with the payload and attributes of every
+/// message before it is sent.
+///
+/// `f` can add or change attributes, and prevent the message from being sent
+/// by returning an error. This covers things like validation, metrics and
+/// injecting headers.
+///
+/// Batches are only sent as a batch if `f` leaves the attributes of all of
+/// their messages empty, and otherwise sent one message at a time. Scheduled
+/// messages fail with [`QueueError::Unsupported`] if `f` adds attributes to
+/// them, since they can't be sent with attributes.
+pub fn before_send<F>(f: F) -> BeforeSendLayer<F>
+where
+    F: Fn(&[u8], &mut Attributes) -> Result<()> + Send + Sync + 'static,
+{
+    BeforeSendLayer { f: Arc::new(f) }
+}
+
+/// A layer created with [`before_send`].
+#[derive(Debug)]
+pub struct BeforeSendLayer<F> {
+    f: Arc<F>,
+}
+
+impl<F> Clone for BeforeSendLayer<F> {
+    fn clone(&self) -> Self {
+        Self { f: self.f.clone() }
+    }
+}
+
+impl<P, F> ProducerLayer<P> for BeforeSendLayer<F>
+where
+    P: QueueProducer,
+    F: Fn(&[u8], &mut Attributes) -> Result<()> + Send + Sync + 'static,
+{
+    type Producer = BeforeSend<P, F>;
+
+    fn layer(&self, inner: P) -> Self::Producer {
+        BeforeSend {
+            inner,
+            f: self.f.clone(),
+        }
+    }
+}
+
+/// The producer returned by [`BeforeSendLayer`].
+#[derive(Debug)]
+pub struct BeforeSend<P, F> {
+    inner: P,
+    f: Arc<F>,
+}
+
+impl<P, F> BeforeSend<P, F>
+where
+    P: QueueProducer,
+    F: Fn(&[u8], &mut Attributes) -> Result<()> + Send + Sync + 'static,
+{
+    /// Returns the wrapped producer.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    fn run(&self, payload: &P::Payload, attributes: &Attributes) -> Result<Attributes> {
+        let mut attributes = attributes.clone();
+        (self.f)(&payload.to_bytes_naive()?, &mut attributes)?;
+        Ok(attributes)
+    }
+}
+
+impl<P, F> QueueProducer for BeforeSend<P, F>
+where
+    P: QueueProducer,
+    F: Fn(&[u8], &mut Attributes) -> Result<()> + Send + Sync + 'static,
+{
+    type Payload = P::Payload;
+
+    async fn send_raw(&self, payload: &Self::Payload) -> Result<()> {
+        let attributes = self.run(payload, &Attributes::new())?;
+        if attributes.is_empty() {
+            self.inner.send_raw(payload).await
+        } else {
+            self.inner
+                .send_raw_with_attributes(payload, &attributes)
+                .await
+        }
+    }
+
+    async fn send_raw_with_attributes(
+        &self,
+        payload: &Self::Payload,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let attributes = self.run(payload, attributes)?;
+        self.inner
+            .send_raw_with_attributes(payload, &attributes)
+            .await
+    }
+
+    async fn send_raw_batch(
+        &self,
+        payloads: impl IntoIterator<Item: AsRef<Self::Payload> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let payloads: Vec<_> = payloads.into_iter().collect();
+        let attributes = payloads
+            .iter()
+            .map(|payload| self.run(payload.as_ref(), &Attributes::new()))
+            .collect::<Result<Vec<_>>>()?;
+
+        if attributes.iter().all(Attributes::is_empty) {
+            return self.inner.send_raw_batch(payloads).await;
+        }
+        for (payload, attributes) in payloads.into_iter().zip(attributes) {
+            self.inner
+                .send_raw_with_attributes(payload.as_ref(), &attributes)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn redrive_dlq(&self) -> Result<()> {
+        self.inner.redrive_dlq().await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    fn payload_is_text(&self) -> bool {
+        self.inner.payload_is_text()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+}
+
+impl<P, F> ScheduledQueueProducer for BeforeSend<P, F>
+where
+    P: ScheduledQueueProducer,
+    F: Fn(&[u8], &mut Attributes) -> Result<()> + Send + Sync + 'static,
+{
+    async fn send_raw_scheduled(&self, payload: &Self::Payload, delay: Duration) -> Result<()> {
+        let attributes = self.run(payload, &Attributes::new())?;
+        if !attributes.is_empty() {
+            return Err(QueueError::Unsupported(
+                "scheduled messages can't be sent with attributes",
+            ));
+        }
+        self.inner.send_raw_scheduled(payload, delay).await
+    }
+}
+
+#[cfg(all(test, feature = "in_memory"))]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::{before_send, layer_fn, BeforeSendLayer, ProducerLayer, Stack};
+    use crate::{
+        backends::InMemoryBackend, Attributes, DynProducer, QueueError, QueueProducer, Result,
+    };
+
+    #[tokio::test]
+    async fn test_before_send() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let layer = before_send({
+            let sent = sent.clone();
+            move |payload: &[u8], attributes: &mut Attributes| {
+                if payload.is_empty() {
+                    return Err(QueueError::Generic("empty payload".into()));
+                }
+                sent.fetch_add(1, Ordering::Relaxed);
+                attributes.insert("layer".to_owned(), "1".to_owned());
+                Ok(())
+            }
+        });
+
+        let (p, mut c) = InMemoryBackend::builder()
+            .layer(layer)
+            .build_pair()
+            .await
+            .unwrap();
+
+        assert!(p.send_raw(&vec![]).await.is_err());
+        p.send_raw(&b"one".to_vec()).await.unwrap();
+        p.send_raw_batch([b"two".to_vec(), b"three".to_vec()])
+            .await
+            .unwrap();
+        assert_eq!(sent.load(Ordering::Relaxed), 3);
+
+        let deliveries = c.receive_all(3, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(deliveries.len(), 3);
+        for delivery in &deliveries {
+            assert_eq!(delivery.attributes()["layer"], "1");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stack_order() {
+        fn tag(
+            name: &'static str,
+        ) -> BeforeSendLayer<impl Fn(&[u8], &mut Attributes) -> Result<()> + Send + Sync> {
+            before_send(move |_: &[u8], attributes: &mut Attributes| {
+                let order = attributes.entry("order".to_owned()).or_default();
+                order.push_str(name);
+                Ok(())
+            })
+        }
+
+        let (p, mut c) = InMemoryBackend::builder()
+            .layer(tag("a"))
+            .layer(tag("b"))
+            .make_dynamic()
+            .build_pair()
+            .await
+            .unwrap();
+        p.send_raw(b"payload").await.unwrap();
+        let delivery = c.receive().await.unwrap();
+        // The layer added first is the outermost one, so it runs first
+        assert_eq!(delivery.attributes()["order"], "ab");
+
+        let stack = Stack::new(tag("inner"), tag("outer"));
+        let stack = Stack::new(stack, layer_fn(QueueProducer::into_dyn));
+        let (p, mut c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        let p: DynProducer = stack.layer(p);
+        p.send_raw(b"payload").await.unwrap();
+        let delivery = c.receive().await.unwrap();
+        assert_eq!(delivery.attributes()["order"], "outerinner");
+    }
+}
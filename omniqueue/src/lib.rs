@@ -19,6 +19,20 @@
 //! * Amazon SQS
 //! * Azure Queue Storage
 //!
+//! Besides JSON, payloads can be encoded with any of the [`codec`]s. The binary
+//! ones are enabled by the `msgpack`, `cbor`, `bincode` and `protobuf`
+//! features.
+//!
+//! Payloads can also be compressed transparently with the wrappers in
+//! `compression`, enabled by the `gzip` and `zstd` features, and encrypted with
+//! the wrappers in `encryption`, enabled by the `encryption` feature. Payloads
+//! that are too large for a queue can be stored elsewhere with the wrappers in
+//! `claim_check` (feature `claim_check`). Trace context can be propagated
+//! through messages with the wrappers in `otel` (feature `otel`).
+//!
+//! With the `metrics` feature, the backends record metrics about the messages
+//! they send and receive, which are listed in `metrics`.
+//!
 //! ## How to Use Omniqueue
 //!
 //! Each queue backend has a unique configuration type. One of these
@@ -83,9 +97,12 @@
 //! # anyhow::Ok(())
 //! # };
 //! ```
+//!
+//! For backends that support delayed messages, `build_scheduled_pair` and
+//! `build_scheduled_producer` return a `DynScheduledProducer` instead.
 #![warn(unreachable_pub)]
 
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 
 use bytesize::ByteSize;
 use thiserror::Error;
@@ -95,13 +112,40 @@ mod macros;
 
 pub mod backends;
 pub mod builder;
+#[cfg(feature = "claim_check")]
+pub mod claim_check;
+pub mod codec;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub mod compression;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(any(
+    feature = "gzip",
+    feature = "zstd",
+    feature = "encryption",
+    feature = "claim_check"
+))]
+mod framing;
+pub mod intercept;
+pub mod layer;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(not(feature = "metrics"))]
+mod metrics;
+#[cfg(feature = "otel")]
+pub mod otel;
 mod queue;
+pub mod retry;
 mod scheduled;
+pub mod worker;
 
 #[allow(deprecated)]
 pub use self::{
     builder::QueueBuilder,
-    queue::{Delivery, DynConsumer, DynProducer, QueueBackend, QueueConsumer, QueueProducer},
+    queue::{
+        Attributes, Capabilities, Delivery, DeliveryMetadata, DynConsumer, DynProducer,
+        MessageOrdering, QueueAdmin, QueueBackend, QueueConsumer, QueueProducer, QueueStats,
+    },
     scheduled::{DynScheduledProducer, ScheduledQueueProducer},
 };
 
@@ -137,12 +181,106 @@ pub enum QueueError {
 
     #[error("{0}")]
     Unsupported(&'static str),
+
+    #[error("{backend} {operation} failed ({kind}): {source}")]
+    Backend {
+        /// What kind of failure this is.
+        kind: ErrorKind,
+
+        /// The backend the error originated from, such as `"redis"`.
+        backend: &'static str,
+
+        /// The operation that failed, such as `"send"` or `"ack"`.
+        operation: &'static str,
+
+        /// The backend's original error.
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
 }
 
 impl QueueError {
     pub fn generic<E: std::error::Error + Send + Sync + 'static>(e: E) -> Self {
         Self::Generic(Box::new(e))
     }
+
+    /// Creates a [`QueueError::Backend`], for use by custom backends.
+    pub fn backend(
+        kind: ErrorKind,
+        backend: &'static str,
+        operation: &'static str,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        Self::Backend {
+            kind,
+            backend,
+            operation,
+            source: source.into(),
+        }
+    }
+
+    /// Classifies this error.
+    ///
+    /// Errors that didn't originate from a backend, or that the backend
+    /// couldn't classify, are [`ErrorKind::Other`].
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Backend { kind, .. } => *kind,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Whether retrying the failed operation later might succeed.
+    ///
+    /// See [`ErrorKind::is_transient`].
+    pub fn is_transient(&self) -> bool {
+        self.kind().is_transient()
+    }
+}
+
+/// A classification of backend errors, as returned by [`QueueError::kind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The connection to the backend was lost or couldn't be established.
+    ConnectionLost,
+    /// The backend didn't respond in time.
+    Timeout,
+    /// The backend rejected the request because of rate limits or quotas.
+    Throttled,
+    /// The credentials were rejected or lack the required permissions.
+    AuthFailure,
+    /// The queue, topic or subscription doesn't exist.
+    QueueNotFound,
+    /// The receipt handle, ack ID or similar used to settle a delivery is
+    /// invalid or has expired.
+    InvalidReceiptHandle,
+    /// Any other error.
+    Other,
+}
+
+impl ErrorKind {
+    /// Whether errors of this kind are usually temporary, i.e. whether
+    /// retrying the failed operation later might succeed.
+    ///
+    /// This is the case for [`ConnectionLost`](Self::ConnectionLost),
+    /// [`Timeout`](Self::Timeout) and [`Throttled`](Self::Throttled).
+    pub fn is_transient(self) -> bool {
+        matches!(self, Self::ConnectionLost | Self::Timeout | Self::Throttled)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::ConnectionLost => "connection lost",
+            Self::Timeout => "timeout",
+            Self::Throttled => "throttled",
+            Self::AuthFailure => "authentication failure",
+            Self::QueueNotFound => "queue not found",
+            Self::InvalidReceiptHandle => "invalid receipt handle",
+            Self::Other => "other",
+        })
+    }
 }
 
 pub trait QueuePayload: Send + Sync + 'static {
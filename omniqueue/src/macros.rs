@@ -33,6 +33,26 @@ macro_rules! omni_delegate {
             Self::send_serde_json(self, payload)
         }
     };
+    ( send_raw_with_attributes ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn send_raw_with_attributes(
+            &self,
+            payload: &Self::Payload,
+            attributes: &crate::Attributes,
+        ) -> impl std::future::Future<Output = Result<()>> + Send {
+            Self::send_raw_with_attributes(self, payload, attributes)
+        }
+    };
+    ( send_serde_json_with_attributes ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn send_serde_json_with_attributes<P: serde::Serialize + Sync>(
+            &self,
+            payload: &P,
+            attributes: &crate::Attributes,
+        ) -> impl std::future::Future<Output = Result<()>> + Send {
+            Self::send_serde_json_with_attributes(self, payload, attributes)
+        }
+    };
     ( send_raw_scheduled ) => {
         #[deny(unconditional_recursion)] // method call must defer to an inherent method
         fn send_raw_scheduled(
@@ -53,6 +73,38 @@ macro_rules! omni_delegate {
             Self::send_serde_json_scheduled(self, payload, delay)
         }
     };
+    ( send_raw_batch ) => {
+        // Only for `Vec<u8>` payloads. Items that are `AsRef<Vec<u8>>` aren't
+        // `AsRef<[u8]>`, so they have to be converted for the inherent method.
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn send_raw_batch(
+            &self,
+            payloads: impl IntoIterator<Item: AsRef<Self::Payload> + Send, IntoIter: Send> + Send,
+        ) -> impl std::future::Future<Output = Result<()>> + Send {
+            async move {
+                let payloads = crate::queue::PayloadBatch::new(payloads);
+                Self::send_raw_batch(self, payloads.as_slices()).await
+            }
+        }
+    };
+    ( send_bytes_batch ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn send_bytes_batch(
+            &self,
+            payloads: impl IntoIterator<Item: AsRef<[u8]> + Send, IntoIter: Send> + Send,
+        ) -> impl std::future::Future<Output = Result<()>> + Send {
+            Self::send_bytes_batch(self, payloads)
+        }
+    };
+    ( send_serde_json_batch ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn send_serde_json_batch(
+            &self,
+            payloads: impl IntoIterator<Item: serde::Serialize + Send, IntoIter: Send> + Send,
+        ) -> impl std::future::Future<Output = Result<()>> + Send {
+            Self::send_serde_json_batch(self, payloads)
+        }
+    };
     ( redrive_dlq ) => {
         #[deny(unconditional_recursion)] // method call must defer to an inherent method
         fn redrive_dlq(
@@ -61,6 +113,55 @@ macro_rules! omni_delegate {
             Self::redrive_dlq(self)
         }
     };
+    ( health_check ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn health_check(
+            &self,
+        ) -> impl std::future::Future<Output = Result<()>> + Send {
+            Self::health_check(self)
+        }
+    };
+    ( stats ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn stats(
+            &self,
+        ) -> impl std::future::Future<Output = Result<$crate::QueueStats>> + Send {
+            Self::stats(self)
+        }
+    };
+    ( peek ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn peek(
+            &self,
+            max_messages: usize,
+        ) -> impl std::future::Future<Output = Result<Vec<Delivery>>> + Send {
+            Self::peek(self, max_messages)
+        }
+    };
+    ( create_queue ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn create_queue(&self) -> impl std::future::Future<Output = Result<()>> + Send {
+            Self::create_queue(self)
+        }
+    };
+    ( delete_queue ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn delete_queue(&self) -> impl std::future::Future<Output = Result<()>> + Send {
+            Self::delete_queue(self)
+        }
+    };
+    ( purge ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn purge(&self) -> impl std::future::Future<Output = Result<()>> + Send {
+            Self::purge(self)
+        }
+    };
+    ( exists ) => {
+        #[deny(unconditional_recursion)] // method call must defer to an inherent method
+        fn exists(&self) -> impl std::future::Future<Output = Result<bool>> + Send {
+            Self::exists(self)
+        }
+    };
 
     ( $method1:ident, $($rest:ident),* $(,)? ) => {
         omni_delegate!($method1);
@@ -0,0 +1,381 @@
+//! Metrics recorded by the backends.
+//!
+//! With the `metrics` feature, all backends record the metrics below through
+//! the [`metrics`](https://docs.rs/metrics) facade, so they are collected by
+//! whichever recorder the application installs (e.g.
+//! `metrics-exporter-prometheus`). Every metric is labelled with `backend`,
+//! such as `"sqs"`, and `queue`, the name of the queue, topic or subscription.
+//!
+//! Call [`describe`] once after installing the recorder to register the
+//! descriptions and units of the metrics.
+
+#![cfg_attr(not(feature = "metrics"), allow(unreachable_pub))]
+#![cfg_attr(
+    not(all(
+        feature = "metrics",
+        any(
+            feature = "in_memory",
+            feature = "gcp_pubsub",
+            feature = "rabbitmq",
+            feature = "redis",
+            feature = "sqs",
+            feature = "azure_queue_storage"
+        )
+    )),
+    allow(dead_code)
+)]
+
+use std::future::Future;
+#[cfg(feature = "metrics")]
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "metrics")]
+use metrics::{
+    counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Label, Unit,
+};
+
+#[cfg(feature = "metrics")]
+use crate::{
+    queue::{Acker, DynAcker},
+    ErrorKind,
+};
+use crate::{Delivery, QueueError, Result};
+
+/// Counter of messages sent successfully.
+pub const MESSAGES_SENT: &str = "omniqueue_messages_sent_total";
+/// Histogram of the time taken by sends, in seconds. A batch is one send.
+pub const SEND_DURATION: &str = "omniqueue_send_duration_seconds";
+/// Histogram of the payload sizes of the messages sent, in bytes.
+pub const SENT_PAYLOAD_SIZE: &str = "omniqueue_sent_payload_bytes";
+/// Counter of messages received.
+pub const MESSAGES_RECEIVED: &str = "omniqueue_messages_received_total";
+/// Histogram of the payload sizes of the messages received, in bytes.
+pub const RECEIVED_PAYLOAD_SIZE: &str = "omniqueue_received_payload_bytes";
+/// Histogram of the number of messages returned by `receive_all`.
+pub const RECEIVE_BATCH_SIZE: &str = "omniqueue_receive_batch_size";
+/// Gauge of the age of the oldest message in the last receive, in seconds.
+///
+/// Only recorded by backends that report when messages were enqueued.
+pub const OLDEST_MESSAGE_AGE: &str = "omniqueue_oldest_message_age_seconds";
+/// Counter of messages acked successfully.
+pub const MESSAGES_ACKED: &str = "omniqueue_messages_acked_total";
+/// Counter of messages nacked successfully, with or without a delay.
+pub const MESSAGES_NACKED: &str = "omniqueue_messages_nacked_total";
+/// Counter of messages moved to a dead-letter queue by omniqueue.
+///
+/// Backends where the broker handles dead-lettering, such as SQS and
+/// RabbitMQ, don't record this.
+pub const MESSAGES_DEAD_LETTERED: &str = "omniqueue_messages_dead_lettered_total";
+/// Counter of failed operations, including those of background tasks.
+///
+/// Additionally labelled with `operation`, such as `"send"` or
+/// `"enqueue_delayed"`, and `kind`, the snake-cased [`ErrorKind`] of the
+/// error, such as `"throttled"`.
+///
+/// [`ErrorKind`]: crate::ErrorKind
+pub const ERRORS: &str = "omniqueue_errors_total";
+
+/// Registers the descriptions and units of the metrics with the installed
+/// recorder.
+#[cfg(feature = "metrics")]
+pub fn describe() {
+    describe_counter!(MESSAGES_SENT, "Messages sent successfully");
+    describe_histogram!(SEND_DURATION, Unit::Seconds, "Time taken by sends");
+    describe_histogram!(
+        SENT_PAYLOAD_SIZE,
+        Unit::Bytes,
+        "Payload sizes of sent messages"
+    );
+    describe_counter!(MESSAGES_RECEIVED, "Messages received");
+    describe_histogram!(
+        RECEIVED_PAYLOAD_SIZE,
+        Unit::Bytes,
+        "Payload sizes of received messages"
+    );
+    describe_histogram!(RECEIVE_BATCH_SIZE, "Messages returned by receive_all");
+    describe_gauge!(
+        OLDEST_MESSAGE_AGE,
+        Unit::Seconds,
+        "Age of the oldest message in the last receive"
+    );
+    describe_counter!(MESSAGES_ACKED, "Messages acked successfully");
+    describe_counter!(MESSAGES_NACKED, "Messages nacked successfully");
+    describe_counter!(
+        MESSAGES_DEAD_LETTERED,
+        "Messages moved to a dead-letter queue"
+    );
+    describe_counter!(ERRORS, "Failed operations");
+}
+
+/// Records the metrics of one producer or consumer.
+///
+/// Without the `metrics` feature, this does nothing.
+#[derive(Clone, Debug)]
+pub(crate) struct QueueMetrics {
+    #[cfg(feature = "metrics")]
+    labels: Arc<[Label]>,
+}
+
+#[cfg(feature = "metrics")]
+impl QueueMetrics {
+    pub(crate) fn new(backend: &'static str, queue: &str) -> Self {
+        Self {
+            labels: Arc::new([
+                Label::new("backend", backend),
+                Label::new("queue", queue.to_owned()),
+            ]),
+        }
+    }
+
+    /// Runs `send`, recording it as sending messages with the given payload
+    /// sizes.
+    pub(crate) async fn send<T>(
+        &self,
+        payload_sizes: impl IntoIterator<Item = usize>,
+        send: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let start = Instant::now();
+        let result = send.await;
+        histogram!(SEND_DURATION, self.labels.iter()).record(start.elapsed());
+
+        match &result {
+            Ok(_) => {
+                let sent_payload_size = histogram!(SENT_PAYLOAD_SIZE, self.labels.iter());
+                let mut messages = 0;
+                for size in payload_sizes {
+                    sent_payload_size.record(size as f64);
+                    messages += 1;
+                }
+                counter!(MESSAGES_SENT, self.labels.iter()).increment(messages);
+            }
+            Err(e) => self.error("send", e),
+        }
+        result
+    }
+
+    /// Records the result of a `receive`.
+    pub(crate) fn receive(&self, result: Result<Delivery>) -> Result<Delivery> {
+        match result {
+            Ok(delivery) => {
+                self.record_oldest_age([&delivery]);
+                Ok(self.received(delivery))
+            }
+            Err(e) => {
+                self.error("receive", &e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Records the result of a `receive_all`.
+    pub(crate) fn receive_all(&self, result: Result<Vec<Delivery>>) -> Result<Vec<Delivery>> {
+        match result {
+            Ok(deliveries) => {
+                histogram!(RECEIVE_BATCH_SIZE, self.labels.iter()).record(deliveries.len() as f64);
+                self.record_oldest_age(&deliveries);
+                Ok(deliveries
+                    .into_iter()
+                    .map(|delivery| self.received(delivery))
+                    .collect())
+            }
+            Err(e) => {
+                self.error("receive", &e);
+                Err(e)
+            }
+        }
+    }
+
+    fn received(&self, delivery: Delivery) -> Delivery {
+        counter!(MESSAGES_RECEIVED, self.labels.iter()).increment(1);
+        let size = delivery.borrow_payload().map_or(0, <[u8]>::len);
+        histogram!(RECEIVED_PAYLOAD_SIZE, self.labels.iter()).record(size as f64);
+
+        let metrics = self.clone();
+        delivery.wrap_acker(|inner| MeteredAcker { inner, metrics })
+    }
+
+    fn record_oldest_age<'a>(&self, deliveries: impl IntoIterator<Item = &'a Delivery>) {
+        let now = time::OffsetDateTime::now_utc();
+        let oldest_age = deliveries
+            .into_iter()
+            .filter_map(|delivery| delivery.metadata().enqueued_at)
+            .map(|enqueued_at| (now - enqueued_at).as_seconds_f64().max(0.0))
+            .reduce(f64::max);
+        if let Some(age) = oldest_age {
+            gauge!(OLDEST_MESSAGE_AGE, self.labels.iter()).set(age);
+        }
+    }
+
+    /// Records that `count` messages were moved to the dead-letter queue.
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub(crate) fn dead_lettered(&self, count: u64) {
+        counter!(MESSAGES_DEAD_LETTERED, self.labels.iter()).increment(count);
+    }
+
+    /// Records a failed operation.
+    ///
+    /// Backend errors are recorded with the operation they carry, and
+    /// `operation` is used for other errors.
+    pub(crate) fn error(&self, operation: &'static str, error: &QueueError) {
+        let operation = match error {
+            // Neither of these is a failure of the queue
+            QueueError::NoData | QueueError::Unsupported(_) => return,
+            QueueError::Backend { operation, .. } => *operation,
+            _ => operation,
+        };
+        let labels = self.labels.iter().cloned().chain([
+            Label::new("operation", operation),
+            Label::new("kind", kind_label(error.kind())),
+        ]);
+        counter!(ERRORS, labels.collect::<Vec<_>>()).increment(1);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+impl QueueMetrics {
+    pub(crate) fn new(_backend: &'static str, _queue: &str) -> Self {
+        Self {}
+    }
+
+    pub(crate) async fn send<T>(
+        &self,
+        _payload_sizes: impl IntoIterator<Item = usize>,
+        send: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        send.await
+    }
+
+    pub(crate) fn receive(&self, result: Result<Delivery>) -> Result<Delivery> {
+        result
+    }
+
+    pub(crate) fn receive_all(&self, result: Result<Vec<Delivery>>) -> Result<Vec<Delivery>> {
+        result
+    }
+
+    pub(crate) fn dead_lettered(&self, _count: u64) {}
+
+    pub(crate) fn error(&self, _operation: &'static str, _error: &QueueError) {}
+}
+
+#[cfg(feature = "metrics")]
+fn kind_label(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::ConnectionLost => "connection_lost",
+        ErrorKind::Timeout => "timeout",
+        ErrorKind::Throttled => "throttled",
+        ErrorKind::AuthFailure => "auth_failure",
+        ErrorKind::QueueNotFound => "queue_not_found",
+        ErrorKind::InvalidReceiptHandle => "invalid_receipt_handle",
+        ErrorKind::Other => "other",
+    }
+}
+
+/// Records the acks and nacks of a delivery.
+#[cfg(feature = "metrics")]
+struct MeteredAcker {
+    inner: DynAcker,
+    metrics: QueueMetrics,
+}
+
+#[cfg(feature = "metrics")]
+impl MeteredAcker {
+    fn record(&self, counter: &'static str, operation: &'static str, result: &Result<()>) {
+        match result {
+            Ok(()) => counter!(counter, self.metrics.labels.iter()).increment(1),
+            Err(e) => self.metrics.error(operation, e),
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Acker for MeteredAcker {
+    async fn ack(&mut self) -> Result<()> {
+        let result = self.inner.ack().await;
+        self.record(MESSAGES_ACKED, "ack", &result);
+        result
+    }
+
+    async fn nack(&mut self) -> Result<()> {
+        let result = self.inner.nack().await;
+        self.record(MESSAGES_NACKED, "nack", &result);
+        result
+    }
+
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        let result = self.inner.nack_with_delay(delay).await;
+        self.record(MESSAGES_NACKED, "nack_with_delay", &result);
+        result
+    }
+
+    async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
+        let result = self.inner.set_ack_deadline(duration).await;
+        if let Err(e) = &result {
+            self.metrics.error("set_ack_deadline", e);
+        }
+        result
+    }
+}
+
+#[cfg(all(test, feature = "metrics", feature = "in_memory"))]
+mod tests {
+    use std::time::Duration;
+
+    use metrics_util::{
+        debugging::{DebugValue, DebuggingRecorder},
+        CompositeKey, MetricKind,
+    };
+
+    use super::{MESSAGES_ACKED, MESSAGES_NACKED, MESSAGES_RECEIVED, MESSAGES_SENT};
+    use crate::{backends::InMemoryBackend, QueueProducer as _};
+
+    fn counter(snapshot: &[(CompositeKey, DebugValue)], name: &str) -> u64 {
+        snapshot
+            .iter()
+            .find_map(|(key, value)| match value {
+                DebugValue::Counter(value)
+                    if key.kind() == MetricKind::Counter && key.key().name() == name =>
+                {
+                    assert!(key
+                        .key()
+                        .labels()
+                        .any(|label| label.key() == "backend" && label.value() == "in_memory"));
+                    Some(*value)
+                }
+                _ => None,
+            })
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let (p, mut c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        p.send_raw(b"one").await.unwrap();
+        p.send_raw_batch([b"two".to_vec(), b"three".to_vec()])
+            .await
+            .unwrap();
+
+        c.receive().await.unwrap().ack().await.unwrap();
+        for delivery in c.receive_all(2, Duration::from_millis(100)).await.unwrap() {
+            delivery.nack().await.unwrap();
+        }
+
+        let snapshot: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| (key, value))
+            .collect();
+        assert_eq!(counter(&snapshot, MESSAGES_SENT), 3);
+        assert_eq!(counter(&snapshot, MESSAGES_RECEIVED), 3);
+        assert_eq!(counter(&snapshot, MESSAGES_ACKED), 1);
+        assert_eq!(counter(&snapshot, MESSAGES_NACKED), 2);
+    }
+}
@@ -0,0 +1,496 @@
+//! OpenTelemetry trace-context propagation through messages.
+//!
+//! [`TracingProducer`] sends every message in a `publish` span and injects the
+//! span's context into the message's [`Attributes`], as the W3C
+//! [`traceparent`] and `tracestate` headers. [`TracingConsumer`] records
+//! `receive`, `ack` and `nack` spans that link to the producer spans of the
+//! messages, and [`process_span`] starts a span for the processing of a
+//! delivery that is a child of its producer span:
+//!
+//! ```ignore
+//! use omniqueue::otel::{process_span, TraceContextLayer, TracingConsumer};
+//! use tracing::Instrument as _;
+//!
+//! let (p, c) = SqsBackend::builder(cfg)
+//!     .layer(TraceContextLayer)
+//!     .build_pair()
+//!     .await?;
+//! let mut c = TracingConsumer::new(c);
+//!
+//! let delivery = c.receive().await?;
+//! let span = process_span(&delivery);
+//! async {
+//!     handle(&delivery).await?;
+//!     delivery.ack().await.map_err(|(e, _)| e)
+//! }
+//! .instrument(span)
+//! .await?;
+//! ```
+//!
+//! The spans are regular [`tracing`] spans. They are only exported to
+//! OpenTelemetry with a [`tracing_opentelemetry`] layer in the subscriber;
+//! without one, the context of the surrounding OpenTelemetry span is injected,
+//! if there is one.
+//!
+//! Propagation requires a backend that supports message attributes. Scheduled
+//! messages are sent without trace context, since they can't have attributes.
+//!
+//! [`traceparent`]: https://www.w3.org/TR/trace-context/#traceparent-header
+
+use std::{future::Future, num::NonZeroUsize, time::Duration};
+
+use opentelemetry::{
+    trace::{SpanContext, SpanId, TraceContextExt as _, TraceFlags, TraceId, TraceState},
+    Context,
+};
+use tracing::{debug, info_span, Instrument as _, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+
+use crate::{
+    layer::ProducerLayer,
+    queue::{Acker, DynAcker},
+    Attributes, Capabilities, Delivery, QueueConsumer, QueueError, QueueProducer, QueueStats,
+    Result, ScheduledQueueProducer,
+};
+
+/// The attribute that carries the W3C `traceparent` header.
+pub const TRACEPARENT: &str = "traceparent";
+/// The attribute that carries the W3C `tracestate` header.
+pub const TRACESTATE: &str = "tracestate";
+
+/// Adds the span context of `cx` to `attributes`.
+///
+/// Nothing is added if `cx` has no valid span context.
+pub fn inject_context(cx: &Context, attributes: &mut Attributes) {
+    let span = cx.span();
+    let span_context = span.span_context();
+    if !span_context.is_valid() {
+        return;
+    }
+
+    attributes.insert(
+        TRACEPARENT.to_owned(),
+        format!(
+            "00-{}-{}-{:02x}",
+            span_context.trace_id(),
+            span_context.span_id(),
+            span_context.trace_flags()
+        ),
+    );
+    let trace_state = span_context.trace_state().header();
+    if !trace_state.is_empty() {
+        attributes.insert(TRACESTATE.to_owned(), trace_state);
+    }
+}
+
+/// Returns the remote span context that was added to `attributes` by
+/// [`inject_context`], if there is a valid one.
+fn extract_span_context(attributes: &Attributes) -> Option<SpanContext> {
+    let traceparent = attributes.get(TRACEPARENT)?;
+    let mut parts = traceparent.trim().split('-');
+    let (version, trace_id, span_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+
+    // Later versions may append fields, but must keep these ones
+    if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+        return None;
+    }
+
+    let trace_id = TraceId::from_hex(trace_id).ok()?;
+    let span_id = SpanId::from_hex(span_id).ok()?;
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    // An invalid tracestate is dropped without dropping the traceparent
+    let trace_state = attributes
+        .get(TRACESTATE)
+        .and_then(|trace_state| parse_trace_state(trace_state))
+        .unwrap_or_default();
+
+    let span_context = SpanContext::new(
+        trace_id,
+        span_id,
+        TraceFlags::new(flags) & TraceFlags::SAMPLED,
+        true,
+        trace_state,
+    );
+    span_context.is_valid().then_some(span_context)
+}
+
+/// Parses a `tracestate` header. Unlike `TraceState`'s `FromStr`
+/// implementation, this rejects malformed headers.
+fn parse_trace_state(header: &str) -> Option<TraceState> {
+    let members = header
+        .split(',')
+        .map(str::trim)
+        .filter(|member| !member.is_empty())
+        .map(|member| {
+            member
+                .split_once('=')
+                .filter(|(key, value)| !key.is_empty() && !value.is_empty())
+        })
+        .collect::<Option<Vec<_>>>()?;
+    TraceState::from_key_value(members).ok()
+}
+
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len
+        && s.bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Returns the context of the span that sent `delivery`, or an empty context
+/// if it was sent without trace context.
+pub fn extract_context(delivery: &Delivery) -> Context {
+    match extract_span_context(delivery.attributes()) {
+        Some(span_context) => Context::new().with_remote_span_context(span_context),
+        None => Context::new(),
+    }
+}
+
+/// Returns a `process` span for processing `delivery`, which is a child of the
+/// span that sent it.
+///
+/// Acking or nacking the delivery inside of this span makes the `ack` or
+/// `nack` span a child of it.
+pub fn process_span(delivery: &Delivery) -> Span {
+    let span = info_span!("process", otel.kind = "consumer");
+    if let Some(span_context) = extract_span_context(delivery.attributes()) {
+        let _ = span.set_parent(Context::new().with_remote_span_context(span_context));
+    }
+    span
+}
+
+/// Returns a `publish` span, and the attributes to send in it.
+fn publish(attributes: &Attributes) -> (Span, Attributes) {
+    let span = info_span!("publish", otel.kind = "producer");
+    let mut cx = span.context();
+    if !cx.span().span_context().is_valid() {
+        // There is no OpenTelemetry layer, but the caller may still have an
+        // OpenTelemetry span of its own
+        cx = Context::current();
+    }
+
+    let mut attributes = attributes.clone();
+    inject_context(&cx, &mut attributes);
+    (span, attributes)
+}
+
+/// A [`QueueProducer`] that injects the trace context of the current span
+/// into the attributes of the messages it sends.
+///
+/// Batches are sent one message at a time, so that each message has its own
+/// `publish` span. If the backend doesn't support attributes, messages are
+/// sent without trace context.
+#[derive(Debug)]
+pub struct TracingProducer<P> {
+    inner: P,
+}
+
+impl<P: QueueProducer> TracingProducer<P> {
+    pub fn new(inner: P) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the wrapped producer.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    async fn send(&self, payload: &P::Payload, attributes: &Attributes) -> Result<()> {
+        let (span, traced_attributes) = publish(attributes);
+        async {
+            if traced_attributes.is_empty() {
+                return self.inner.send_raw(payload).await;
+            }
+            match self
+                .inner
+                .send_raw_with_attributes(payload, &traced_attributes)
+                .await
+            {
+                Err(QueueError::Unsupported(msg)) if attributes.is_empty() => {
+                    debug!("sending message without trace context: {msg}");
+                    self.inner.send_raw(payload).await
+                }
+                result => result,
+            }
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+impl<P: QueueProducer> QueueProducer for TracingProducer<P> {
+    type Payload = P::Payload;
+
+    async fn send_raw(&self, payload: &Self::Payload) -> Result<()> {
+        self.send(payload, &Attributes::new()).await
+    }
+
+    async fn send_raw_with_attributes(
+        &self,
+        payload: &Self::Payload,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        self.send(payload, attributes).await
+    }
+
+    async fn send_raw_batch(
+        &self,
+        payloads: impl IntoIterator<Item: AsRef<Self::Payload> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        for payload in payloads {
+            self.send(payload.as_ref(), &Attributes::new()).await?;
+        }
+        Ok(())
+    }
+
+    async fn redrive_dlq(&self) -> Result<()> {
+        self.inner.redrive_dlq().await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    fn payload_is_text(&self) -> bool {
+        self.inner.payload_is_text()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+}
+
+impl<P: ScheduledQueueProducer> ScheduledQueueProducer for TracingProducer<P> {
+    async fn send_raw_scheduled(&self, payload: &Self::Payload, delay: Duration) -> Result<()> {
+        self.inner.send_raw_scheduled(payload, delay).await
+    }
+}
+
+/// A [`ProducerLayer`] that wraps producers in a [`TracingProducer`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraceContextLayer;
+
+impl<P: QueueProducer> ProducerLayer<P> for TraceContextLayer {
+    type Producer = TracingProducer<P>;
+
+    fn layer(&self, inner: P) -> Self::Producer {
+        TracingProducer::new(inner)
+    }
+}
+
+/// A [`QueueConsumer`] that records spans for receiving, acking and nacking
+/// messages, linked to the spans that sent them.
+#[derive(Debug)]
+pub struct TracingConsumer<C> {
+    inner: C,
+}
+
+impl<C: QueueConsumer> TracingConsumer<C> {
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the wrapped consumer.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+/// Links `span` to the span that sent `delivery`, and records its ack or nack.
+fn trace(span: &Span, delivery: Delivery) -> Delivery {
+    let link = extract_span_context(delivery.attributes());
+    if let Some(link) = &link {
+        span.add_link(link.clone());
+    }
+    delivery.wrap_acker(|inner| TracingAcker { inner, link })
+}
+
+impl<C: QueueConsumer> QueueConsumer for TracingConsumer<C> {
+    type Payload = C::Payload;
+
+    async fn receive(&mut self) -> Result<Delivery> {
+        let span = info_span!("receive", otel.kind = "consumer");
+        let delivery = self.inner.receive().instrument(span.clone()).await?;
+        Ok(trace(&span, delivery))
+    }
+
+    async fn receive_all(
+        &mut self,
+        max_messages: usize,
+        deadline: Duration,
+    ) -> Result<Vec<Delivery>> {
+        let span = info_span!("receive", otel.kind = "consumer", messages = max_messages);
+        let deliveries = self
+            .inner
+            .receive_all(max_messages, deadline)
+            .instrument(span.clone())
+            .await?;
+        span.record("messages", deliveries.len());
+        Ok(deliveries
+            .into_iter()
+            .map(|delivery| trace(&span, delivery))
+            .collect())
+    }
+
+    fn max_messages(&self) -> Option<NonZeroUsize> {
+        self.inner.max_messages()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn health_check(&self) -> impl Future<Output = Result<()>> + Send {
+        // Not an `async fn`, as consumers don't have to be `Sync`
+        self.inner.health_check()
+    }
+
+    fn stats(&self) -> impl Future<Output = Result<QueueStats>> + Send {
+        self.inner.stats()
+    }
+
+    fn peek(&self, max_messages: usize) -> impl Future<Output = Result<Vec<Delivery>>> + Send {
+        self.inner.peek(max_messages)
+    }
+}
+
+/// Records `ack` and `nack` spans, linked to the span that sent the delivery.
+struct TracingAcker {
+    inner: DynAcker,
+    link: Option<SpanContext>,
+}
+
+impl TracingAcker {
+    fn link(&self, span: Span) -> Span {
+        if let Some(link) = &self.link {
+            span.add_link(link.clone());
+        }
+        span
+    }
+}
+
+impl Acker for TracingAcker {
+    async fn ack(&mut self) -> Result<()> {
+        let span = self.link(info_span!("ack", otel.kind = "consumer"));
+        self.inner.ack().instrument(span).await
+    }
+
+    async fn nack(&mut self) -> Result<()> {
+        let span = self.link(info_span!("nack", otel.kind = "consumer"));
+        self.inner.nack().instrument(span).await
+    }
+
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        let span = self.link(info_span!("nack", otel.kind = "consumer"));
+        self.inner.nack_with_delay(delay).instrument(span).await
+    }
+
+    async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
+        self.inner.set_ack_deadline(duration).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::{
+        trace::{SpanContext, SpanId, TraceContextExt as _, TraceFlags, TraceId, TraceState},
+        Context,
+    };
+
+    use super::{extract_span_context, inject_context, TRACEPARENT, TRACESTATE};
+    use crate::Attributes;
+
+    fn span_context() -> SpanContext {
+        SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            "vendor=value".parse().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_inject_extract() {
+        let cx = Context::new().with_remote_span_context(span_context());
+        let mut attributes = Attributes::new();
+        inject_context(&cx, &mut attributes);
+
+        assert_eq!(
+            attributes[TRACEPARENT],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        assert_eq!(attributes[TRACESTATE], "vendor=value");
+        assert_eq!(extract_span_context(&attributes), Some(span_context()));
+
+        // No context is injected without a span
+        let mut attributes = Attributes::new();
+        inject_context(&Context::new(), &mut attributes);
+        assert!(attributes.is_empty());
+    }
+
+    #[test]
+    fn test_extract_invalid() {
+        for traceparent in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        ] {
+            let attributes = Attributes::from([(TRACEPARENT.to_owned(), traceparent.to_owned())]);
+            assert_eq!(extract_span_context(&attributes), None, "{traceparent}");
+        }
+
+        // Future versions can add fields, and an invalid tracestate is ignored
+        let attributes = Attributes::from([
+            (
+                TRACEPARENT.to_owned(),
+                "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra".to_owned(),
+            ),
+            (TRACESTATE.to_owned(), "=".to_owned()),
+        ]);
+        let span_context = extract_span_context(&attributes).unwrap();
+        assert!(span_context.is_sampled());
+        assert_eq!(span_context.trace_state(), &TraceState::default());
+    }
+
+    #[cfg(feature = "in_memory")]
+    #[tokio::test]
+    async fn test_producer_consumer() {
+        use std::time::Duration;
+
+        use super::{extract_context, TraceContextLayer, TracingConsumer};
+        use crate::{backends::InMemoryBackend, QueueConsumer, QueueProducer};
+
+        let (p, c) = InMemoryBackend::builder()
+            .layer(TraceContextLayer)
+            .build_pair()
+            .await
+            .unwrap();
+        let mut c = TracingConsumer::new(c);
+
+        // Without an OpenTelemetry layer, the current context is propagated
+        {
+            let _guard = Context::new()
+                .with_remote_span_context(span_context())
+                .attach();
+            p.send_raw_batch([b"one".to_vec()]).await.unwrap();
+        }
+        p.send_raw(&b"two".to_vec()).await.unwrap();
+
+        let deliveries = c.receive_all(2, Duration::from_millis(100)).await.unwrap();
+        let cx = extract_context(&deliveries[0]);
+        assert_eq!(cx.span().span_context(), &span_context());
+        assert!(!extract_context(&deliveries[1]).has_active_span());
+
+        for delivery in deliveries {
+            delivery.ack().await.unwrap();
+        }
+    }
+}
@@ -1,13 +1,23 @@
-use std::{future::Future, pin::Pin, time::Duration};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use sync_wrapper::SyncWrapper;
+use tokio::{sync::Mutex, task::AbortHandle};
 
-use crate::Result;
+use super::DeliveryMetadata;
+use crate::{
+    intercept::{ConsumerInterceptor, SettleEvent, Settlement},
+    QueueError, Result,
+};
 
 pub(crate) trait Acker: Send {
     fn ack(&mut self) -> impl Future<Output = Result<()>> + Send;
     fn nack(&mut self) -> impl Future<Output = Result<()>> + Send;
-    #[cfg_attr(not(feature = "beta"), allow(dead_code))]
+    fn nack_with_delay(&mut self, delay: Duration) -> impl Future<Output = Result<()>> + Send;
     fn set_ack_deadline(&mut self, duration: Duration) -> impl Future<Output = Result<()>> + Send;
 }
 
@@ -29,6 +39,10 @@ impl Acker for DynAcker {
         self.0.get_mut().nack().await
     }
 
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        self.0.get_mut().nack_with_delay(delay).await
+    }
+
     async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
         self.0.get_mut().set_ack_deadline(duration).await
     }
@@ -37,7 +51,10 @@ impl Acker for DynAcker {
 trait ErasedAcker: Send {
     fn ack(&mut self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>;
     fn nack(&mut self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>;
-    #[cfg_attr(not(feature = "beta"), allow(dead_code))]
+    fn nack_with_delay(
+        &mut self,
+        delay: Duration,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>;
     fn set_ack_deadline(
         &mut self,
         duration: Duration,
@@ -57,6 +74,13 @@ impl<C: Acker> ErasedAcker for DynAckerInner<C> {
         Box::pin(async move { self.inner.nack().await })
     }
 
+    fn nack_with_delay(
+        &mut self,
+        delay: Duration,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+        Box::pin(async move { self.inner.nack_with_delay(delay).await })
+    }
+
     fn set_ack_deadline(
         &mut self,
         duration: Duration,
@@ -65,9 +89,176 @@ impl<C: Acker> ErasedAcker for DynAckerInner<C> {
     }
 }
 
+/// The shortest ack deadline [`KeepAliveAcker`] extends deadlines by, which
+/// keeps it from hammering the backend with deadline updates.
+pub(super) const MIN_KEEP_ALIVE_DEADLINE: Duration = Duration::from_secs(1);
+
+/// Wraps another acker, extending its ack deadline from a background task
+/// until it is acked, nacked or dropped.
+pub(super) struct KeepAliveAcker {
+    inner: Arc<Mutex<DynAcker>>,
+    task: AbortHandle,
+}
+
+impl KeepAliveAcker {
+    pub(super) fn new(inner: DynAcker, ack_deadline: Duration) -> Self {
+        let ack_deadline = ack_deadline.max(MIN_KEEP_ALIVE_DEADLINE);
+        let inner = Arc::new(Mutex::new(inner));
+        let task = tokio::spawn(keep_alive(inner.clone(), ack_deadline)).abort_handle();
+        Self { inner, task }
+    }
+}
+
+/// Sets the ack deadline to `ack_deadline` every half `ack_deadline`, so that
+/// one failed attempt doesn't immediately lose the lease.
+async fn keep_alive(inner: Arc<Mutex<DynAcker>>, ack_deadline: Duration) {
+    let mut interval = tokio::time::interval(ack_deadline / 2);
+    // The first tick completes immediately, and the deadline was only just
+    // started by the receive
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        match inner.lock().await.set_ack_deadline(ack_deadline).await {
+            Ok(()) => {}
+            Err(QueueError::Unsupported(msg)) => {
+                tracing::debug!("not keeping delivery alive: {msg}");
+                return;
+            }
+            Err(e) => tracing::warn!(error = %e, "failed to extend ack deadline"),
+        }
+    }
+}
+
+impl Acker for KeepAliveAcker {
+    async fn ack(&mut self) -> Result<()> {
+        self.task.abort();
+        self.inner.lock().await.ack().await
+    }
+
+    async fn nack(&mut self) -> Result<()> {
+        self.task.abort();
+        self.inner.lock().await.nack().await
+    }
+
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        self.task.abort();
+        self.inner.lock().await.nack_with_delay(delay).await
+    }
+
+    async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
+        self.inner.lock().await.set_ack_deadline(duration).await
+    }
+}
+
+impl Drop for KeepAliveAcker {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Wraps another acker, reporting the outcome of acks and nacks to a
+/// [`ConsumerInterceptor`].
+pub(crate) struct InterceptingAcker<I> {
+    inner: DynAcker,
+    interceptor: Arc<I>,
+    metadata: DeliveryMetadata,
+    received_at: Instant,
+}
+
+impl<I: ConsumerInterceptor> InterceptingAcker<I> {
+    pub(crate) fn new(inner: DynAcker, interceptor: Arc<I>, metadata: DeliveryMetadata) -> Self {
+        Self {
+            inner,
+            interceptor,
+            metadata,
+            received_at: Instant::now(),
+        }
+    }
+
+    async fn settle(&mut self, settlement: Settlement) -> Result<()> {
+        let start = Instant::now();
+        let result = match settlement {
+            Settlement::Ack => self.inner.ack().await,
+            Settlement::Nack => self.inner.nack().await,
+            Settlement::NackWithDelay(delay) => self.inner.nack_with_delay(delay).await,
+        };
+
+        self.interceptor.on_settle(&SettleEvent {
+            settlement,
+            metadata: &self.metadata,
+            result: result.as_ref().map(|_| ()),
+            latency: start.elapsed(),
+            held_for: self.received_at.elapsed(),
+        });
+        result
+    }
+}
+
+impl<I: ConsumerInterceptor> Acker for InterceptingAcker<I> {
+    async fn ack(&mut self) -> Result<()> {
+        self.settle(Settlement::Ack).await
+    }
+
+    async fn nack(&mut self) -> Result<()> {
+        self.settle(Settlement::Nack).await
+    }
+
+    async fn nack_with_delay(&mut self, delay: Duration) -> Result<()> {
+        self.settle(Settlement::NackWithDelay(delay)).await
+    }
+
+    async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
+        self.inner.set_ack_deadline(duration).await
+    }
+}
+
+/// The acker of deliveries returned by [`QueueConsumer::peek`], which weren't
+/// claimed from the queue and so can't be acked or nacked.
+///
+/// [`QueueConsumer::peek`]: crate::QueueConsumer::peek
+#[cfg_attr(
+    not(any(
+        feature = "in_memory",
+        feature = "redis",
+        feature = "azure_queue_storage"
+    )),
+    allow(dead_code)
+)]
+pub(crate) struct PeekAcker;
+
+impl Acker for PeekAcker {
+    async fn ack(&mut self) -> Result<()> {
+        Err(QueueError::Unsupported("peeked deliveries can't be acked"))
+    }
+
+    async fn nack(&mut self) -> Result<()> {
+        Err(QueueError::Unsupported("peeked deliveries can't be nacked"))
+    }
+
+    async fn nack_with_delay(&mut self, _delay: Duration) -> Result<()> {
+        Err(QueueError::Unsupported("peeked deliveries can't be nacked"))
+    }
+
+    async fn set_ack_deadline(&mut self, _duration: Duration) -> Result<()> {
+        Err(QueueError::Unsupported(
+            "peeked deliveries have no ack deadline",
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::DynAcker;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::{Acker, DynAcker, KeepAliveAcker, MIN_KEEP_ALIVE_DEADLINE};
+    use crate::Result;
 
     fn assert_sync<T: Sync>() {}
 
@@ -75,4 +266,70 @@ mod tests {
     fn assert_acker_sync() {
         assert_sync::<DynAcker>();
     }
+
+    struct CountingAcker {
+        deadlines_set: Arc<AtomicUsize>,
+        min_deadline: Duration,
+    }
+
+    impl Acker for CountingAcker {
+        async fn ack(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn nack(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn nack_with_delay(&mut self, _delay: Duration) -> Result<()> {
+            Ok(())
+        }
+
+        async fn set_ack_deadline(&mut self, duration: Duration) -> Result<()> {
+            assert!(duration >= self.min_deadline);
+            self.deadlines_set.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn counting_acker(deadlines_set: &Arc<AtomicUsize>) -> DynAcker {
+        DynAcker::new(CountingAcker {
+            deadlines_set: deadlines_set.clone(),
+            min_deadline: MIN_KEEP_ALIVE_DEADLINE,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keep_alive_until_ack() {
+        let deadlines_set = Arc::new(AtomicUsize::new(0));
+        let mut acker =
+            KeepAliveAcker::new(counting_acker(&deadlines_set), Duration::from_secs(10));
+
+        tokio::time::sleep(Duration::from_millis(17_500)).await;
+        assert_eq!(deadlines_set.load(Ordering::SeqCst), 3);
+
+        acker.ack().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(deadlines_set.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keep_alive_stops_on_drop() {
+        let deadlines_set = Arc::new(AtomicUsize::new(0));
+        let acker = KeepAliveAcker::new(counting_acker(&deadlines_set), Duration::from_secs(10));
+
+        tokio::time::sleep(Duration::from_millis(7_500)).await;
+        drop(acker);
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(deadlines_set.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keep_alive_min_deadline() {
+        let deadlines_set = Arc::new(AtomicUsize::new(0));
+        let _acker = KeepAliveAcker::new(counting_acker(&deadlines_set), Duration::ZERO);
+
+        tokio::time::sleep(MIN_KEEP_ALIVE_DEADLINE * 5 / 4).await;
+        assert_eq!(deadlines_set.load(Ordering::SeqCst), 2);
+    }
 }
@@ -0,0 +1,26 @@
+use std::future::Future;
+
+use crate::Result;
+
+/// Creates, deletes and purges the queue a backend is configured for.
+///
+/// This is meant for bootstrapping and tests, so that they don't have to use
+/// the backend's own client. Admins are created with the `build_admin` method
+/// of a backend's builder, from the same configuration as the producers and
+/// consumers.
+///
+/// What makes up a "queue" depends on the backend; see the documentation of
+/// the implementations for details.
+pub trait QueueAdmin: Send + Sync {
+    /// Creates the queue, or does nothing if it already exists.
+    fn create_queue(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Deletes the queue along with all of its messages.
+    fn delete_queue(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Deletes all messages from the queue, keeping the queue itself.
+    fn purge(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Returns whether the queue exists.
+    fn exists(&self) -> impl Future<Output = Result<bool>> + Send;
+}
@@ -1,7 +1,22 @@
-use std::{cmp::min, future::Future, num::NonZeroUsize, pin::Pin, time::Duration};
+use std::{
+    cmp::min, collections::VecDeque, future::Future, num::NonZeroUsize, pin::Pin, time::Duration,
+};
 
-use super::Delivery;
-use crate::{QueuePayload, Result};
+use futures_util::{stream, Stream};
+
+use super::{Capabilities, Delivery, QueueStats};
+use crate::{
+    intercept::{ConsumerInterceptor, Intercepted},
+    worker::RECEIVE_ERROR_BACKOFF,
+    QueueError, QueuePayload, Result,
+};
+
+/// The batch size used by [`QueueConsumer::into_stream`] for backends that
+/// don't report a [`max_messages`](QueueConsumer::max_messages).
+const DEFAULT_STREAM_BATCH_SIZE: usize = 10;
+/// How long [`QueueConsumer::into_stream`] waits for a batch to fill up before
+/// yielding what it has.
+const STREAM_BATCH_DEADLINE: Duration = Duration::from_secs(5);
 
 pub trait QueueConsumer: Send + Sized {
     type Payload: QueuePayload;
@@ -21,6 +36,31 @@ pub trait QueueConsumer: Send + Sized {
         DynConsumer::new(self)
     }
 
+    /// Turns this consumer into an endless [`Stream`] of deliveries.
+    ///
+    /// Deliveries are fetched in batches of up to
+    /// [`max_messages`](Self::max_messages) (or 10 if the backend has no
+    /// limit) using [`receive_all`](Self::receive_all), then yielded one at a
+    /// time.
+    /// Receive errors are yielded as items, and do not end the stream; the
+    /// next receive waits for a second after an error.
+    ///
+    /// Note that a delivery's ack deadline starts when its batch is received,
+    /// not when it is yielded from the stream.
+    fn into_stream(self) -> impl Stream<Item = Result<Delivery>> + Send + Unpin
+    where
+        Self: 'static,
+    {
+        delivery_stream(self)
+    }
+
+    /// Runs `interceptor` on the deliveries received by this consumer.
+    ///
+    /// See the [`intercept`](crate::intercept) module for details.
+    fn with_interceptor<I: ConsumerInterceptor>(self, interceptor: I) -> Intercepted<Self, I> {
+        Intercepted::new(self, interceptor)
+    }
+
     /// Returns the largest number that may be passed as `max_messages` to
     /// `receive_all`.
     ///
@@ -29,6 +69,62 @@ pub trait QueueConsumer: Send + Sized {
     fn max_messages(&self) -> Option<NonZeroUsize> {
         None
     }
+
+    /// Returns what the backend supports.
+    ///
+    /// The default implementation reports
+    /// [`max_messages`](Self::max_messages) as the
+    /// [`max_receive_batch_size`](Capabilities::max_receive_batch_size), and no
+    /// support for anything else.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_receive_batch_size: self.max_messages(),
+            ..Default::default()
+        }
+    }
+
+    /// Checks that the broker can be reached, for use in readiness probes.
+    ///
+    /// Backends make a cheap request to the broker that also verifies that
+    /// the queue or subscription exists. The default implementation returns
+    /// [`QueueError::Unsupported`].
+    fn health_check(&self) -> impl Future<Output = Result<()>> + Send {
+        async {
+            Err(QueueError::Unsupported(
+                "health checks are not supported by this backend",
+            ))
+        }
+    }
+
+    /// Returns approximate message counts of the queue, e.g. for autoscaling
+    /// consumers.
+    ///
+    /// Counts the backend doesn't track are left as `None`. The default
+    /// implementation returns [`QueueError::Unsupported`].
+    fn stats(&self) -> impl Future<Output = Result<QueueStats>> + Send {
+        async {
+            Err(QueueError::Unsupported(
+                "queue stats are not supported by this backend",
+            ))
+        }
+    }
+
+    /// Returns up to `max_messages` messages from the front of the queue,
+    /// without claiming them or bumping their receive counts, e.g. for
+    /// debugging.
+    ///
+    /// The returned deliveries can't be acked or nacked; trying to do so
+    /// returns [`QueueError::Unsupported`]. Backends that can't look at
+    /// messages without receiving them return [`QueueError::Unsupported`],
+    /// which is also what the default implementation does.
+    fn peek(&self, max_messages: usize) -> impl Future<Output = Result<Vec<Delivery>>> + Send {
+        let _ = max_messages;
+        async {
+            Err(QueueError::Unsupported(
+                "peeking is not supported by this backend",
+            ))
+        }
+    }
 }
 
 pub struct DynConsumer(Box<dyn ErasedQueueConsumer>);
@@ -48,6 +144,13 @@ trait ErasedQueueConsumer: Send {
         deadline: Duration,
     ) -> Pin<Box<dyn Future<Output = Result<Vec<Delivery>>> + Send + '_>>;
     fn max_messages(&self) -> Option<NonZeroUsize>;
+    fn capabilities(&self) -> Capabilities;
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>;
+    fn stats(&self) -> Pin<Box<dyn Future<Output = Result<QueueStats>> + Send + '_>>;
+    fn peek(
+        &self,
+        max_messages: usize,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Delivery>>> + Send + '_>>;
 }
 
 struct DynConsumerInner<C> {
@@ -56,13 +159,7 @@ struct DynConsumerInner<C> {
 
 impl<C: QueueConsumer> ErasedQueueConsumer for DynConsumerInner<C> {
     fn receive(&mut self) -> Pin<Box<dyn Future<Output = Result<Delivery>> + Send + '_>> {
-        Box::pin(async move {
-            let mut t_payload = self.inner.receive().await?;
-            Ok(Delivery {
-                payload: t_payload.take_payload(),
-                acker: t_payload.acker,
-            })
-        })
+        Box::pin(async move { self.inner.receive().await })
     }
 
     fn receive_all(
@@ -70,22 +167,65 @@ impl<C: QueueConsumer> ErasedQueueConsumer for DynConsumerInner<C> {
         max_messages: usize,
         deadline: Duration,
     ) -> Pin<Box<dyn Future<Output = Result<Vec<Delivery>>> + Send + '_>> {
-        Box::pin(async move {
-            let xs = self.inner.receive_all(max_messages, deadline).await?;
-            let mut out = Vec::with_capacity(xs.len());
-            for mut t_payload in xs {
-                out.push(Delivery {
-                    payload: t_payload.take_payload(),
-                    acker: t_payload.acker,
-                });
-            }
-            Ok(out)
-        })
+        Box::pin(async move { self.inner.receive_all(max_messages, deadline).await })
     }
 
     fn max_messages(&self) -> Option<NonZeroUsize> {
         self.inner.max_messages()
     }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+        Box::pin(self.inner.health_check())
+    }
+
+    fn stats(&self) -> Pin<Box<dyn Future<Output = Result<QueueStats>> + Send + '_>> {
+        Box::pin(self.inner.stats())
+    }
+
+    fn peek(
+        &self,
+        max_messages: usize,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Delivery>>> + Send + '_>> {
+        Box::pin(self.inner.peek(max_messages))
+    }
+}
+
+fn delivery_stream<C: QueueConsumer + 'static>(
+    consumer: C,
+) -> impl Stream<Item = Result<Delivery>> + Send + Unpin {
+    let batch_size = consumer
+        .max_messages()
+        .map_or(DEFAULT_STREAM_BATCH_SIZE, NonZeroUsize::get);
+
+    // The flag is set after an error, to back off before receiving again
+    Box::pin(stream::unfold(
+        (consumer, VecDeque::new(), false),
+        move |(mut consumer, mut buffer, mut failed)| async move {
+            loop {
+                if let Some(delivery) = buffer.pop_front() {
+                    return Some((Ok(delivery), (consumer, buffer, failed)));
+                }
+
+                if failed {
+                    tokio::time::sleep(RECEIVE_ERROR_BACKOFF).await;
+                }
+                match consumer
+                    .receive_all(batch_size, STREAM_BATCH_DEADLINE)
+                    .await
+                {
+                    Ok(deliveries) => {
+                        failed = false;
+                        buffer.extend(deliveries);
+                    }
+                    Err(e) => return Some((Err(e), (consumer, buffer, true))),
+                }
+            }
+        },
+    ))
 }
 
 impl DynConsumer {
@@ -110,11 +250,40 @@ impl DynConsumer {
         };
         self.0.receive_all(max_messages, deadline).await
     }
+
+    /// Turns this consumer into an endless [`Stream`] of deliveries.
+    ///
+    /// See [`QueueConsumer::into_stream`] for details.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Delivery>> + Send + Unpin {
+        delivery_stream(self)
+    }
+
+    /// Checks that the broker of the wrapped consumer can be reached.
+    pub fn health_check(&self) -> impl Future<Output = Result<()>> + Send + '_ {
+        self.0.health_check()
+    }
+
+    /// Returns what the backend of the wrapped consumer supports.
+    pub fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
+
+    /// Returns approximate message counts of the queue of the wrapped consumer.
+    pub fn stats(&self) -> impl Future<Output = Result<QueueStats>> + Send + '_ {
+        self.0.stats()
+    }
+
+    pub fn peek(
+        &self,
+        max_messages: usize,
+    ) -> impl Future<Output = Result<Vec<Delivery>>> + Send + '_ {
+        self.0.peek(max_messages)
+    }
 }
 
 impl crate::QueueConsumer for DynConsumer {
     type Payload = Vec<u8>;
-    omni_delegate!(receive, receive_all);
+    omni_delegate!(receive, receive_all, health_check, stats, peek);
 
     fn into_dyn(self) -> DynConsumer {
         self
@@ -123,4 +292,48 @@ impl crate::QueueConsumer for DynConsumer {
     fn max_messages(&self) -> Option<NonZeroUsize> {
         self.0.max_messages()
     }
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::StreamExt as _;
+
+    use super::QueueConsumer;
+    use crate::{worker::RECEIVE_ERROR_BACKOFF, Delivery, QueueError, Result};
+
+    struct FailingConsumer;
+
+    impl QueueConsumer for FailingConsumer {
+        type Payload = Vec<u8>;
+
+        async fn receive(&mut self) -> Result<Delivery> {
+            Err(QueueError::Generic("receive failed".into()))
+        }
+
+        async fn receive_all(
+            &mut self,
+            _max_messages: usize,
+            _deadline: Duration,
+        ) -> Result<Vec<Delivery>> {
+            Err(QueueError::Generic("receive failed".into()))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_backs_off_after_errors() {
+        let mut stream = FailingConsumer.into_stream();
+
+        let start = tokio::time::Instant::now();
+        assert!(stream.next().await.unwrap().is_err());
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        assert!(stream.next().await.unwrap().is_err());
+        assert_eq!(start.elapsed(), RECEIVE_ERROR_BACKOFF);
+    }
 }
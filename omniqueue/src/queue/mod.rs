@@ -1,17 +1,28 @@
-#[cfg_attr(not(feature = "beta"), allow(unused_imports))]
-use std::{fmt, future::Future, time::Duration};
+use std::{collections::HashMap, fmt, future::Future, num::NonZeroUsize, time::Duration};
 
 use serde::de::DeserializeOwned;
+use time::OffsetDateTime;
 
-use crate::{QueueError, QueuePayload, Result};
+use crate::{codec::Codec, QueueError, QueuePayload, Result};
 
 mod acker;
+mod admin;
 mod consumer;
 mod producer;
 
-use self::acker::DynAcker;
-pub(crate) use self::{acker::Acker, producer::ErasedQueueProducer};
+use self::acker::KeepAliveAcker;
+#[cfg(any(
+    feature = "in_memory",
+    feature = "redis",
+    feature = "azure_queue_storage"
+))]
+pub(crate) use self::acker::PeekAcker;
+pub(crate) use self::{
+    acker::{Acker, DynAcker, InterceptingAcker},
+    producer::{ErasedQueueProducer, PayloadBatch},
+};
 pub use self::{
+    admin::QueueAdmin,
     consumer::{DynConsumer, QueueConsumer},
     producer::{DynProducer, QueueProducer},
 };
@@ -44,9 +55,112 @@ pub trait QueueBackend {
     fn consuming_half(config: Self::Config) -> impl Future<Output = Result<Self::Consumer>> + Send;
 }
 
+/// Key-value metadata that travels alongside a message's payload.
+///
+/// Each backend maps these to its native message metadata where one exists
+/// (SQS message attributes, RabbitMQ headers, Pub/Sub attributes, Redis stream
+/// fields). Backends without such a feature wrap the payload in an envelope
+/// instead.
+pub type Attributes = HashMap<String, String>;
+
+/// Backend-provided information about a [`Delivery`].
+///
+/// Every field is optional since not all backends track all of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DeliveryMetadata {
+    /// The identifier the backend assigned to the message.
+    pub message_id: Option<String>,
+    /// How many times the message has been received, including this delivery.
+    ///
+    /// For some backends (e.g. SQS) this is only an approximation.
+    pub receive_count: Option<u32>,
+    /// When the message was originally enqueued.
+    pub enqueued_at: Option<OffsetDateTime>,
+}
+
+/// Approximate message counts of a queue, as returned by
+/// [`QueueConsumer::stats`].
+///
+/// Every field is optional since not all backends track all of them. Counts
+/// are snapshots and may already be out of date when they are returned.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct QueueStats {
+    /// Messages that are ready to be received.
+    pub visible: Option<u64>,
+    /// Messages that have been received, but not yet acked or nacked.
+    pub in_flight: Option<u64>,
+    /// Messages that were sent with a delay that hasn't passed yet.
+    pub delayed: Option<u64>,
+    /// Messages in the dead-letter queue.
+    pub dead_lettered: Option<u64>,
+}
+
+/// What a backend supports, as returned by [`QueueProducer::capabilities`]
+/// and [`QueueConsumer::capabilities`].
+///
+/// This lets generic code check for support up front rather than handling
+/// [`QueueError::Unsupported`]. Limits that are `None` are either not fixed
+/// by the backend, e.g. because they are configured on the broker, or not
+/// known to omniqueue.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The largest payload the backend accepts, in bytes.
+    pub max_payload_size: Option<usize>,
+    /// The largest number of messages the backend sends in one request. Larger
+    /// batches passed to [`send_raw_batch`](QueueProducer::send_raw_batch) are
+    /// split up.
+    ///
+    /// `None` if the backend has no batch send, so batches are sent one
+    /// message at a time.
+    pub max_send_batch_size: Option<NonZeroUsize>,
+    /// The largest number of messages the backend receives at once, which is
+    /// also the largest `max_messages` that `receive_all` accepts.
+    ///
+    /// This is the same as [`QueueConsumer::max_messages`].
+    pub max_receive_batch_size: Option<NonZeroUsize>,
+    /// Whether messages can be sent with a delay, using
+    /// [`ScheduledQueueProducer`](crate::ScheduledQueueProducer).
+    pub scheduling: bool,
+    /// The longest delay messages can be sent with, if `scheduling` is
+    /// supported.
+    pub max_delay: Option<Duration>,
+    /// Whether messages that were received too often are moved to a
+    /// dead-letter queue, from which
+    /// [`redrive_dlq`](QueueProducer::redrive_dlq) moves them back.
+    ///
+    /// Dead-letter queues that are set up on the broker itself, like an SQS
+    /// redrive policy, aren't reflected here.
+    pub dead_letter_queue: bool,
+    /// Whether [`Delivery::set_ack_deadline`] is supported.
+    pub set_ack_deadline: bool,
+    /// The order in which messages are received.
+    pub ordering: MessageOrdering,
+}
+
+/// The ordering guarantee of a backend, as reported in
+/// [`Capabilities::ordering`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MessageOrdering {
+    /// Messages may be received in any order.
+    #[default]
+    Unordered,
+    /// Messages are usually received in the order they were sent, but this
+    /// isn't guaranteed.
+    BestEffort,
+    /// Messages are received in the order they were sent, except for those
+    /// that were sent with a delay or are redelivered.
+    Fifo,
+}
+
 /// The output of queue backends
 pub struct Delivery {
     payload: Option<Vec<u8>>,
+    attributes: Attributes,
+    metadata: DeliveryMetadata,
     acker: DynAcker,
 }
 
@@ -65,10 +179,61 @@ impl Delivery {
     pub(crate) fn new(payload: Vec<u8>, acker: impl Acker + 'static) -> Self {
         Self {
             payload: Some(payload),
+            attributes: Attributes::new(),
+            metadata: DeliveryMetadata::default(),
             acker: DynAcker::new(acker),
         }
     }
 
+    #[cfg_attr(
+        not(any(
+            feature = "in_memory",
+            feature = "gcp_pubsub",
+            feature = "rabbitmq",
+            feature = "redis",
+            feature = "sqs",
+            feature = "azure_queue_storage"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn with_attributes(mut self, attributes: Attributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    #[cfg_attr(
+        not(any(
+            feature = "in_memory",
+            feature = "gcp_pubsub",
+            feature = "rabbitmq",
+            feature = "redis",
+            feature = "sqs",
+            feature = "azure_queue_storage"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn with_metadata(mut self, metadata: DeliveryMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Wraps this delivery's acker in another one, e.g. to run extra work on
+    /// ack.
+    pub(crate) fn wrap_acker<A: Acker + 'static>(self, f: impl FnOnce(DynAcker) -> A) -> Self {
+        let Self {
+            payload,
+            attributes,
+            metadata,
+            acker,
+        } = self;
+        Self {
+            payload,
+            attributes,
+            metadata,
+            acker: DynAcker::new(f(acker)),
+        }
+    }
+
     /// Acknowledges the receipt and successful processing of this [`Delivery`].
     ///
     /// On failure, `self` is returned alongside the error to allow retrying.
@@ -79,7 +244,6 @@ impl Delivery {
         self.acker.ack().await.map_err(|e| (e, self))
     }
 
-    #[cfg(feature = "beta")]
     /// Sets the deadline for acknowledging this [`Delivery`] to `duration`,
     /// starting from the time this method is called.
     ///
@@ -92,6 +256,22 @@ impl Delivery {
         self.acker.set_ack_deadline(duration).await
     }
 
+    /// Keeps this [`Delivery`] from being redelivered while it is held, by
+    /// setting its ack deadline to `ack_deadline` from a background task every
+    /// `ack_deadline / 2`.
+    ///
+    /// This stops once the delivery is acked, nacked or dropped, or if the
+    /// backend doesn't support setting ack deadlines. Failures to extend the
+    /// deadline are logged and retried on the next interval.
+    ///
+    /// Deadlines shorter than one second are raised to one second.
+    ///
+    /// This must be called from within a tokio runtime.
+    #[must_use]
+    pub fn keep_alive(self, ack_deadline: Duration) -> Self {
+        self.wrap_acker(|acker| KeepAliveAcker::new(acker, ack_deadline))
+    }
+
     /// Explicitly does not Acknowledge the successful processing of this
     /// [`Delivery`].
     ///
@@ -104,6 +284,24 @@ impl Delivery {
         self.acker.nack().await.map_err(|e| (e, self))
     }
 
+    /// Explicitly does not Acknowledge the successful processing of this
+    /// [`Delivery`], and asks for it to be redelivered no earlier than `delay`
+    /// from now.
+    ///
+    /// On failure, `self` is returned alongside the error to allow retrying.
+    ///
+    /// With RabbitMQ, the message is acked and republished through the
+    /// configured exchange, so it arrives as a new message that keeps the
+    /// original's receive count. This requires a delayed message exchange,
+    /// see
+    /// [`RabbitMqConfig::delayed_message_exchange`](crate::backends::RabbitMqConfig::delayed_message_exchange).
+    pub async fn nack_with_delay(mut self, delay: Duration) -> Result<(), (QueueError, Self)> {
+        self.acker
+            .nack_with_delay(delay)
+            .await
+            .map_err(|e| (e, self))
+    }
+
     /// This method will take the contained bytes out of the delivery, doing no
     /// further processing.
     ///
@@ -117,16 +315,43 @@ impl Delivery {
         self.payload.as_deref()
     }
 
+    /// Replaces the payload of this delivery, e.g. after decoding it.
+    pub fn set_payload(&mut self, payload: Vec<u8>) {
+        self.payload = Some(payload);
+    }
+
+    /// The attributes the message was sent with.
+    ///
+    /// This is empty if the message was sent without attributes.
+    pub fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    /// Backend-provided information about this delivery, such as the message
+    /// ID and how often it has been received.
+    pub fn metadata(&self) -> &DeliveryMetadata {
+        &self.metadata
+    }
+
     pub fn payload_serde_json<T: DeserializeOwned>(&self) -> Result<Option<T>> {
         let Some(bytes) = self.payload.as_ref() else {
             return Ok(None);
         };
         serde_json::from_slice(bytes).map_err(Into::into)
     }
+
+    /// Decodes the payload with the [`Codec`] `C`.
+    ///
+    /// Returns `Ok(None)` if the payload was taken out of the delivery.
+    pub fn payload_decoded<C: Codec<T>, T>(&self) -> Result<Option<T>> {
+        self.payload.as_deref().map(C::decode).transpose()
+    }
 }
 
 impl fmt::Debug for Delivery {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Delivery").finish()
+        f.debug_struct("Delivery")
+            .field("metadata", &self.metadata)
+            .finish_non_exhaustive()
     }
 }
@@ -1,8 +1,8 @@
-use std::{future::Future, pin::Pin};
+use std::{borrow::Borrow, future::Future, pin::Pin};
 
 use serde::Serialize;
 
-use crate::{QueuePayload, Result};
+use crate::{codec::Codec, Attributes, Capabilities, QueueError, QueuePayload, Result};
 
 pub trait QueueProducer: Send + Sync + Sized {
     type Payload: QueuePayload;
@@ -11,6 +11,26 @@ pub trait QueueProducer: Send + Sync + Sized {
 
     fn redrive_dlq(&self) -> impl Future<Output = Result<()>> + Send;
 
+    /// Send a raw message along with a set of [`Attributes`].
+    ///
+    /// The default implementation only accepts an empty set of attributes and
+    /// otherwise returns [`QueueError::Unsupported`]. Backends override this to
+    /// map attributes to their native message metadata.
+    fn send_raw_with_attributes(
+        &self,
+        payload: &Self::Payload,
+        attributes: &Attributes,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            if !attributes.is_empty() {
+                return Err(QueueError::Unsupported(
+                    "message attributes are not supported by this backend",
+                ));
+            }
+            self.send_raw(payload).await
+        }
+    }
+
     /// Send a batch of raw messages.
     ///
     /// The default implementation of this sends the payloads sequentially using
@@ -36,6 +56,17 @@ pub trait QueueProducer: Send + Sync + Sized {
         }
     }
 
+    fn send_bytes_with_attributes(
+        &self,
+        payload: &[u8],
+        attributes: &Attributes,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let payload = Self::Payload::from_bytes_naive(payload)?;
+            self.send_raw_with_attributes(&payload, attributes).await
+        }
+    }
+
     #[tracing::instrument(name = "send_batch", skip_all)]
     fn send_bytes_batch(
         &self,
@@ -60,6 +91,17 @@ pub trait QueueProducer: Send + Sync + Sized {
         }
     }
 
+    fn send_serde_json_with_attributes<P: Serialize + Sync>(
+        &self,
+        payload: &P,
+        attributes: &Attributes,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let payload = serde_json::to_vec(payload)?;
+            self.send_bytes_with_attributes(&payload, attributes).await
+        }
+    }
+
     #[tracing::instrument(name = "send_batch", skip_all)]
     fn send_serde_json_batch(
         &self,
@@ -77,6 +119,74 @@ pub trait QueueProducer: Send + Sync + Sized {
         }
     }
 
+    /// Send a message encoded with the [`Codec`] `C`.
+    fn send_encoded<C: Codec<T>, T: Sync>(
+        &self,
+        payload: &T,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let payload = C::encode(payload)?;
+            self.send_bytes(&payload).await
+        }
+    }
+
+    fn send_encoded_with_attributes<C: Codec<T>, T: Sync>(
+        &self,
+        payload: &T,
+        attributes: &Attributes,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let payload = C::encode(payload)?;
+            self.send_bytes_with_attributes(&payload, attributes).await
+        }
+    }
+
+    #[tracing::instrument(name = "send_batch", skip_all)]
+    fn send_encoded_batch<C: Codec<T>, T>(
+        &self,
+        payloads: impl IntoIterator<Item: Borrow<T> + Send, IntoIter: Send> + Send,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let payloads: Vec<_> = payloads
+                .into_iter()
+                .map(|payload| {
+                    let payload = C::encode(payload.borrow())?;
+                    Self::Payload::from_bytes_naive(&payload)
+                })
+                .collect::<Result<_>>()?;
+            self.send_raw_batch(payloads).await
+        }
+    }
+
+    /// Checks that the broker can be reached, for use in readiness probes.
+    ///
+    /// Backends make a cheap request to the broker that also verifies that
+    /// the queue or topic exists. The default implementation returns
+    /// [`QueueError::Unsupported`].
+    fn health_check(&self) -> impl Future<Output = Result<()>> + Send {
+        async {
+            Err(QueueError::Unsupported(
+                "health checks are not supported by this backend",
+            ))
+        }
+    }
+
+    /// Whether this producer can only send UTF-8 text payloads.
+    ///
+    /// Middleware that produces binary data uses this to decide whether to
+    /// encode that data as text. Wrappers such as [`DynProducer`] report it
+    /// for the producer they wrap.
+    fn payload_is_text(&self) -> bool {
+        false
+    }
+
+    /// Returns what the backend supports.
+    ///
+    /// The default implementation reports no support for anything.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
     fn into_dyn(self) -> DynProducer
     where
         Self: 'static,
@@ -100,7 +210,24 @@ pub(crate) trait ErasedQueueProducer: Send + Sync {
         payload: &'a [u8],
     ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
 
+    fn send_raw_with_attributes<'a>(
+        &'a self,
+        payload: &'a [u8],
+        attributes: &'a Attributes,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+    fn send_raw_batch<'a>(
+        &'a self,
+        payloads: &'a [&'a [u8]],
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
     fn redrive_dlq<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+    fn health_check<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+    fn payload_is_text(&self) -> bool;
+
+    fn capabilities(&self) -> Capabilities;
 }
 
 struct DynProducerInner<P> {
@@ -115,9 +242,40 @@ impl<P: QueueProducer> ErasedQueueProducer for DynProducerInner<P> {
         Box::pin(async move { self.inner.send_bytes(payload).await })
     }
 
+    fn send_raw_with_attributes<'a>(
+        &'a self,
+        payload: &'a [u8],
+        attributes: &'a Attributes,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            self.inner
+                .send_bytes_with_attributes(payload, attributes)
+                .await
+        })
+    }
+
+    fn send_raw_batch<'a>(
+        &'a self,
+        payloads: &'a [&'a [u8]],
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move { self.inner.send_bytes_batch(payloads).await })
+    }
+
     fn redrive_dlq<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
         Box::pin(async move { self.inner.redrive_dlq().await })
     }
+
+    fn health_check<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move { self.inner.health_check().await })
+    }
+
+    fn payload_is_text(&self) -> bool {
+        self.inner.payload_is_text()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
 }
 
 impl DynProducer {
@@ -130,12 +288,183 @@ impl DynProducer {
         self.send_raw(&payload).await
     }
 
+    pub async fn send_raw_with_attributes(
+        &self,
+        payload: &[u8],
+        attributes: &Attributes,
+    ) -> Result<()> {
+        self.0.send_raw_with_attributes(payload, attributes).await
+    }
+
+    pub async fn send_serde_json_with_attributes<P: Serialize + Sync>(
+        &self,
+        payload: &P,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = serde_json::to_vec(payload)?;
+        self.send_raw_with_attributes(&payload, attributes).await
+    }
+
+    pub async fn send_encoded<C: Codec<T>, T: Sync>(&self, payload: &T) -> Result<()> {
+        let payload = C::encode(payload)?;
+        self.send_raw(&payload).await
+    }
+
+    pub async fn send_encoded_with_attributes<C: Codec<T>, T: Sync>(
+        &self,
+        payload: &T,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = C::encode(payload)?;
+        self.send_raw_with_attributes(&payload, attributes).await
+    }
+
+    /// Send a batch of raw messages.
+    ///
+    /// This uses the batch implementation of the wrapped producer, so it is
+    /// as efficient as calling [`QueueProducer::send_raw_batch`] on that.
+    pub async fn send_raw_batch(
+        &self,
+        payloads: impl IntoIterator<Item: AsRef<[u8]> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let payloads = PayloadBatch::new(payloads);
+        self.0.send_raw_batch(&payloads.as_slices()).await
+    }
+
+    /// Send a batch of raw messages.
+    ///
+    /// The same as [`send_raw_batch`](Self::send_raw_batch).
+    pub async fn send_bytes_batch(
+        &self,
+        payloads: impl IntoIterator<Item: AsRef<[u8]> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        self.send_raw_batch(payloads).await
+    }
+
+    pub async fn send_serde_json_batch(
+        &self,
+        payloads: impl IntoIterator<Item: Serialize + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let payloads = payloads
+            .into_iter()
+            .map(|payload| serde_json::to_vec(&payload))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.send_raw_batch(payloads).await
+    }
+
+    pub async fn send_encoded_batch<C: Codec<T>, T>(
+        &self,
+        payloads: impl IntoIterator<Item: Borrow<T> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let payloads = payloads
+            .into_iter()
+            .map(|payload| C::encode(payload.borrow()))
+            .collect::<Result<Vec<_>>>()?;
+        self.send_raw_batch(payloads).await
+    }
+
     pub async fn redrive_dlq(&self) -> Result<()> {
         self.0.redrive_dlq().await
     }
+
+    pub async fn health_check(&self) -> Result<()> {
+        self.0.health_check().await
+    }
+
+    /// Returns what the backend of the wrapped producer supports.
+    pub fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
 }
 
 impl crate::QueueProducer for DynProducer {
     type Payload = Vec<u8>;
-    omni_delegate!(send_raw, send_serde_json, redrive_dlq);
+    omni_delegate!(
+        send_raw,
+        send_serde_json,
+        send_raw_with_attributes,
+        send_serde_json_with_attributes,
+        send_raw_batch,
+        send_bytes_batch,
+        send_serde_json_batch,
+        redrive_dlq,
+        health_check
+    );
+
+    fn payload_is_text(&self) -> bool {
+        self.0.payload_is_text()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
+}
+
+/// A batch of payloads collected from an iterator, so it can be passed on as
+/// byte slices.
+pub(crate) struct PayloadBatch<T>(Vec<T>);
+
+impl<T> PayloadBatch<T> {
+    pub(crate) fn new(payloads: impl IntoIterator<Item = T>) -> Self {
+        Self(payloads.into_iter().collect())
+    }
+
+    /// Borrows the payloads as byte slices, where each payload is a reference
+    /// to a `P`, such as `[u8]` or `Vec<u8>`.
+    pub(crate) fn as_slices<'a, P>(&'a self) -> Vec<&'a [u8]>
+    where
+        T: AsRef<P>,
+        P: AsRef<[u8]> + ?Sized + 'a,
+    {
+        self.0.iter().map(|p| p.as_ref().as_ref()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::QueueProducer;
+    use crate::{QueueError, Result};
+
+    /// Records the sizes of the batches it is asked to send.
+    struct BatchRecorder(Arc<Mutex<Vec<usize>>>);
+
+    impl QueueProducer for BatchRecorder {
+        type Payload = Vec<u8>;
+
+        async fn send_raw(&self, _payload: &Vec<u8>) -> Result<()> {
+            self.0.lock().unwrap().push(1);
+            Ok(())
+        }
+
+        async fn send_raw_batch(
+            &self,
+            payloads: impl IntoIterator<Item: AsRef<Vec<u8>> + Send, IntoIter: Send> + Send,
+        ) -> Result<()> {
+            let len = payloads.into_iter().count();
+            self.0.lock().unwrap().push(len);
+            Ok(())
+        }
+
+        async fn redrive_dlq(&self) -> Result<()> {
+            Err(QueueError::Unsupported("DLQ redrive"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dyn_send_batch() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let p = BatchRecorder(batches.clone()).into_dyn();
+
+        p.send_raw_batch([b"1".as_slice(), b"2"]).await.unwrap();
+        p.send_serde_json_batch([1, 2, 3]).await.unwrap();
+        QueueProducer::send_raw_batch(&p, [b"1".to_vec()])
+            .await
+            .unwrap();
+
+        // Batches are passed on to the batch implementation of the wrapped
+        // producer rather than being sent one message at a time.
+        assert_eq!(*batches.lock().unwrap(), [2, 3, 1]);
+    }
 }
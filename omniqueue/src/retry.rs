@@ -0,0 +1,299 @@
+//! Retry policies for deliveries that failed to be processed.
+//!
+//! A [`RetryPolicy`] decides, based on a delivery's receive count and the
+//! error it failed with, whether to redeliver it after a backoff via
+//! [`Delivery::nack_with_delay`], or to give up and dead-letter it.
+//!
+//! ```no_run
+//! # async {
+//! use std::time::Duration;
+//!
+//! use omniqueue::{backends::InMemoryBackend, retry::RetryPolicy};
+//!
+//! let (p, mut c) = InMemoryBackend::builder().build_pair().await?;
+//! let policy = RetryPolicy::new(5)
+//!     .initial_backoff(Duration::from_secs(1))
+//!     .max_backoff(Duration::from_secs(60));
+//!
+//! let delivery = c.receive().await?;
+//! if let Err(e) = delivery.payload_serde_json::<String>() {
+//!     policy
+//!         .handle_failure(delivery, Some(&e))
+//!         .await
+//!         .map_err(|(e, _)| e)?;
+//! }
+//! # anyhow::Ok(())
+//! # };
+//! ```
+
+use std::{error::Error, fmt, sync::Arc, time::Duration};
+
+use crate::{Delivery, DynProducer, QueueError, Result};
+
+type RetryablePredicate = dyn Fn(&(dyn Error + Send + Sync + 'static)) -> bool + Send + Sync;
+
+/// What to do with a delivery that failed to be processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Redeliver it after the given delay.
+    Retry(Duration),
+    /// Stop retrying it.
+    DeadLetter,
+}
+
+/// Describes how often and when failed deliveries are retried.
+///
+/// The backoff before attempt `n + 1` is `initial_backoff * multiplier^(n -
+/// 1)`, capped at `max_backoff`, and then reduced by a random fraction of up
+/// to `jitter`.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+    retryable: Option<Arc<RetryablePredicate>>,
+    dead_letter: Option<Arc<DynProducer>>,
+}
+
+impl RetryPolicy {
+    /// Creates a policy that gives up on a delivery once it has been received
+    /// `max_attempts` times.
+    ///
+    /// By default the backoff starts at one second, doubles with every
+    /// attempt up to five minutes, and has a jitter of one half. All errors
+    /// are retryable.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+            multiplier: 2.0,
+            jitter: 0.5,
+            retryable: None,
+            dead_letter: None,
+        }
+    }
+
+    /// Sets the backoff before the second attempt.
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Sets the upper limit for the backoff.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Sets the factor the backoff grows by with every attempt.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Sets the largest fraction the backoff is randomly reduced by, between
+    /// `0.0` (no jitter) and `1.0` (anywhere between zero and the full
+    /// backoff). NaN disables jitter.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = if jitter.is_nan() {
+            0.0
+        } else {
+            jitter.clamp(0.0, 1.0)
+        };
+        self
+    }
+
+    /// Only retries deliveries that failed with errors matching `predicate`.
+    ///
+    /// Deliveries that failed with other errors are dead-lettered
+    /// immediately. Failures without an error are always retryable.
+    ///
+    /// For example, to only retry transient [`QueueError`]s:
+    ///
+    /// ```
+    /// use omniqueue::{retry::RetryPolicy, QueueError};
+    ///
+    /// let policy = RetryPolicy::new(5).retry_if(|e| {
+    ///     e.downcast_ref::<QueueError>()
+    ///         .is_some_and(QueueError::is_transient)
+    /// });
+    /// ```
+    pub fn retry_if(
+        mut self,
+        predicate: impl Fn(&(dyn Error + Send + Sync + 'static)) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retryable = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Sends the payload and attributes of dead-lettered deliveries to
+    /// `producer`, then acks them.
+    ///
+    /// Without a dead letter producer, dead-lettered deliveries are nacked,
+    /// leaving it to the backend's own dead-lettering (such as an SQS redrive
+    /// policy or [`DeadLetterQueueConfig`] for Redis) to stop redelivering
+    /// them.
+    ///
+    /// [`DeadLetterQueueConfig`]: crate::backends::redis::DeadLetterQueueConfig
+    pub fn dead_letter_to(mut self, producer: DynProducer) -> Self {
+        self.dead_letter = Some(Arc::new(producer));
+        self
+    }
+
+    pub(crate) fn has_dead_letter_producer(&self) -> bool {
+        self.dead_letter.is_some()
+    }
+
+    /// Returns the backoff after the given attempt failed, without jitter.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    /// Decides what to do with a delivery that failed with `error` after
+    /// having been received `receive_count` times.
+    ///
+    /// An unknown receive count is treated as the first attempt.
+    pub fn decide(
+        &self,
+        receive_count: Option<u32>,
+        error: Option<&(dyn Error + Send + Sync + 'static)>,
+    ) -> RetryDecision {
+        let attempt = receive_count.unwrap_or(1);
+        let retryable = match (&self.retryable, error) {
+            (Some(predicate), Some(error)) => predicate(error),
+            _ => true,
+        };
+
+        if !retryable || attempt >= self.max_attempts {
+            return RetryDecision::DeadLetter;
+        }
+
+        let backoff = self.backoff(attempt);
+        let jitter = self.jitter * fastrand::f64();
+        RetryDecision::Retry(backoff.mul_f64(1.0 - jitter))
+    }
+
+    /// Retries or dead-letters a delivery that failed to be processed,
+    /// according to [`decide`](Self::decide).
+    ///
+    /// On failure, the delivery is returned alongside the error to allow
+    /// retrying.
+    pub async fn handle_failure(
+        &self,
+        delivery: Delivery,
+        error: Option<&(dyn Error + Send + Sync + 'static)>,
+    ) -> Result<(), (QueueError, Delivery)> {
+        match self.decide(delivery.metadata().receive_count, error) {
+            RetryDecision::Retry(delay) => delivery.nack_with_delay(delay).await,
+            RetryDecision::DeadLetter => self.dead_letter(delivery).await,
+        }
+    }
+
+    async fn dead_letter(&self, delivery: Delivery) -> Result<(), (QueueError, Delivery)> {
+        let Some(producer) = &self.dead_letter else {
+            return delivery.nack().await;
+        };
+        let Some(payload) = delivery.borrow_payload() else {
+            tracing::warn!("payload was taken from delivery, nacking instead of dead-lettering");
+            return delivery.nack().await;
+        };
+
+        if let Err(e) = producer
+            .send_raw_with_attributes(payload, delivery.attributes())
+            .await
+        {
+            return Err((e, delivery));
+        }
+        delivery.ack().await
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("multiplier", &self.multiplier)
+            .field("jitter", &self.jitter)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{RetryDecision, RetryPolicy};
+    use crate::QueueError;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(10)
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_secs(1));
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_decide() {
+        let policy = RetryPolicy::new(3)
+            .initial_backoff(Duration::from_secs(1))
+            .jitter(0.0)
+            .retry_if(|e| !matches!(e.downcast_ref(), Some(QueueError::PayloadTooLarge { .. })));
+
+        assert_eq!(
+            policy.decide(None, None),
+            RetryDecision::Retry(Duration::from_secs(1))
+        );
+        assert_eq!(
+            policy.decide(Some(2), Some(&QueueError::NoData)),
+            RetryDecision::Retry(Duration::from_secs(2))
+        );
+        assert_eq!(policy.decide(Some(3), None), RetryDecision::DeadLetter);
+
+        let too_large = QueueError::PayloadTooLarge {
+            limit: 1,
+            actual: 2,
+        };
+        assert_eq!(
+            policy.decide(Some(1), Some(&too_large)),
+            RetryDecision::DeadLetter
+        );
+    }
+
+    #[test]
+    fn test_jitter() {
+        let policy = RetryPolicy::new(3)
+            .initial_backoff(Duration::from_secs(1))
+            .jitter(0.5);
+
+        for _ in 0..100 {
+            let RetryDecision::Retry(delay) = policy.decide(Some(1), None) else {
+                panic!("expected a retry");
+            };
+            assert!(delay > Duration::from_millis(500));
+            assert!(delay <= Duration::from_secs(1));
+        }
+
+        let policy = RetryPolicy::new(3)
+            .initial_backoff(Duration::from_secs(1))
+            .jitter(f64::NAN);
+        assert_eq!(
+            policy.decide(Some(1), None),
+            RetryDecision::Retry(Duration::from_secs(1))
+        );
+    }
+}
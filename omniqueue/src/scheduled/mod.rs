@@ -1,8 +1,12 @@
-use std::{future::Future, pin::Pin, time::Duration};
+use std::{borrow::Borrow, future::Future, pin::Pin, time::Duration};
 
 use serde::Serialize;
 
-use crate::{queue::ErasedQueueProducer, QueuePayload, QueueProducer, Result};
+use crate::{
+    codec::Codec,
+    queue::{ErasedQueueProducer, PayloadBatch},
+    Attributes, Capabilities, QueuePayload, QueueProducer, Result,
+};
 
 pub trait ScheduledQueueProducer: QueueProducer {
     fn send_raw_scheduled(
@@ -33,6 +37,17 @@ pub trait ScheduledQueueProducer: QueueProducer {
         }
     }
 
+    fn send_encoded_scheduled<C: Codec<T>, T: Sync>(
+        &self,
+        payload: &T,
+        delay: Duration,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let payload = C::encode(payload)?;
+            self.send_bytes_scheduled(&payload, delay).await
+        }
+    }
+
     fn into_dyn_scheduled(self) -> DynScheduledProducer
     where
         Self: 'static,
@@ -69,9 +84,35 @@ impl<P: ScheduledQueueProducer> ErasedQueueProducer for DynScheduledProducerInne
     ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
         Box::pin(async move { self.inner.send_bytes(payload).await })
     }
+    fn send_raw_with_attributes<'a>(
+        &'a self,
+        payload: &'a [u8],
+        attributes: &'a Attributes,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            self.inner
+                .send_bytes_with_attributes(payload, attributes)
+                .await
+        })
+    }
+    fn send_raw_batch<'a>(
+        &'a self,
+        payloads: &'a [&'a [u8]],
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move { self.inner.send_bytes_batch(payloads).await })
+    }
     fn redrive_dlq<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
         Box::pin(async move { self.inner.redrive_dlq().await })
     }
+    fn health_check<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move { self.inner.health_check().await })
+    }
+    fn payload_is_text(&self) -> bool {
+        self.inner.payload_is_text()
+    }
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
 }
 
 impl<P: ScheduledQueueProducer> ErasedScheduledQueueProducer for DynScheduledProducerInner<P> {
@@ -94,6 +135,23 @@ impl DynScheduledProducer {
         self.send_raw(&payload).await
     }
 
+    pub async fn send_raw_with_attributes(
+        &self,
+        payload: &[u8],
+        attributes: &Attributes,
+    ) -> Result<()> {
+        self.0.send_raw_with_attributes(payload, attributes).await
+    }
+
+    pub async fn send_serde_json_with_attributes<P: Serialize + Sync>(
+        &self,
+        payload: &P,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = serde_json::to_vec(payload)?;
+        self.send_raw_with_attributes(&payload, attributes).await
+    }
+
     pub async fn send_raw_scheduled(&self, payload: &[u8], delay: Duration) -> Result<()> {
         self.0.send_raw_scheduled(payload, delay).await
     }
@@ -107,14 +165,108 @@ impl DynScheduledProducer {
         self.0.send_raw_scheduled(&payload, delay).await
     }
 
+    pub async fn send_encoded<C: Codec<T>, T: Sync>(&self, payload: &T) -> Result<()> {
+        let payload = C::encode(payload)?;
+        self.send_raw(&payload).await
+    }
+
+    pub async fn send_encoded_with_attributes<C: Codec<T>, T: Sync>(
+        &self,
+        payload: &T,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let payload = C::encode(payload)?;
+        self.send_raw_with_attributes(&payload, attributes).await
+    }
+
+    pub async fn send_encoded_scheduled<C: Codec<T>, T: Sync>(
+        &self,
+        payload: &T,
+        delay: Duration,
+    ) -> Result<()> {
+        let payload = C::encode(payload)?;
+        self.0.send_raw_scheduled(&payload, delay).await
+    }
+
+    /// Send a batch of raw messages.
+    ///
+    /// This uses the batch implementation of the wrapped producer, so it is
+    /// as efficient as calling [`QueueProducer::send_raw_batch`] on that.
+    pub async fn send_raw_batch(
+        &self,
+        payloads: impl IntoIterator<Item: AsRef<[u8]> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let payloads = PayloadBatch::new(payloads);
+        self.0.send_raw_batch(&payloads.as_slices()).await
+    }
+
+    /// Send a batch of raw messages.
+    ///
+    /// The same as [`send_raw_batch`](Self::send_raw_batch).
+    pub async fn send_bytes_batch(
+        &self,
+        payloads: impl IntoIterator<Item: AsRef<[u8]> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        self.send_raw_batch(payloads).await
+    }
+
+    pub async fn send_serde_json_batch(
+        &self,
+        payloads: impl IntoIterator<Item: Serialize + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let payloads = payloads
+            .into_iter()
+            .map(|payload| serde_json::to_vec(&payload))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.send_raw_batch(payloads).await
+    }
+
+    pub async fn send_encoded_batch<C: Codec<T>, T>(
+        &self,
+        payloads: impl IntoIterator<Item: Borrow<T> + Send, IntoIter: Send> + Send,
+    ) -> Result<()> {
+        let payloads = payloads
+            .into_iter()
+            .map(|payload| C::encode(payload.borrow()))
+            .collect::<Result<Vec<_>>>()?;
+        self.send_raw_batch(payloads).await
+    }
+
     pub async fn redrive_dlq(&self) -> Result<()> {
         self.0.redrive_dlq().await
     }
+
+    pub async fn health_check(&self) -> Result<()> {
+        self.0.health_check().await
+    }
+
+    /// Returns what the backend of the wrapped producer supports.
+    pub fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
 }
 
 impl crate::QueueProducer for DynScheduledProducer {
     type Payload = Vec<u8>;
-    omni_delegate!(send_raw, send_serde_json, redrive_dlq);
+    omni_delegate!(
+        send_raw,
+        send_serde_json,
+        send_raw_with_attributes,
+        send_serde_json_with_attributes,
+        send_raw_batch,
+        send_bytes_batch,
+        send_serde_json_batch,
+        redrive_dlq,
+        health_check
+    );
+
+    fn payload_is_text(&self) -> bool {
+        self.0.payload_is_text()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
 }
 impl crate::ScheduledQueueProducer for DynScheduledProducer {
     omni_delegate!(send_raw_scheduled, send_serde_json_scheduled);
@@ -0,0 +1,460 @@
+//! A managed worker loop on top of [`QueueConsumer`].
+//!
+//! [`Worker`] receives deliveries, runs an async handler on each of them with
+//! bounded concurrency, and acks or nacks depending on the handler's result:
+//!
+//! ```no_run
+//! # async {
+//! use omniqueue::{
+//!     backends::InMemoryBackend,
+//!     worker::{Job, Outcome, Worker},
+//! };
+//!
+//! let (p, c) = InMemoryBackend::builder().build_pair().await?;
+//! let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+//!
+//! Worker::new(c, |job: Job| async move {
+//!     let name: String = job.payload_serde_json()?;
+//!     println!("hello, {name}");
+//!     Ok::<_, omniqueue::QueueError>(Outcome::Ack)
+//! })
+//! .concurrency(8)
+//! .run_until(async {
+//!     shutdown_rx.await.ok();
+//! })
+//! .await;
+//! # anyhow::Ok(())
+//! # };
+//! ```
+
+use std::{
+    cmp::min,
+    error::Error,
+    future::{pending, Future},
+    num::NonZeroUsize,
+    panic::AssertUnwindSafe,
+    pin::pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures_util::{
+    future::{select, Either},
+    FutureExt as _,
+};
+use serde::de::DeserializeOwned;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{retry::RetryPolicy, Attributes, Delivery, DeliveryMetadata, QueueConsumer, Result};
+
+/// How long a single `receive_all` call waits for its batch to fill up.
+const RECEIVE_DEADLINE: Duration = Duration::from_secs(5);
+/// How long to wait before receiving again after a receive error.
+pub(crate) const RECEIVE_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// What the worker should do with a delivery once its handler has run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Acknowledge the delivery, so it is not processed again.
+    Ack,
+    /// Negatively acknowledge the delivery, so the backend can redeliver it or
+    /// move it to a dead letter queue.
+    Nack,
+}
+
+/// The error type handlers' errors get converted to.
+pub type HandlerError = Box<dyn Error + Send + Sync>;
+
+/// A delivery's contents, as passed to a [`Worker`]'s handler.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Job {
+    pub payload: Vec<u8>,
+    pub attributes: Attributes,
+    pub metadata: DeliveryMetadata,
+}
+
+impl Job {
+    fn from_delivery(delivery: &mut Delivery, keep_payload: bool) -> Self {
+        let payload = if keep_payload {
+            delivery.borrow_payload().map(ToOwned::to_owned)
+        } else {
+            delivery.take_payload()
+        };
+        Self {
+            payload: payload.unwrap_or_default(),
+            attributes: delivery.attributes().clone(),
+            metadata: delivery.metadata().clone(),
+        }
+    }
+
+    pub fn payload_serde_json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.payload).map_err(Into::into)
+    }
+}
+
+/// Runs a handler on every delivery received from a consumer.
+///
+/// Deliveries are acked if the handler returns [`Outcome::Ack`], and nacked if
+/// it returns [`Outcome::Nack`], returns an error or panics. With a
+/// [`retry_policy`](Self::retry_policy), the policy decides how to nack them
+/// instead.
+pub struct Worker<C, H> {
+    consumer: C,
+    handler: H,
+    concurrency: NonZeroUsize,
+    keep_alive: Option<Duration>,
+    retry_policy: Option<Arc<RetryPolicy>>,
+}
+
+impl<C, H> Worker<C, H> {
+    /// Creates a new worker that runs `handler` on deliveries from
+    /// `consumer`, one at a time.
+    pub fn new(consumer: C, handler: H) -> Self {
+        Self {
+            consumer,
+            handler,
+            concurrency: NonZeroUsize::MIN,
+            keep_alive: None,
+            retry_policy: None,
+        }
+    }
+
+    /// Sets how many deliveries may be handled concurrently.
+    ///
+    /// A value of zero is treated as one.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = NonZeroUsize::new(concurrency).unwrap_or(NonZeroUsize::MIN);
+        self
+    }
+
+    /// Keeps deliveries from being redelivered while their handler is still
+    /// running, by repeatedly extending their ack deadline.
+    ///
+    /// See [`Delivery::keep_alive`] for details.
+    pub fn keep_alive(mut self, ack_deadline: Duration) -> Self {
+        self.keep_alive = Some(ack_deadline);
+        self
+    }
+
+    /// Retries or dead-letters deliveries that weren't handled successfully
+    /// according to `policy`, rather than simply nacking them.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Runs the worker forever.
+    ///
+    /// Receive errors are logged and retried after a short backoff, and errors
+    /// while settling deliveries are logged as well.
+    pub async fn run<Fut, E>(self)
+    where
+        C: QueueConsumer,
+        H: Fn(Job) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Outcome, E>> + Send + 'static,
+        E: Into<HandlerError>,
+    {
+        self.run_until(pending()).await
+    }
+
+    /// Runs the worker until `shutdown` completes.
+    ///
+    /// Once `shutdown` completes, no more deliveries are received, and this
+    /// method returns after all deliveries that are already being handled
+    /// have been acked or nacked.
+    pub async fn run_until<Fut, E>(self, shutdown: impl Future<Output = ()>)
+    where
+        C: QueueConsumer,
+        H: Fn(Job) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Outcome, E>> + Send + 'static,
+        E: Into<HandlerError>,
+    {
+        let Self {
+            mut consumer,
+            handler,
+            concurrency,
+            keep_alive,
+            retry_policy,
+        } = self;
+
+        let handler = Arc::new(handler);
+        let semaphore = Arc::new(Semaphore::new(concurrency.get()));
+        let batch_max = consumer
+            .max_messages()
+            .map_or(usize::MAX, NonZeroUsize::get);
+        let mut in_flight = JoinSet::new();
+        let mut shutdown = pin!(shutdown);
+
+        loop {
+            // Only receive once there is capacity to handle what's received.
+            let acquire = pin!(semaphore.clone().acquire_owned());
+            let permit = match select(acquire, shutdown.as_mut()).await {
+                Either::Left((permit, _)) => permit.expect("semaphore is never closed"),
+                Either::Right(_) => break,
+            };
+
+            let max_messages = min(1 + semaphore.available_permits(), batch_max);
+            let receive = pin!(consumer.receive_all(max_messages, RECEIVE_DEADLINE));
+            let deliveries = match select(receive, shutdown.as_mut()).await {
+                Either::Left((Ok(deliveries), _)) => deliveries,
+                Either::Left((Err(e), _)) => {
+                    tracing::error!(error = %e, "failed to receive deliveries");
+                    drop(permit);
+                    let backoff = pin!(tokio::time::sleep(RECEIVE_ERROR_BACKOFF));
+                    match select(backoff, shutdown.as_mut()).await {
+                        Either::Left(_) => continue,
+                        Either::Right(_) => break,
+                    }
+                }
+                Either::Right(_) => break,
+            };
+
+            let mut permit = Some(permit);
+            for delivery in deliveries {
+                // No one else acquires permits, so `try_acquire_owned` only
+                // fails if the backend returned more than `max_messages`.
+                let permit = match permit.take() {
+                    Some(permit) => permit,
+                    None => match semaphore.clone().try_acquire_owned() {
+                        Ok(permit) => permit,
+                        Err(_) => semaphore
+                            .clone()
+                            .acquire_owned()
+                            .await
+                            .expect("semaphore is never closed"),
+                    },
+                };
+                let delivery = match keep_alive {
+                    Some(ack_deadline) => delivery.keep_alive(ack_deadline),
+                    None => delivery,
+                };
+                let handler = handler.clone();
+                let retry_policy = retry_policy.clone();
+                in_flight.spawn(async move {
+                    handle(&*handler, retry_policy.as_deref(), delivery).await;
+                    drop(permit);
+                });
+            }
+
+            // Reap finished tasks so the set doesn't grow forever.
+            while in_flight.try_join_next().is_some() {}
+        }
+
+        while in_flight.join_next().await.is_some() {}
+    }
+}
+
+async fn handle<H, Fut, E>(handler: &H, retry_policy: Option<&RetryPolicy>, mut delivery: Delivery)
+where
+    H: Fn(Job) -> Fut,
+    Fut: Future<Output = Result<Outcome, E>>,
+    E: Into<HandlerError>,
+{
+    // Dead-lettering needs the payload after the handler has run.
+    let keep_payload = retry_policy.is_some_and(RetryPolicy::has_dead_letter_producer);
+    let job = Job::from_delivery(&mut delivery, keep_payload);
+    let (outcome, error) = match AssertUnwindSafe(async { handler(job).await })
+        .catch_unwind()
+        .await
+    {
+        Ok(Ok(outcome)) => (outcome, None),
+        Ok(Err(e)) => {
+            let e: HandlerError = e.into();
+            tracing::error!(error = %e, "handler failed");
+            (Outcome::Nack, Some(e))
+        }
+        Err(_) => {
+            tracing::error!("handler panicked");
+            (Outcome::Nack, None)
+        }
+    };
+
+    let result = match (outcome, retry_policy) {
+        (Outcome::Ack, _) => delivery.ack().await,
+        (Outcome::Nack, Some(policy)) => policy.handle_failure(delivery, error.as_deref()).await,
+        (Outcome::Nack, None) => delivery.nack().await,
+    };
+    if let Err((e, _)) = result {
+        tracing::error!(error = %e, ?outcome, "failed to settle delivery");
+    }
+}
+
+#[cfg(all(test, feature = "in_memory"))]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use tokio::sync::{oneshot, Barrier};
+
+    use super::{Job, Outcome, Worker};
+    use crate::{
+        backends::InMemoryBackend, retry::RetryPolicy, Attributes, QueueError, QueueProducer as _,
+    };
+
+    #[tokio::test]
+    async fn test_acks_and_stops_on_shutdown() {
+        let (p, c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        for a in 0..5 {
+            p.send_serde_json(&a).await.unwrap();
+        }
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let done_tx = std::sync::Mutex::new(Some(done_tx));
+        let sum = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::new(AtomicUsize::new(0));
+
+        let worker = {
+            let sum = sum.clone();
+            Worker::new(c, move |job: Job| {
+                let sum = sum.clone();
+                let done_tx = (seen.fetch_add(1, Ordering::SeqCst) == 4)
+                    .then(|| done_tx.lock().unwrap().take())
+                    .flatten();
+                async move {
+                    sum.fetch_add(job.payload_serde_json()?, Ordering::SeqCst);
+                    if let Some(tx) = done_tx {
+                        tx.send(()).unwrap();
+                    }
+                    Ok::<_, QueueError>(Outcome::Ack)
+                }
+            })
+            .concurrency(2)
+        };
+
+        worker
+            .run_until(async {
+                done_rx.await.unwrap();
+            })
+            .await;
+        assert_eq!(sum.load(Ordering::SeqCst), (0..5).sum::<usize>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrency_limit_and_drain() {
+        let (p, c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        for _ in 0..6 {
+            p.send_raw(b"").await.unwrap();
+        }
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let finished = Arc::new(AtomicUsize::new(0));
+        // Passed by the three handlers that may run at once and by the shutdown
+        let started = Arc::new(Barrier::new(4));
+
+        let worker = {
+            let (running, max_running, finished, started) = (
+                running.clone(),
+                max_running.clone(),
+                finished.clone(),
+                started.clone(),
+            );
+            Worker::new(c, move |_job: Job| {
+                let (running, max_running, finished, started) = (
+                    running.clone(),
+                    max_running.clone(),
+                    finished.clone(),
+                    started.clone(),
+                );
+                async move {
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now_running, Ordering::SeqCst);
+                    started.wait().await;
+                    // Only finish once the worker has seen the shutdown
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    finished.fetch_add(1, Ordering::SeqCst);
+                    Ok::<_, QueueError>(Outcome::Ack)
+                }
+            })
+            .concurrency(3)
+        };
+
+        // Shut down while the first batch is still being handled
+        worker
+            .run_until(async {
+                started.wait().await;
+            })
+            .await;
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_nacks_on_error_and_panic() {
+        let (p, c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        p.send_raw(b"fail").await.unwrap();
+        p.send_raw(b"panic").await.unwrap();
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let done_tx = std::sync::Mutex::new(Some(done_tx));
+
+        Worker::new(c, move |job: Job| {
+            // The in-memory backend redelivers nacked messages, so both
+            // messages come back for a second attempt.
+            let first_attempt = job.metadata.receive_count == Some(1);
+            let done_tx = (!first_attempt && job.payload == b"panic")
+                .then(|| done_tx.lock().unwrap().take())
+                .flatten();
+            async move {
+                match (first_attempt, job.payload.as_slice()) {
+                    (true, b"fail") => Err("failed".into()),
+                    (true, _) => panic!("handler panic"),
+                    (false, _) => {
+                        if let Some(tx) = done_tx {
+                            tx.send(()).unwrap();
+                        }
+                        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Outcome::Ack)
+                    }
+                }
+            }
+        })
+        .run_until(async {
+            done_rx.await.unwrap();
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy() {
+        let (p, c) = InMemoryBackend::builder().build_pair().await.unwrap();
+        let (dlq_p, mut dlq_c) = InMemoryBackend::builder().build_pair().await.unwrap();
+
+        let attributes = Attributes::from([("key".to_owned(), "value".to_owned())]);
+        p.send_raw_with_attributes(b"fail", &attributes)
+            .await
+            .unwrap();
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let policy = RetryPolicy::new(3)
+            .initial_backoff(Duration::from_millis(10))
+            .dead_letter_to(dlq_p.into_dyn());
+
+        let worker = {
+            let attempts = attempts.clone();
+            Worker::new(c, move |_job: Job| {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                async { Err::<Outcome, _>("failed") }
+            })
+            .retry_policy(policy)
+        };
+
+        worker
+            .run_until(async {
+                let delivery = dlq_c.receive().await.unwrap();
+                assert_eq!(delivery.borrow_payload().unwrap(), b"fail");
+                assert_eq!(delivery.attributes(), &attributes);
+                delivery.ack().await.unwrap();
+            })
+            .await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+}
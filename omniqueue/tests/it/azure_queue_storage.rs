@@ -7,7 +7,7 @@ use azure_storage::StorageCredentials;
 use azure_storage_queues::QueueServiceClientBuilder;
 use omniqueue::{
     backends::{AqsBackend, AqsConfig, AqsConsumer, AqsProducer},
-    QueueError,
+    Attributes, QueueError,
 };
 use serde::{Deserialize, Serialize};
 
@@ -86,6 +86,25 @@ async fn test_serde_send_recv() {
     d.ack().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_send_recv_with_attributes() {
+    let (producer, mut consumer) = create_queue_get_a_pair().await;
+
+    let payload = ExType {
+        a: "test123".to_string(),
+    };
+    let attributes = Attributes::from([("trace-id".to_owned(), "abc".to_owned())]);
+    producer
+        .send_serde_json_with_attributes(&payload, &attributes)
+        .await
+        .unwrap();
+
+    let d = consumer.receive().await.unwrap();
+    assert_eq!(d.payload_serde_json::<ExType>().unwrap().unwrap(), payload);
+    assert_eq!(d.attributes(), &attributes);
+    d.ack().await.unwrap();
+}
+
 // Note: Azure Queue Storage doesn't guarantee order of messages, hence
 // the HashSet popping and length validation instead of assuming
 // particular values:
@@ -260,3 +279,46 @@ async fn test_receive_timeout() {
         _ => panic!("Unexpected result"),
     }
 }
+
+#[tokio::test]
+async fn test_admin() {
+    let queue_name: String = std::iter::repeat_with(fastrand::lowercase)
+        .take(8)
+        .collect();
+    let cfg = AqsConfig {
+        queue_name,
+        empty_receive_delay: None,
+        message_ttl: Duration::from_secs(90),
+        storage_account: azure_storage::EMULATOR_ACCOUNT.to_string(),
+        credentials: StorageCredentials::access_key(
+            azure_storage::EMULATOR_ACCOUNT.to_string(),
+            azure_storage::EMULATOR_ACCOUNT_KEY.to_string(),
+        ),
+        cloud_uri: Some(format!(
+            "http://localhost:10001/{}",
+            azure_storage::EMULATOR_ACCOUNT
+        )),
+        receive_timeout: None,
+    };
+    let admin = AqsBackend::builder(cfg.clone())
+        .build_admin()
+        .await
+        .unwrap();
+
+    assert!(!admin.exists().await.unwrap());
+    admin.create_queue().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+
+    let (producer, mut consumer) = AqsBackend::builder(cfg).build_pair().await.unwrap();
+    producer.send_raw("test123").await.unwrap();
+    admin.purge().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+    let d = consumer
+        .receive_all(1, Duration::from_millis(500))
+        .await
+        .unwrap();
+    assert!(d.is_empty());
+
+    admin.delete_queue().await.unwrap();
+    assert!(!admin.exists().await.unwrap());
+}
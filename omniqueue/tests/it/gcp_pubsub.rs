@@ -63,7 +63,7 @@ use gcloud_pubsub::{
 };
 use omniqueue::{
     backends::{GcpPubSubBackend, GcpPubSubConfig},
-    QueueBuilder,
+    Attributes, QueueBuilder,
 };
 use serde::{Deserialize, Serialize};
 
@@ -179,6 +179,22 @@ async fn test_serde_send_recv() {
     d.ack().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_send_recv_with_attributes() {
+    let payload = ExType { a: 2 };
+    let attributes = Attributes::from([("trace-id".to_owned(), "abc".to_owned())]);
+    let (p, mut c) = make_test_queue().await.build_pair().await.unwrap();
+
+    p.send_serde_json_with_attributes(&payload, &attributes)
+        .await
+        .unwrap();
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.payload_serde_json::<ExType>().unwrap().unwrap(), payload);
+    assert_eq!(d.attributes(), &attributes);
+    d.ack().await.unwrap();
+}
+
 /// Consumer will return immediately if there are fewer than max messages to
 /// start with.
 #[tokio::test]
@@ -291,3 +307,49 @@ async fn test_send_recv_all_late_arriving_items() {
     assert!(elapsed >= deadline);
     assert!(elapsed <= deadline + Duration::from_millis(200));
 }
+
+#[tokio::test]
+async fn test_health_check() {
+    let (p, c) = make_test_queue().await.build_pair().await.unwrap();
+    p.health_check().await.unwrap();
+    c.health_check().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_admin() {
+    // The `Default` impl for `ClientConfig` needs the emulator env var, which
+    // `get_client` sets
+    get_client().await;
+
+    let config = GcpPubSubConfig {
+        topic_id: "topic-".chars().chain(random_chars().take(8)).collect(),
+        subscription_id: "subscription-"
+            .chars()
+            .chain(random_chars().take(8))
+            .collect(),
+        credentials_file: None,
+    };
+    let admin = GcpPubSubBackend::builder(config.clone())
+        .build_admin()
+        .await
+        .unwrap();
+
+    assert!(!admin.exists().await.unwrap());
+    admin.create_queue().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+    // Creating an existing queue is a no-op.
+    admin.create_queue().await.unwrap();
+
+    let (p, mut c) = GcpPubSubBackend::builder(config)
+        .build_pair()
+        .await
+        .unwrap();
+    p.send_raw(b"purged").await.unwrap();
+    admin.purge().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+    let d = c.receive_all(1, Duration::from_secs(1)).await.unwrap();
+    assert!(d.is_empty());
+
+    admin.delete_queue().await.unwrap();
+    assert!(!admin.exists().await.unwrap());
+}
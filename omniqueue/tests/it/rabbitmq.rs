@@ -9,7 +9,7 @@ use lapin::{
 };
 use omniqueue::{
     backends::{RabbitMqBackend, RabbitMqConfig},
-    QueueBuilder,
+    Attributes, QueueBuilder,
 };
 use serde::{Deserialize, Serialize};
 
@@ -95,6 +95,7 @@ async fn make_test_queue(
         consume_arguments: FieldTable::default(),
         consume_prefetch_count: prefetch_count,
         requeue_on_nack: reinsert_on_nack,
+        delayed_message_exchange: true,
     };
 
     RabbitMqBackend::builder(config)
@@ -146,6 +147,26 @@ async fn test_serde_send_recv() {
     d.ack().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_send_recv_with_attributes() {
+    let payload = ExType { a: 2 };
+    let attributes = Attributes::from([("trace-id".to_owned(), "abc".to_owned())]);
+    let (p, mut c) = make_test_queue(None, false)
+        .await
+        .build_pair()
+        .await
+        .unwrap();
+
+    p.send_serde_json_with_attributes(&payload, &attributes)
+        .await
+        .unwrap();
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.payload_serde_json::<ExType>().unwrap().unwrap(), payload);
+    assert_eq!(d.attributes(), &attributes);
+    d.ack().await.unwrap();
+}
+
 /// Consumer will return immediately if there are fewer than max messages to
 /// start with.
 #[tokio::test]
@@ -308,3 +329,91 @@ async fn test_scheduled() {
     assert!(now.elapsed() < delay * 2);
     assert_eq!(Some(payload1), delivery.payload_serde_json().unwrap());
 }
+
+#[tokio::test]
+async fn test_nack_with_delay() {
+    let payload = ExType { a: 1 };
+    let (p, mut c) = make_test_queue(None, false)
+        .await
+        .build_pair()
+        .await
+        .unwrap();
+
+    p.send_serde_json(&payload).await.unwrap();
+    let delivery = c.receive().await.unwrap();
+    assert_eq!(delivery.metadata().receive_count, Some(1));
+
+    let delay = Duration::from_secs(1);
+    let now = Instant::now();
+    delivery.nack_with_delay(delay).await.unwrap();
+
+    let delivery = c.receive().await.unwrap();
+    assert!(now.elapsed() >= delay);
+    assert_eq!(Some(payload), delivery.payload_serde_json().unwrap());
+    assert_eq!(delivery.metadata().receive_count, Some(2));
+    delivery.ack().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_health_check() {
+    let (p, c) = make_test_queue(None, false)
+        .await
+        .build_pair()
+        .await
+        .unwrap();
+    p.health_check().await.unwrap();
+    c.health_check().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_admin() {
+    let queue_name: String = std::iter::repeat_with(fastrand::alphanumeric)
+        .take(8)
+        .collect();
+    let cfg = RabbitMqConfig {
+        uri: MQ_URI.to_owned(),
+        connection_properties: ConnectionProperties::default()
+            .with_executor(tokio_executor_trait::Tokio::current())
+            .with_reactor(tokio_reactor_trait::Tokio),
+        publish_exchange: String::new(),
+        publish_routing_key: queue_name.clone(),
+        publish_options: BasicPublishOptions::default(),
+        publish_properties: BasicProperties::default(),
+        consume_queue: queue_name,
+        consumer_tag: "test".to_owned(),
+        consume_options: BasicConsumeOptions::default(),
+        consume_arguments: FieldTable::default(),
+        consume_prefetch_count: None,
+        requeue_on_nack: false,
+        delayed_message_exchange: false,
+    };
+    let admin = RabbitMqBackend::builder(cfg).build_admin().await.unwrap();
+
+    assert!(!admin.exists().await.unwrap());
+    admin.create_queue().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+
+    admin.purge().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+
+    admin.delete_queue().await.unwrap();
+    assert!(!admin.exists().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let (p, c) = make_test_queue(None, false)
+        .await
+        .build_pair()
+        .await
+        .unwrap();
+    p.send_raw(b"test").await.unwrap();
+
+    // The message may already have been pushed to the consumer, making it
+    // unacknowledged rather than ready
+    let stats = c.stats().await.unwrap();
+    assert!(stats.visible.is_some_and(|visible| visible <= 1));
+    assert_eq!(stats.in_flight, None);
+    // Stats are fetched on a separate channel, leaving the consumer's alone
+    c.health_check().await.unwrap();
+}
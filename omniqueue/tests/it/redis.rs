@@ -8,9 +8,12 @@ use omniqueue::{
         redis::{DeadLetterQueueConfig, RedisBackendBuilder, RedisConnection, SentinelConfig},
         RedisBackend, RedisConfig,
     },
-    Delivery,
+    Attributes, Delivery,
+};
+use redis::{
+    streams::{StreamPendingReply, StreamReadOptions, StreamReadReply},
+    AsyncCommands, Client, Commands,
 };
-use redis::{AsyncCommands, Client, Commands};
 use rstest::rstest;
 use serde::{Deserialize, Serialize};
 
@@ -68,11 +71,21 @@ async fn make_test_queue<R: RedisConnection>(
             .await
             .unwrap();
     }
-    let config = RedisConfig {
+
+    (
+        RedisBackendBuilder::new(test_config(dsn, &stream_name)),
+        RedisStreamDrop(stream_name),
+    )
+}
+
+/// Returns the configuration that [`make_test_queue`] uses for the given
+/// stream.
+fn test_config(dsn: String, stream_name: &str) -> RedisConfig {
+    RedisConfig {
         dsn,
         max_connections: 8,
         reinsert_on_nack: false,
-        queue_key: stream_name.clone(),
+        queue_key: stream_name.to_owned(),
         delayed_queue_key: format!("{stream_name}::delayed"),
         delayed_lock_key: format!("{stream_name}::delayed_lock"),
         consumer_group: "test_cg".to_owned(),
@@ -88,12 +101,7 @@ async fn make_test_queue<R: RedisConnection>(
             redis_password: None,
             redis_use_resp3: true,
         }),
-    };
-
-    (
-        RedisBackendBuilder::new(config),
-        RedisStreamDrop(stream_name),
-    )
+    }
 }
 
 #[rstest]
@@ -161,6 +169,52 @@ async fn test_serde_send_recv<R: RedisConnection>(
     d.ack().await.unwrap();
 }
 
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_send_recv_with_attributes<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, _drop) = get_builder.await;
+    let payload = ExType { a: 2 };
+    let attributes = Attributes::from([("trace-id".to_owned(), "abc".to_owned())]);
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    p.send_serde_json_with_attributes(&payload, &attributes)
+        .await
+        .unwrap();
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.payload_serde_json::<ExType>().unwrap().unwrap(), payload);
+    assert_eq!(d.attributes(), &attributes);
+    d.ack().await.unwrap();
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_delivery_metadata<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, _drop) = get_builder.await;
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    p.send_raw(b"hello").await.unwrap();
+
+    let d = c.receive().await.unwrap();
+    let metadata = d.metadata();
+    assert!(metadata.message_id.is_some());
+    assert_eq!(metadata.receive_count, Some(1));
+    assert!(metadata.enqueued_at.is_some());
+    d.ack().await.unwrap();
+}
+
 /// Consumer will return immediately if there are fewer than max messages to
 /// start with.
 #[rstest]
@@ -396,6 +450,116 @@ async fn test_pending<R: RedisConnection>(
         .is_empty());
 }
 
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_keep_alive<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let payload = ExType { a: 1 };
+    let (builder, _drop) = get_builder.await;
+
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    p.send_serde_json(&payload).await.unwrap();
+    let delivery = c
+        .receive()
+        .await
+        .unwrap()
+        .keep_alive(Duration::from_secs(5));
+
+    // Without the keep-alive, the message would be reinserted into the queue
+    // once the 5 second ack deadline passes.
+    assert!(c
+        .receive_all(1, Duration::from_secs(8))
+        .await
+        .unwrap()
+        .is_empty());
+
+    delivery.ack().await.unwrap();
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_nack_with_delay<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let payload = ExType { a: 1 };
+    let (builder, _drop) = get_builder.await;
+
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    p.send_serde_json(&payload).await.unwrap();
+    let delivery = c.receive().await.unwrap();
+    assert_eq!(delivery.metadata().receive_count, Some(1));
+
+    let delay = Duration::from_secs(1);
+    let now = Instant::now();
+    delivery.nack_with_delay(delay).await.unwrap();
+
+    let delivery = c
+        .receive_all(1, delay * 4)
+        .await
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap();
+    assert!(now.elapsed() >= delay);
+    assert_eq!(
+        Some(&payload),
+        delivery.payload_serde_json().unwrap().as_ref()
+    );
+    // The receive count is kept across the delay
+    assert_eq!(delivery.metadata().receive_count, Some(2));
+    delivery.ack().await.unwrap();
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_nack_with_delay_keeps_attributes<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let attributes = Attributes::from([
+        ("trace-id".to_owned(), "abc".to_owned()),
+        ("needs|escaping".to_owned(), "{\"#@\"}".to_owned()),
+    ]);
+    let (builder, _drop) = get_builder.await;
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    p.send_raw_with_attributes(b"payload|with#separators", &attributes)
+        .await
+        .unwrap();
+    let delivery = c.receive().await.unwrap();
+
+    let delay = Duration::from_secs(1);
+    delivery.nack_with_delay(delay).await.unwrap();
+
+    let delivery = c
+        .receive_all(1, delay * 4)
+        .await
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap();
+    assert_eq!(
+        delivery.borrow_payload().unwrap(),
+        b"payload|with#separators"
+    );
+    assert_eq!(delivery.attributes(), &attributes);
+    delivery.ack().await.unwrap();
+}
+
 #[tokio::test]
 async fn test_deadletter_config() {
     let payload = ExType { a: 1 };
@@ -692,3 +856,269 @@ async fn test_backward_compatible() {
         .unwrap();
     assert!(delivery.is_empty());
 }
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_health_check<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, _drop) = get_builder.await;
+    let (p, c) = builder.build_pair().await.unwrap();
+    p.health_check().await.unwrap();
+    c.health_check().await.unwrap();
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_admin<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, drop) = get_builder.await;
+    let stream_name = &drop.0;
+    let admin = builder.build_admin().await.unwrap();
+
+    assert!(admin.exists().await.unwrap());
+    // Creating an existing queue is a no-op.
+    admin.create_queue().await.unwrap();
+
+    // Add two entries and deliver one of them, so it's pending
+    let client = Client::open(ROOT_URL).unwrap();
+    let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+    for _ in 0..2 {
+        let _: String = conn
+            .xadd(stream_name, "*", &[("payload", "data")])
+            .await
+            .unwrap();
+    }
+    let _: StreamReadReply = conn
+        .xread_options(
+            &[stream_name],
+            &[">"],
+            &StreamReadOptions::default()
+                .group("test_cg", "test_cn")
+                .count(1),
+        )
+        .await
+        .unwrap();
+
+    // Purging removes both entries, but keeps the stream and consumer group
+    admin.purge().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+    let len: usize = conn.xlen(stream_name).await.unwrap();
+    assert_eq!(len, 0);
+    let pending: StreamPendingReply = conn.xpending(stream_name, "test_cg").await.unwrap();
+    assert_eq!(pending.count(), 0);
+
+    admin.delete_queue().await.unwrap();
+    assert!(!admin.exists().await.unwrap());
+    admin.create_queue().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { (make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await, ROOT_URL) }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { (make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await, SENTINEL_ROOT_URL) }))]
+#[tokio::test]
+async fn test_purge_with_active_consumer<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: ((RedisBackendBuilder<R>, RedisStreamDrop), &str),
+) {
+    let ((builder, drop), dsn) = get_builder.await;
+    let admin = RedisBackendBuilder::<R>::new(test_config(dsn.to_owned(), &drop.0))
+        .build_admin()
+        .await
+        .unwrap();
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    p.send_raw(b"purged").await.unwrap();
+    let purged = c.receive().await.unwrap();
+    admin.purge().await.unwrap();
+
+    // A message sent after the purge is kept, and acking it works
+    p.send_raw(b"kept").await.unwrap();
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.borrow_payload().unwrap(), b"kept");
+    assert_eq!(c.stats().await.unwrap().in_flight, Some(1));
+    d.ack().await.unwrap();
+
+    // The purge already acked the message received before it
+    purged.ack().await.unwrap();
+    let stats = c.stats().await.unwrap();
+    assert_eq!(stats.visible, Some(0));
+    assert_eq!(stats.in_flight, Some(0));
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_stats<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, _drop) = get_builder.await;
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    p.send_raw(b"1").await.unwrap();
+    p.send_raw(b"2").await.unwrap();
+    p.send_raw_scheduled(b"3", Duration::from_secs(60))
+        .await
+        .unwrap();
+    let stats = c.stats().await.unwrap();
+    assert_eq!(stats.visible, Some(2));
+    assert_eq!(stats.in_flight, Some(0));
+    assert_eq!(stats.delayed, Some(1));
+    assert_eq!(stats.dead_lettered, None);
+
+    let _d = c.receive().await.unwrap();
+    let stats = c.stats().await.unwrap();
+    assert_eq!(stats.visible, Some(1));
+    assert_eq!(stats.in_flight, Some(1));
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_peek<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, _drop) = get_builder.await;
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    p.send_raw(b"1").await.unwrap();
+    p.send_raw(b"2").await.unwrap();
+
+    let peeked = c.peek(10).await.unwrap();
+    assert_eq!(peeked.len(), 2);
+    assert_eq!(peeked[0].borrow_payload().unwrap(), b"1");
+    assert_eq!(peeked[0].metadata().receive_count, Some(0));
+    assert_eq!(c.peek(usize::MAX).await.unwrap().len(), 2);
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.borrow_payload().unwrap(), b"1");
+    assert_eq!(d.metadata().receive_count, Some(1));
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_background_shutdown<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, _drop) = get_builder.await;
+    let (p, c) = builder.build_pair().await.unwrap();
+
+    // Give the tasks time for an iteration
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let status = c.background_status();
+    for task in [status.delayed_mover, status.reenqueuer] {
+        let task = task.unwrap();
+        assert!(task.running);
+        assert!(task.last_success.is_some());
+        assert_eq!(task.consecutive_failures, 0);
+    }
+
+    p.shutdown().await.unwrap();
+    let status = c.background_status();
+    assert!(!status.delayed_mover.unwrap().running);
+    assert!(!status.reenqueuer.unwrap().running);
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_maintenance<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, _drop) = get_builder.await;
+    let maintenance = builder
+        .maintenance()
+        .delayed_poll_interval(Duration::from_millis(100));
+    let (p, mut c) = builder.background_tasks(false).build_pair().await.unwrap();
+    assert_eq!(c.background_status(), Default::default());
+
+    let delay = Duration::from_secs(1);
+    p.send_raw_scheduled(b"delayed", delay).await.unwrap();
+
+    // Without background tasks, the message is never moved to the main queue
+    let deliveries = c.receive_all(1, delay * 3).await.unwrap();
+    assert!(deliveries.is_empty());
+
+    let maintenance = maintenance.start().await.unwrap();
+    let deliveries = c.receive_all(1, delay * 3).await.unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].borrow_payload().unwrap(), b"delayed");
+    assert!(maintenance.status().delayed_mover.unwrap().running);
+
+    maintenance.shutdown().await.unwrap();
+    assert!(!maintenance.status().delayed_mover.unwrap().running);
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_dyn_scheduled<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, _drop) = get_builder.await;
+    let (p, mut c) = builder.make_dynamic().build_scheduled_pair().await.unwrap();
+
+    let delay = Duration::from_secs(1);
+    let now = Instant::now();
+    p.send_raw_scheduled(b"delayed", delay).await.unwrap();
+    let delivery = c
+        .receive_all(1, delay * 3)
+        .await
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap();
+    assert!(now.elapsed() >= delay);
+    assert_eq!(delivery.borrow_payload().unwrap(), b"delayed");
+}
+
+#[rstest]
+#[cfg_attr(feature = "redis", case(async { make_test_queue::<RedisConnectionManager>(ROOT_URL.to_owned()).await }))]
+#[cfg_attr(feature = "redis_sentinel", case(async { make_test_queue::<RedisSentinelConnectionManager>(SENTINEL_ROOT_URL.to_owned()).await }))]
+#[tokio::test]
+async fn test_dyn_keeps_builder_settings<R: RedisConnection>(
+    #[future]
+    #[case]
+    get_builder: (RedisBackendBuilder<R>, RedisStreamDrop),
+) {
+    let (builder, _drop) = get_builder.await;
+    let (p, mut c) = builder
+        .background_tasks(false)
+        .make_dynamic()
+        .build_scheduled_pair()
+        .await
+        .unwrap();
+
+    // Without background tasks, the message is never moved to the main queue
+    let delay = Duration::from_secs(1);
+    p.send_raw_scheduled(b"delayed", delay).await.unwrap();
+    let deliveries = c.receive_all(1, delay * 3).await.unwrap();
+    assert!(deliveries.is_empty());
+}
@@ -593,3 +593,11 @@ async fn test_backward_compatible() {
         .unwrap();
     assert!(delivery.is_empty());
 }
+
+#[tokio::test]
+async fn test_health_check() {
+    let (builder, _drop) = make_test_queue().await;
+    let (p, c) = builder.build_pair().await.unwrap();
+    p.health_check().await.unwrap();
+    c.health_check().await.unwrap();
+}
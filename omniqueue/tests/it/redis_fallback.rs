@@ -6,7 +6,7 @@ use omniqueue::{
         redis::{DeadLetterQueueConfig, RedisBackendBuilder},
         RedisBackend, RedisConfig,
     },
-    Delivery,
+    Attributes, Delivery, QueueError,
 };
 use redis::{AsyncCommands, Client, Commands};
 use serde::{Deserialize, Serialize};
@@ -103,6 +103,31 @@ async fn test_serde_send_recv() {
     d.ack().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_send_with_attributes_unsupported() {
+    let (builder, _drop) = make_test_queue().await;
+    let payload = ExType { a: 2 };
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    let attributes = Attributes::from([("trace-id".to_owned(), "abc".to_owned())]);
+    assert!(matches!(
+        p.send_serde_json_with_attributes(&payload, &attributes)
+            .await
+            .unwrap_err(),
+        QueueError::Unsupported(_)
+    ));
+
+    // Sending without attributes still works
+    p.send_serde_json_with_attributes(&payload, &Attributes::new())
+        .await
+        .unwrap();
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.payload_serde_json::<ExType>().unwrap().unwrap(), payload);
+    assert!(d.attributes().is_empty());
+    d.ack().await.unwrap();
+}
+
 // Fallback implementation currently implements receive_all such that it always
 // only returns the first item, uncomment when the implementation is changed.
 /*
@@ -568,3 +593,51 @@ async fn test_backward_compatible() {
         .unwrap();
     assert!(delivery.is_empty());
 }
+
+#[tokio::test]
+async fn test_admin() {
+    let (builder, drop) = make_test_queue().await;
+    let queue_key = &drop.0;
+    let admin = builder.build_admin().await.unwrap();
+
+    // The lists only exist while they hold messages
+    assert!(matches!(
+        admin.exists().await,
+        Err(QueueError::Unsupported(_))
+    ));
+    admin.create_queue().await.unwrap();
+
+    let client = Client::open(ROOT_URL).unwrap();
+    let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+    let _: () = conn.lpush(queue_key, "payload").await.unwrap();
+
+    admin.purge().await.unwrap();
+    let len: usize = conn.llen(queue_key).await.unwrap();
+    assert_eq!(len, 0);
+
+    let _: () = conn.lpush(queue_key, "payload").await.unwrap();
+    admin.delete_queue().await.unwrap();
+    let exists: bool = conn.exists(queue_key).await.unwrap();
+    assert!(!exists);
+}
+
+#[tokio::test]
+async fn test_peek() {
+    let (builder, _drop) = make_test_queue().await;
+    let (p, mut c) = builder.build_pair().await.unwrap();
+
+    p.send_raw(b"1").await.unwrap();
+    p.send_raw(b"2").await.unwrap();
+
+    let peeked = c.peek(1).await.unwrap();
+    assert_eq!(peeked.len(), 1);
+    assert_eq!(peeked[0].borrow_payload().unwrap(), b"1");
+
+    // Asking for more messages than there are returns all of them
+    let peeked = c.peek(usize::MAX).await.unwrap();
+    let payloads: Vec<_> = peeked.iter().map(|d| d.borrow_payload().unwrap()).collect();
+    assert_eq!(payloads, [b"1", b"2"]);
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.borrow_payload().unwrap(), b"1");
+}
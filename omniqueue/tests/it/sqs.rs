@@ -3,7 +3,7 @@ use std::time::{Duration, Instant};
 use aws_sdk_sqs::Client;
 use omniqueue::{
     backends::{SqsBackend, SqsConfig},
-    QueueBuilder,
+    Attributes, ErrorKind, QueueBuilder,
 };
 use serde::{Deserialize, Serialize};
 
@@ -89,6 +89,36 @@ async fn test_serde_send_recv() {
     d.ack().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_send_recv_with_attributes() {
+    let payload = ExType { a: 2 };
+    let attributes = Attributes::from([("trace-id".to_owned(), "abc".to_owned())]);
+    let (p, c) = make_test_queue().await.build_pair().await.unwrap();
+
+    p.send_serde_json_with_attributes(&payload, &attributes)
+        .await
+        .unwrap();
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.payload_serde_json::<ExType>().unwrap().unwrap(), payload);
+    assert_eq!(d.attributes(), &attributes);
+    d.ack().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_delivery_metadata() {
+    let (p, c) = make_test_queue().await.build_pair().await.unwrap();
+
+    p.send_raw("hello").await.unwrap();
+
+    let d = c.receive().await.unwrap();
+    let metadata = d.metadata();
+    assert!(metadata.message_id.is_some());
+    assert_eq!(metadata.receive_count, Some(1));
+    assert!(metadata.enqueued_at.is_some());
+    d.ack().await.unwrap();
+}
+
 /// Consumer will return immediately if there are fewer than max messages to
 /// start with.
 #[tokio::test]
@@ -221,3 +251,178 @@ async fn test_scheduled() {
     assert!(now.elapsed() < delay * 2);
     assert_eq!(Some(payload1), delivery.payload_serde_json().unwrap());
 }
+
+#[tokio::test]
+async fn test_nack_with_delay() {
+    let payload = ExType { a: 1 };
+    let (p, c) = make_test_queue().await.build_pair().await.unwrap();
+
+    p.send_serde_json(&payload).await.unwrap();
+    let delivery = c.receive().await.unwrap();
+
+    let delay = Duration::from_secs(1);
+    let now = Instant::now();
+    delivery.nack_with_delay(delay).await.unwrap();
+
+    let delivery = c
+        .receive_all(1, delay * 5)
+        .await
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap();
+    assert!(now.elapsed() >= delay);
+    assert_eq!(
+        Some(&payload),
+        delivery.payload_serde_json().unwrap().as_ref()
+    );
+    assert_eq!(delivery.metadata().receive_count, Some(2));
+    delivery.ack().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_queue_not_found() {
+    for (var, val) in &DEFAULT_CFG {
+        if std::env::var(var).is_err() {
+            std::env::set_var(var, val);
+        }
+    }
+
+    let config = SqsConfig {
+        queue_dsn: format!("{ROOT_URL}/queue/does-not-exist"),
+        override_endpoint: true,
+    };
+    let p = SqsBackend::builder(config).build_producer().await.unwrap();
+
+    let err = p.send_raw("test").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::QueueNotFound);
+    assert!(!err.is_transient());
+
+    let err = p.health_check().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::QueueNotFound);
+}
+
+#[tokio::test]
+async fn test_health_check() {
+    let (p, c) = make_test_queue().await.build_pair().await.unwrap();
+    p.health_check().await.unwrap();
+    c.health_check().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_admin() {
+    for (var, val) in &DEFAULT_CFG {
+        if std::env::var(var).is_err() {
+            std::env::set_var(var, val);
+        }
+    }
+
+    let queue_name: String = std::iter::repeat_with(fastrand::alphanumeric)
+        .take(8)
+        .collect();
+    let config = SqsConfig {
+        queue_dsn: format!("{ROOT_URL}/queue/{queue_name}"),
+        override_endpoint: true,
+    };
+    let admin = SqsBackend::builder(config).build_admin().await.unwrap();
+
+    assert!(!admin.exists().await.unwrap());
+    admin.create_queue().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+
+    admin.purge().await.unwrap();
+    assert!(admin.exists().await.unwrap());
+
+    admin.delete_queue().await.unwrap();
+    assert!(!admin.exists().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let (p, c) = make_test_queue().await.build_pair().await.unwrap();
+    p.send_raw("test").await.unwrap();
+
+    let stats = c.stats().await.unwrap();
+    assert_eq!(stats.visible, Some(1));
+    assert_eq!(stats.in_flight, Some(0));
+    assert_eq!(stats.delayed, Some(0));
+
+    let _d = c.receive().await.unwrap();
+    let stats = c.stats().await.unwrap();
+    assert_eq!(stats.visible, Some(0));
+    assert_eq!(stats.in_flight, Some(1));
+}
+
+#[cfg(feature = "zstd")]
+#[tokio::test]
+async fn test_compression() {
+    use omniqueue::{
+        compression::{Algorithm, CompressingProducer, CompressionConfig, DecompressingConsumer},
+        QueueConsumer as _, QueueProducer as _,
+    };
+
+    let (p, c) = make_test_queue()
+        .await
+        .make_dynamic()
+        .build_pair()
+        .await
+        .unwrap();
+    let p = CompressingProducer::new(p, CompressionConfig::new(Algorithm::Zstd));
+    let mut c = DecompressingConsumer::new(c);
+
+    let payload = r#"{"test":"data"}"#.repeat(1000);
+    p.send_raw(&payload.as_bytes().to_vec()).await.unwrap();
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.borrow_payload().unwrap(), payload.as_bytes());
+}
+
+#[cfg(feature = "encryption")]
+#[tokio::test]
+async fn test_encryption() {
+    use omniqueue::{
+        encryption::{Cipher, DecryptingConsumer, EncryptingProducer, Key, Keyring},
+        QueueConsumer as _, QueueProducer as _,
+    };
+
+    let (p, c) = make_test_queue()
+        .await
+        .make_dynamic()
+        .build_pair()
+        .await
+        .unwrap();
+    let keyring = Keyring::new(Key::new("k1", Cipher::Aes256Gcm, [7; 32]).unwrap());
+    let p = EncryptingProducer::new(p, keyring.clone());
+    let mut c = DecryptingConsumer::new(c, keyring);
+
+    let payload = r#"{"test":"data"}"#;
+    p.send_raw(&payload.as_bytes().to_vec()).await.unwrap();
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.borrow_payload().unwrap(), payload.as_bytes());
+}
+
+#[cfg(feature = "claim_check")]
+#[tokio::test]
+async fn test_claim_check() {
+    use omniqueue::{
+        claim_check::{ClaimCheckConsumer, ClaimCheckProducer, FsBlobStore},
+        QueueConsumer as _, QueueProducer as _,
+    };
+
+    let dir = std::env::temp_dir().join(format!("omniqueue-sqs-{:016x}", fastrand::u64(..)));
+    let store = FsBlobStore::new(&dir);
+    let (p, c) = make_test_queue().await.build_pair().await.unwrap();
+    let p = ClaimCheckProducer::new(p, store.clone(), 16);
+    let mut c = ClaimCheckConsumer::new(c, store).delete_on_ack(true);
+
+    let payload = r#"{"test":"data"}"#.repeat(10);
+    p.send_raw(&payload).await.unwrap();
+
+    let d = c.receive().await.unwrap();
+    assert_eq!(d.borrow_payload().unwrap(), payload.as_bytes());
+    d.ack().await.unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}